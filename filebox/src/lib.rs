@@ -1,22 +1,35 @@
 use std::fmt;
 
 // TODO: FileBox Implementation
-pub struct FileBox {}
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileBox {
+    name: String,
+    buffer: Vec<u8>,
+}
 
 impl FileBox {
-    pub fn to_string(&self) -> String {
-        String::new()
+    /// Create a file box holding the contents of a file in memory.
+    pub fn from_buffer(name: String, buffer: Vec<u8>) -> Self {
+        Self { name, buffer }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
     }
 }
 
 impl fmt::Display for FileBox {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(fmt, "{}", self.to_string())
+        write!(fmt, "")
     }
 }
 
 impl From<String> for FileBox {
     fn from(_: String) -> Self {
-        Self {}
+        Self::default()
     }
 }
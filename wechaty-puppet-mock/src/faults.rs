@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use wechaty_puppet::{PuppetError, PuppetEvent};
+
+/// Options for injecting faults into a mock puppet.
+///
+/// Rates are probabilities between 0 and 1. Every random decision is drawn from an RNG seeded with `seed`,
+/// so a run with the same calls and events fails the same way.
+#[derive(Debug, Clone, Default)]
+pub struct FaultOptions {
+    pub seed: u64,
+    /// Rates of failing calls, keyed by method name such as `message_send_text`. The rate keyed by `*`
+    /// applies to the methods not listed.
+    pub error_rates: HashMap<String, f64>,
+    /// The error failing calls return.
+    pub error: FaultKind,
+    /// Range of the latency added to every call, in milliseconds.
+    pub latency: Option<(u64, u64)>,
+    /// Rate of events never delivered.
+    pub drop_rate: f64,
+    /// Rate of events delivered twice.
+    pub duplicate_rate: f64,
+    /// Rate of events held back and delivered after the next one.
+    pub reorder_rate: f64,
+    /// Log the bot out when this many calls were made to the puppet.
+    pub logout_after_calls: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum FaultKind {
+    #[default]
+    Network,
+    Unsupported,
+}
+
+/// The faults to apply to one call.
+pub(crate) struct CallFault {
+    pub(crate) latency: Option<Duration>,
+    pub(crate) error: Option<PuppetError>,
+    pub(crate) logout: bool,
+}
+
+pub(crate) struct FaultInjector {
+    options: FaultOptions,
+    rng: StdRng,
+    calls: usize,
+    held: Option<PuppetEvent>,
+}
+
+impl FaultInjector {
+    pub(crate) fn new(options: FaultOptions) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(options.seed),
+            options,
+            calls: 0,
+            held: None,
+        }
+    }
+
+    fn roll(&mut self, rate: f64) -> bool {
+        rate > 0.0 && self.rng.gen_bool(rate.min(1.0))
+    }
+
+    pub(crate) fn call(&mut self, method: &str) -> CallFault {
+        self.calls += 1;
+        let latency = self
+            .options
+            .latency
+            .map(|(min, max)| Duration::from_millis(self.rng.gen_range(min..=max.max(min))));
+        let rate = match self.options.error_rates.get(method) {
+            Some(rate) => *rate,
+            None => self.options.error_rates.get("*").cloned().unwrap_or_default(),
+        };
+        let error = if self.roll(rate) {
+            Some(match self.options.error {
+                FaultKind::Network => PuppetError::Network(format!("Injected fault in {}", method)),
+                FaultKind::Unsupported => PuppetError::Unsupported(method.to_owned()),
+            })
+        } else {
+            None
+        };
+        CallFault {
+            latency,
+            error,
+            logout: self.options.logout_after_calls == Some(self.calls),
+        }
+    }
+
+    /// Decide which events to deliver in place of `event`, in order.
+    pub(crate) fn event(&mut self, event: PuppetEvent) -> Vec<PuppetEvent> {
+        if self.roll(self.options.drop_rate) {
+            return vec![];
+        }
+        if self.held.is_none() && self.roll(self.options.reorder_rate) {
+            self.held = Some(event);
+            return vec![];
+        }
+        let mut events = vec![event.clone()];
+        if self.roll(self.options.duplicate_rate) {
+            events.push(event);
+        }
+        events.extend(self.held.take());
+        events
+    }
+
+    /// Take the event held back for reordering, if any.
+    pub(crate) fn flush(&mut self) -> Option<PuppetEvent> {
+        self.held.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use wechaty_puppet::{EventDongPayload, FileBox, ImageType, Puppet, PuppetImpl};
+
+    use super::*;
+    use crate::{Mocker, PuppetMock};
+
+    fn dong(data: &str) -> PuppetEvent {
+        PuppetEvent::Dong(EventDongPayload { data: data.to_owned() })
+    }
+
+    fn data(events: Vec<PuppetEvent>) -> Vec<String> {
+        events
+            .into_iter()
+            .map(|event| match event {
+                PuppetEvent::Dong(payload) => payload.data,
+                event => panic!("Unexpected event {:?}", event),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn faults_are_reproducible() {
+        let options = FaultOptions {
+            seed: 42,
+            error_rates: vec![("*".to_owned(), 0.5)].into_iter().collect(),
+            drop_rate: 0.2,
+            duplicate_rate: 0.2,
+            reorder_rate: 0.2,
+            ..FaultOptions::default()
+        };
+        let run = |options: FaultOptions| {
+            let mut faults = FaultInjector::new(options);
+            let errors: Vec<bool> = (0..32).map(|_| faults.call("ding").error.is_some()).collect();
+            let events: Vec<String> = (0..32).flat_map(|i| data(faults.event(dong(&i.to_string())))).collect();
+            (errors, events)
+        };
+        let (errors, events) = run(options.clone());
+        assert_eq!(run(options.clone()), (errors.clone(), events.clone()));
+        assert!(errors.contains(&true) && errors.contains(&false));
+        assert_ne!(events, (0..32).map(|i| i.to_string()).collect::<Vec<_>>());
+
+        let mut faults = FaultInjector::new(FaultOptions {
+            reorder_rate: 1.0,
+            duplicate_rate: 1.0,
+            ..FaultOptions::default()
+        });
+        assert!(faults.event(dong("first")).is_empty());
+        assert_eq!(data(faults.event(dong("second"))), vec!["second", "second", "first"]);
+    }
+
+    #[actix_rt::test]
+    async fn calls_fail_and_sessions_end_as_configured() {
+        let puppet = Puppet::new(PuppetMock::new());
+        let mocker = Mocker::new(&puppet);
+        let mut events = Box::pin(puppet.events(16));
+        mocker.login("bot");
+        mocker.inject_faults(FaultOptions {
+            error_rates: vec![("message_send_text".to_owned(), 1.0)].into_iter().collect(),
+            error: FaultKind::Unsupported,
+            latency: Some((10, 20)),
+            logout_after_calls: Some(2),
+            ..FaultOptions::default()
+        });
+
+        assert!(matches!(
+            puppet
+                .message_send_text("bot".to_owned(), "Hello".to_owned(), vec![])
+                .await,
+            Err(PuppetError::Unsupported(_))
+        ));
+        assert!(mocker.sent_messages().is_empty());
+        assert!(matches!(
+            puppet.contact_self_qr_code().await,
+            Err(PuppetError::InvalidState(_))
+        ));
+        mocker.clear_faults();
+        assert_eq!(puppet.tag_list().await.unwrap(), Vec::<String>::new());
+
+        let mut logged_out = false;
+        while let Ok(Some(Ok(event))) = actix_rt::time::timeout(Duration::from_millis(100), events.next()).await {
+            logged_out |= matches!(event, PuppetEvent::Logout(_));
+        }
+        assert!(logged_out);
+    }
+
+    #[actix_rt::test]
+    async fn every_call_draws_its_faults_once() {
+        let puppet = Puppet::new(PuppetMock::new());
+        let mocker = Mocker::new(&puppet);
+        mocker.login("bot");
+        let message_id = puppet
+            .message_send_file("bot".to_owned(), FileBox::from("file".to_owned()))
+            .await
+            .unwrap()
+            .unwrap();
+        mocker.inject_faults(FaultOptions {
+            error_rates: vec![("message_file".to_owned(), 1.0)].into_iter().collect(),
+            logout_after_calls: Some(2),
+            ..FaultOptions::default()
+        });
+
+        // Were the image loaded through `message_file`, it would fail, and it would count as a second call.
+        assert!(puppet.message_image(message_id, ImageType::HD).await.is_ok());
+        puppet.flush().await;
+        assert_eq!(puppet.self_id(), Some("bot".to_owned()));
+    }
+}
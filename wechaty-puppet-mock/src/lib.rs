@@ -1,3 +1,14 @@
+mod faults;
+mod mocker;
 mod puppet_mock;
+mod scenario;
+mod world;
 
+pub use faults::{FaultKind, FaultOptions};
+pub use mocker::Mocker;
 pub use puppet_mock::PuppetMock;
+pub use scenario::{
+    DiffLine, Expectation, Scenario, ScenarioContact, ScenarioError, ScenarioEvent, ScenarioReport, ScenarioRoom,
+    ScenarioStep, TextMatcher,
+};
+pub use world::{MessageContent, SentMessage};
@@ -0,0 +1,652 @@
+use std::sync::MutexGuard;
+
+use log::debug;
+use wechaty_puppet::*;
+
+use crate::faults::{FaultInjector, FaultOptions};
+use crate::world::{now, MessageContent, MockWorld, SentMessage, WorldPtr};
+use crate::PuppetMock;
+
+/// Controls the world of a mock puppet from tests: populates it, injects incoming events and inspects what
+/// the bot did.
+///
+/// Events are delivered through `Puppet::self_addr()`, so they reach subscribers and `Puppet::events()`
+/// the same way events from a real puppet do.
+///
+/// ```ignore
+/// let puppet = Puppet::new(PuppetMock::new());
+/// let mocker = Mocker::new(&puppet);
+/// mocker.create_contact("alice", "Alice");
+/// mocker.scan_and_login("bot");
+/// mocker.receive_text("alice", "ding");
+/// ```
+#[derive(Clone)]
+pub struct Mocker {
+    world: WorldPtr,
+}
+
+impl Mocker {
+    /// Attach a mocker to a puppet, the events of the mock puppet are emitted from then on.
+    pub fn new(puppet: &Puppet<PuppetMock>) -> Self {
+        let world = puppet.puppet_impl().world.clone();
+        world.lock().unwrap().addr = Some(puppet.self_addr());
+        Self { world }
+    }
+
+    fn world(&self) -> MutexGuard<'_, MockWorld> {
+        self.world.lock().unwrap()
+    }
+
+    /*
+       World
+    */
+
+    pub fn add_contact(&self, payload: ContactPayload) {
+        debug!("add_contact(payload = {:?})", payload);
+        self.world().contacts.insert(payload.id.clone(), payload);
+    }
+
+    /// Add a friend with the given id and name, and return its payload.
+    pub fn create_contact(&self, id: &str, name: &str) -> ContactPayload {
+        let payload = ContactPayload {
+            id: id.to_owned(),
+            gender: ContactGender::Unknown,
+            contact_type: ContactType::Individual,
+            name: name.to_owned(),
+            avatar: String::new(),
+            address: String::new(),
+            alias: String::new(),
+            city: String::new(),
+            friend: true,
+            province: String::new(),
+            signature: String::new(),
+            star: false,
+            weixin: String::new(),
+            corporation: String::new(),
+            title: String::new(),
+            description: String::new(),
+            coworker: false,
+            phone: vec![],
+        };
+        self.add_contact(payload.clone());
+        payload
+    }
+
+    pub fn contact(&self, contact_id: &str) -> Option<ContactPayload> {
+        self.world().contacts.get(contact_id).cloned()
+    }
+
+    pub fn add_room(&self, payload: RoomPayload) {
+        debug!("add_room(payload = {:?})", payload);
+        self.world().rooms.insert(payload.id.clone(), payload);
+    }
+
+    /// Add a room with the given id, topic and members, and return its payload.
+    pub fn create_room(&self, id: &str, topic: &str, member_id_list: &[&str]) -> RoomPayload {
+        let payload = RoomPayload {
+            id: id.to_owned(),
+            topic: topic.to_owned(),
+            avatar: String::new(),
+            member_id_list: member_id_list.iter().map(|id| id.to_string()).collect(),
+            owner_id: member_id_list.first().map(|id| id.to_string()).unwrap_or_default(),
+            admin_id_list: vec![],
+        };
+        self.add_room(payload.clone());
+        payload
+    }
+
+    pub fn room(&self, room_id: &str) -> Option<RoomPayload> {
+        self.world().rooms.get(room_id).cloned()
+    }
+
+    /// Set the alias of a member in a room.
+    pub fn set_room_alias(&self, room_id: &str, contact_id: &str, room_alias: &str) {
+        debug!(
+            "set_room_alias(room_id = {}, contact_id = {}, room_alias = {})",
+            room_id, contact_id, room_alias
+        );
+        let mut world = self.world();
+        let (avatar, name) = match world.contacts.get(contact_id) {
+            Some(contact) => (contact.avatar.clone(), contact.name.clone()),
+            None => (String::new(), String::new()),
+        };
+        world
+            .room_members
+            .entry((room_id.to_owned(), contact_id.to_owned()))
+            .or_insert(RoomMemberPayload {
+                id: contact_id.to_owned(),
+                room_alias: String::new(),
+                inviter_id: String::new(),
+                avatar,
+                name,
+            })
+            .room_alias = room_alias.to_owned();
+    }
+
+    /*
+       Faults
+    */
+
+    /// Inject faults into the calls to the puppet and the events it emits, replacing any injected before.
+    pub fn inject_faults(&self, options: FaultOptions) {
+        debug!("inject_faults(options = {:?})", options);
+        self.clear_faults();
+        self.world().faults = Some(FaultInjector::new(options));
+    }
+
+    /// Stop injecting faults, delivering the event held back for reordering if any.
+    pub fn clear_faults(&self) {
+        debug!("clear_faults()");
+        let mut world = self.world();
+        if let Some(event) = world.faults.take().and_then(|mut faults| faults.flush()) {
+            world.deliver(event);
+        }
+    }
+
+    /*
+       Events
+    */
+
+    /// Emit any event from the puppet.
+    pub fn emit(&self, event: PuppetEvent) {
+        debug!("emit(event = {:?})", event);
+        self.world().emit(event);
+    }
+
+    pub fn scan(&self, status: ScanStatus, qrcode: Option<String>) {
+        self.emit(PuppetEvent::Scan(EventScanPayload {
+            status,
+            qrcode,
+            data: None,
+        }));
+    }
+
+    /// Log in as `contact_id`, who is added to the contacts if unknown.
+    pub fn login(&self, contact_id: &str) {
+        debug!("login(contact_id = {})", contact_id);
+        if self.contact(contact_id).is_none() {
+            self.create_contact(contact_id, contact_id);
+        }
+        let mut world = self.world();
+        world.self_id = Some(contact_id.to_owned());
+        world.emit(PuppetEvent::Login(EventLoginPayload {
+            contact_id: contact_id.to_owned(),
+        }));
+    }
+
+    /// Go through the QR code scan like a user would, then log in.
+    pub fn scan_and_login(&self, contact_id: &str) {
+        let qrcode = Some(format!("mock://qrcode/login/{}", contact_id));
+        self.scan(ScanStatus::Waiting, qrcode.clone());
+        self.scan(ScanStatus::Scanned, qrcode.clone());
+        self.scan(ScanStatus::Confirmed, qrcode);
+        self.login(contact_id);
+    }
+
+    /// Log out as if the session ended on the phone.
+    pub fn logout(&self, data: &str) {
+        debug!("logout(data = {})", data);
+        let mut world = self.world();
+        if let Some(contact_id) = world.self_id.take() {
+            world.emit(PuppetEvent::Logout(EventLogoutPayload {
+                contact_id,
+                data: data.to_owned(),
+            }));
+        }
+    }
+
+    /// Store a message without emitting it, return its id.
+    ///
+    /// An empty id in the payload is replaced with a generated one.
+    pub fn add_message(&self, payload: MessagePayload, content: MessageContent) -> String {
+        debug!("add_message(payload = {:?}, content = {:?})", payload, content);
+        self.world().add_message(payload, content)
+    }
+
+    pub fn message(&self, message_id: &str) -> Option<MessagePayload> {
+        self.world().messages.get(message_id).cloned()
+    }
+
+    /// Set the account the puppet is logged in as, without emitting any event.
+    pub fn set_self_id(&self, contact_id: Option<&str>) {
+        debug!("set_self_id(contact_id = {:?})", contact_id);
+        self.world().self_id = contact_id.map(str::to_owned);
+    }
+
+    /// Store an incoming message and emit it, return its id.
+    ///
+    /// An empty id in the payload is replaced with a generated one.
+    pub fn receive_message(&self, payload: MessagePayload, content: MessageContent) -> String {
+        debug!("receive_message(payload = {:?}, content = {:?})", payload, content);
+        let mut world = self.world();
+        let message_id = world.add_message(payload, content);
+        world.emit(PuppetEvent::Message(EventMessagePayload {
+            message_id: message_id.clone(),
+        }));
+        message_id
+    }
+
+    /// Receive a text from a contact to the bot, return the message id.
+    pub fn receive_text(&self, from_id: &str, text: &str) -> String {
+        let to_id = self.world().self_id.clone().unwrap_or_default();
+        self.receive_message(
+            text_payload(from_id, "", &to_id, text, &[]),
+            MessageContent::Text {
+                text: text.to_owned(),
+                mention_id_list: vec![],
+            },
+        )
+    }
+
+    /// Receive a text from a member of a room, return the message id.
+    pub fn receive_room_text(&self, room_id: &str, from_id: &str, text: &str, mention_id_list: &[&str]) -> String {
+        self.receive_message(
+            text_payload(from_id, room_id, "", text, mention_id_list),
+            MessageContent::Text {
+                text: text.to_owned(),
+                mention_id_list: mention_id_list.iter().map(|id| id.to_string()).collect(),
+            },
+        )
+    }
+
+    /// Make contacts join a room, invited by `inviter_id`.
+    pub fn join_room(&self, room_id: &str, invitee_id_list: &[&str], inviter_id: &str) {
+        debug!(
+            "join_room(room_id = {}, invitee_id_list = {:?}, inviter_id = {})",
+            room_id, invitee_id_list, inviter_id
+        );
+        let mut world = self.world();
+        if let Some(room) = world.rooms.get_mut(room_id) {
+            for invitee_id in invitee_id_list {
+                if !room.member_id_list.iter().any(|id| id == invitee_id) {
+                    room.member_id_list.push(invitee_id.to_string());
+                }
+            }
+        }
+        world.emit(PuppetEvent::RoomJoin(EventRoomJoinPayload {
+            room_id: room_id.to_owned(),
+            inviter_id: inviter_id.to_owned(),
+            invitee_id_list: invitee_id_list.iter().map(|id| id.to_string()).collect(),
+            timestamp: now(),
+        }));
+    }
+
+    /// Make contacts leave a room, removed by `remover_id`.
+    pub fn leave_room(&self, room_id: &str, removee_id_list: &[&str], remover_id: &str) {
+        debug!(
+            "leave_room(room_id = {}, removee_id_list = {:?}, remover_id = {})",
+            room_id, removee_id_list, remover_id
+        );
+        let mut world = self.world();
+        if let Some(room) = world.rooms.get_mut(room_id) {
+            room.member_id_list.retain(|id| !removee_id_list.contains(&id.as_str()));
+        }
+        world.emit(PuppetEvent::RoomLeave(EventRoomLeavePayload {
+            room_id: room_id.to_owned(),
+            remover_id: remover_id.to_owned(),
+            removee_id_list: removee_id_list.iter().map(|id| id.to_string()).collect(),
+            timestamp: now(),
+        }));
+    }
+
+    pub fn change_room_topic(&self, room_id: &str, new_topic: &str, changer_id: &str) {
+        debug!(
+            "change_room_topic(room_id = {}, new_topic = {}, changer_id = {})",
+            room_id, new_topic, changer_id
+        );
+        let mut world = self.world();
+        let old_topic = match world.rooms.get_mut(room_id) {
+            Some(room) => std::mem::replace(&mut room.topic, new_topic.to_owned()),
+            None => String::new(),
+        };
+        world.emit(PuppetEvent::RoomTopic(EventRoomTopicPayload {
+            room_id: room_id.to_owned(),
+            changer_id: changer_id.to_owned(),
+            old_topic,
+            new_topic: new_topic.to_owned(),
+            timestamp: now(),
+        }));
+    }
+
+    /// Receive a friendship request, return the friendship id.
+    pub fn request_friendship(&self, contact_id: &str, hello: &str) -> String {
+        debug!("request_friendship(contact_id = {}, hello = {})", contact_id, hello);
+        let mut world = self.world();
+        let friendship_id = world.next_id("friendship");
+        world.friendships.insert(
+            friendship_id.clone(),
+            FriendshipPayload {
+                id: friendship_id.clone(),
+                contact_id: contact_id.to_owned(),
+                hello: hello.to_owned(),
+                timestamp: now(),
+                scene: FriendshipSceneType::Weixin,
+                stranger: String::new(),
+                ticket: String::new(),
+                friendship_type: FriendshipType::Receive,
+            },
+        );
+        world.emit(PuppetEvent::Friendship(EventFriendshipPayload {
+            friendship_id: friendship_id.clone(),
+        }));
+        friendship_id
+    }
+
+    /// Receive an invitation to a room, return the invitation id.
+    pub fn invite_to_room(&self, inviter_id: &str, topic: &str, member_id_list: &[&str]) -> String {
+        debug!(
+            "invite_to_room(inviter_id = {}, topic = {}, member_id_list = {:?})",
+            inviter_id, topic, member_id_list
+        );
+        let mut world = self.world();
+        let room_invitation_id = world.next_id("room-invitation");
+        let receiver_id = world.self_id.clone().unwrap_or_default();
+        world.room_invitations.insert(
+            room_invitation_id.clone(),
+            RoomInvitationPayload {
+                id: room_invitation_id.clone(),
+                inviter_id: inviter_id.to_owned(),
+                topic: topic.to_owned(),
+                avatar: String::new(),
+                invitation: String::new(),
+                member_count: member_id_list.len() as u32,
+                member_id_list: member_id_list.iter().map(|id| id.to_string()).collect(),
+                timestamp: now(),
+                receiver_id,
+            },
+        );
+        world.emit(PuppetEvent::RoomInvite(EventRoomInvitePayload {
+            room_invitation_id: room_invitation_id.clone(),
+        }));
+        room_invitation_id
+    }
+
+    /*
+       Records
+    */
+
+    /// Get the messages sent by the bot, oldest first.
+    pub fn sent_messages(&self) -> Vec<SentMessage> {
+        self.world().sent.clone()
+    }
+
+    /// Get and forget the messages sent by the bot, oldest first.
+    pub fn take_sent_messages(&self) -> Vec<SentMessage> {
+        std::mem::take(&mut self.world().sent)
+    }
+
+    /// Get the friendship requests sent by the bot, with their greetings.
+    pub fn sent_friendship_requests(&self) -> Vec<(String, Option<String>)> {
+        self.world().friendship_requests.clone()
+    }
+
+    pub fn accepted_room_invitations(&self) -> Vec<String> {
+        self.world().accepted_room_invitations.clone()
+    }
+
+    pub fn corporation_remark(&self, contact_id: &str) -> Option<String> {
+        self.world().corporation_remarks.get(contact_id).cloned()
+    }
+}
+
+fn text_payload(from_id: &str, room_id: &str, to_id: &str, text: &str, mention_id_list: &[&str]) -> MessagePayload {
+    MessagePayload {
+        id: String::new(),
+        filename: String::new(),
+        text: text.to_owned(),
+        timestamp: now(),
+        message_type: MessageType::Text,
+        from_id: from_id.to_owned(),
+        mention_id_list: mention_id_list.iter().map(|id| id.to_string()).collect(),
+        room_id: room_id.to_owned(),
+        to_id: to_id.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::{FutureExt, Stream, StreamExt};
+
+    use super::*;
+
+    /// Wait for the next event that is not a state change.
+    async fn next_event<S>(events: &mut S) -> PuppetEvent
+    where
+        S: Stream<Item = Result<PuppetEvent, PuppetError>> + Unpin,
+    {
+        loop {
+            match actix_rt::time::timeout(Duration::from_secs(5), events.next()).await {
+                Ok(Some(Ok(PuppetEvent::StateChange(_)))) => {}
+                Ok(Some(Ok(event))) => return event,
+                result => panic!("Expected an event, got {:?}", result),
+            }
+        }
+    }
+
+    #[actix_rt::test]
+    async fn bots_can_talk_to_mocked_contacts() {
+        let puppet = Puppet::new(PuppetMock::new());
+        let mocker = Mocker::new(&puppet);
+        let mut events = Box::pin(puppet.events(16));
+        mocker.create_contact("alice", "Alice");
+        mocker.scan_and_login("bot");
+        for _ in 0..3 {
+            assert!(matches!(next_event(&mut events).await, PuppetEvent::Scan(_)));
+        }
+        assert!(matches!(next_event(&mut events).await, PuppetEvent::Login(_)));
+        assert_eq!(puppet.self_id(), Some("bot".to_owned()));
+
+        let message_id = mocker.receive_text("alice", "ding");
+        match next_event(&mut events).await {
+            PuppetEvent::Message(payload) => assert_eq!(payload.message_id, message_id),
+            event => panic!("Expected a message, got {:?}", event),
+        }
+        let payload = puppet.message_payload(message_id).await.unwrap();
+        assert_eq!((payload.from_id.as_str(), payload.text.as_str()), ("alice", "ding"));
+
+        let reply_id = puppet
+            .message_send_text("alice".to_owned(), "dong".to_owned(), vec![])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            mocker.sent_messages(),
+            vec![SentMessage {
+                id: reply_id,
+                conversation_id: "alice".to_owned(),
+                content: MessageContent::Text {
+                    text: "dong".to_owned(),
+                    mention_id_list: vec![],
+                },
+            }]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn room_changes_update_the_world() {
+        let puppet = Puppet::new(PuppetMock::new());
+        let mocker = Mocker::new(&puppet);
+        let mut events = Box::pin(puppet.events(16));
+        mocker.login("bot");
+        mocker.create_room("room", "Mock room", &["bot", "alice"]);
+        mocker.join_room("room", &["bob"], "alice");
+        mocker.change_room_topic("room", "New topic", "bob");
+        puppet
+            .tag_contact_add("friends".to_owned(), "bot".to_owned())
+            .await
+            .unwrap();
+
+        assert!(matches!(next_event(&mut events).await, PuppetEvent::Login(_)));
+        assert!(matches!(next_event(&mut events).await, PuppetEvent::RoomJoin(_)));
+        match next_event(&mut events).await {
+            PuppetEvent::RoomTopic(payload) => assert_eq!(payload.old_topic, "Mock room"),
+            event => panic!("Expected a topic change, got {:?}", event),
+        }
+        assert_eq!(
+            puppet.room_member_list("room".to_owned()).await.unwrap(),
+            vec!["bot", "alice", "bob"]
+        );
+        assert_eq!(puppet.room_topic("room".to_owned()).await.unwrap(), "New topic");
+        assert_eq!(
+            puppet.tag_contact_list("bot".to_owned()).await.unwrap(),
+            vec!["friends"]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn event_streams_fan_out_with_their_own_buffers() {
+        let puppet = Puppet::new(PuppetMock::new());
+        let mocker = Mocker::new(&puppet);
+        mocker.create_contact("alice", "Alice");
+        mocker.login("bot");
+        puppet.flush().await;
+
+        let mut fast = Box::pin(puppet.events(16));
+        let mut slow = Box::pin(puppet.events(2));
+        let message_id_list: Vec<String> = (0..5).map(|i| mocker.receive_text("alice", &i.to_string())).collect();
+        puppet.flush().await;
+
+        for message_id in &message_id_list {
+            match next_event(&mut fast).await {
+                PuppetEvent::Message(payload) => assert_eq!(&payload.message_id, message_id),
+                event => panic!("Expected a message, got {:?}", event),
+            }
+        }
+        // The slow consumer only kept the last two events, and is told how many it missed.
+        match slow.next().await {
+            Some(Err(PuppetError::Lagged(skipped))) => assert_eq!(skipped, 3),
+            item => panic!("Expected the stream to lag, got {:?}", item),
+        }
+        for message_id in &message_id_list[3..] {
+            match next_event(&mut slow).await {
+                PuppetEvent::Message(payload) => assert_eq!(&payload.message_id, message_id),
+                event => panic!("Expected a message, got {:?}", event),
+            }
+        }
+    }
+
+    #[actix_rt::test]
+    async fn lifecycle_follows_start_login_logout_and_stop() {
+        let puppet = Puppet::new(PuppetMock::new());
+        let mocker = Mocker::new(&puppet);
+        assert_eq!(puppet.state(), PuppetState::Stopped);
+        puppet.start().await.unwrap();
+        assert_eq!(puppet.state(), PuppetState::LoggedOut);
+        assert!(matches!(puppet.start().await, Err(PuppetError::InvalidState(_))));
+
+        mocker.login("bot");
+        puppet.flush().await;
+        assert_eq!(puppet.state(), PuppetState::LoggedIn("bot".to_owned()));
+        mocker.logout("bye");
+        puppet.flush().await;
+        assert_eq!(puppet.state(), PuppetState::LoggedOut);
+
+        puppet.stop().await.unwrap();
+        assert_eq!(puppet.state(), PuppetState::Stopped);
+        assert!(matches!(puppet.stop().await, Err(PuppetError::InvalidState(_))));
+        puppet.start().await.unwrap();
+        assert_eq!(puppet.state(), PuppetState::LoggedOut);
+    }
+
+    #[actix_rt::test]
+    async fn a_login_reported_before_start_is_kept() {
+        let puppet = Puppet::new(PuppetMock::new());
+        let mocker = Mocker::new(&puppet);
+        mocker.login("bot");
+        puppet.flush().await;
+        assert_eq!(puppet.state(), PuppetState::LoggedIn("bot".to_owned()));
+
+        puppet.start().await.unwrap();
+        assert_eq!(puppet.state(), PuppetState::LoggedIn("bot".to_owned()));
+        assert!(matches!(puppet.start().await, Err(PuppetError::InvalidState(_))));
+        puppet.stop().await.unwrap();
+        assert_eq!(puppet.state(), PuppetState::Stopped);
+    }
+
+    #[actix_rt::test]
+    async fn a_failed_start_can_be_retried() {
+        let puppet = Puppet::new(PuppetMock::new());
+        let mocker = Mocker::new(&puppet);
+        mocker.inject_faults(FaultOptions {
+            error_rates: vec![("start".to_owned(), 1.0)].into_iter().collect(),
+            ..FaultOptions::default()
+        });
+        assert!(puppet.start().await.is_err());
+        assert_eq!(puppet.state(), PuppetState::Stopped);
+
+        mocker.clear_faults();
+        puppet.start().await.unwrap();
+        assert_eq!(puppet.state(), PuppetState::LoggedOut);
+    }
+
+    #[actix_rt::test]
+    async fn a_login_reported_before_a_failed_start_is_kept() {
+        let puppet = Puppet::new(PuppetMock::new());
+        let mocker = Mocker::new(&puppet);
+        mocker.login("bot");
+        puppet.flush().await;
+        mocker.inject_faults(FaultOptions {
+            error_rates: vec![("start".to_owned(), 1.0)].into_iter().collect(),
+            ..FaultOptions::default()
+        });
+        assert!(puppet.start().await.is_err());
+        assert_eq!(puppet.state(), PuppetState::LoggedIn("bot".to_owned()));
+
+        mocker.clear_faults();
+        puppet.start().await.unwrap();
+        assert_eq!(puppet.state(), PuppetState::LoggedIn("bot".to_owned()));
+        assert!(matches!(puppet.start().await, Err(PuppetError::InvalidState(_))));
+
+        puppet.stop().await.unwrap();
+        mocker.login("bot");
+        mocker.logout("bye");
+        puppet.flush().await;
+        assert_eq!(puppet.state(), PuppetState::Stopped);
+        assert!(matches!(puppet.stop().await, Err(PuppetError::InvalidState(_))));
+    }
+
+    #[actix_rt::test]
+    async fn a_logout_reported_while_starting_or_stopping_is_kept() {
+        let puppet = Puppet::new(PuppetMock::new());
+        let mocker = Mocker::new(&puppet);
+        let mut events = Box::pin(puppet.events(16));
+        mocker.login("bot");
+        puppet.flush().await;
+        // The logout is injected when `start` is called, and handled while it is delayed.
+        mocker.inject_faults(FaultOptions {
+            latency: Some((100, 100)),
+            logout_after_calls: Some(1),
+            ..FaultOptions::default()
+        });
+        puppet.start().await.unwrap();
+        assert_eq!(puppet.state(), PuppetState::LoggedOut);
+
+        mocker.clear_faults();
+        mocker.login("bot");
+        puppet.flush().await;
+        mocker.inject_faults(FaultOptions {
+            error_rates: vec![("stop".to_owned(), 1.0)].into_iter().collect(),
+            latency: Some((100, 100)),
+            logout_after_calls: Some(1),
+            ..FaultOptions::default()
+        });
+        assert!(puppet.stop().await.is_err());
+        assert_eq!(puppet.state(), PuppetState::LoggedOut);
+
+        // State changes are delivered in the order they happened.
+        puppet.flush().await;
+        let mut changes = vec![];
+        while let Some(Some(Ok(event))) = events.next().now_or_never() {
+            if let PuppetEvent::StateChange(change) = event {
+                changes.push(change);
+            }
+        }
+        assert_eq!(changes[0].previous, PuppetState::Stopped);
+        assert_eq!(changes.last().unwrap().current, PuppetState::LoggedOut);
+        for pair in changes.windows(2) {
+            assert_eq!(pair[0].current, pair[1].previous, "{:?}", changes);
+        }
+    }
+}
@@ -1,62 +1,179 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use actix::clock::sleep;
 use async_trait::async_trait;
+use log::debug;
 use wechaty_puppet::*;
 
-#[derive(Debug)]
-pub struct PuppetMock {}
+use crate::world::{not_found, now, MessageContent, MockWorld, WorldPtr};
+
+/// A puppet backed by an in-memory world, to test bots without WeChat.
+///
+/// The world is populated and driven by a `Mocker`, which also delivers the events of the puppet.
+#[derive(Clone, Default)]
+pub struct PuppetMock {
+    pub(crate) world: WorldPtr,
+}
+
+impl PuppetMock {
+    pub fn new() -> Self {
+        Self {
+            world: Arc::new(Mutex::new(MockWorld::default())),
+        }
+    }
+
+    fn world(&self) -> MutexGuard<'_, MockWorld> {
+        self.world.lock().unwrap()
+    }
+
+    /// Apply the injected faults to a call of `method`.
+    async fn fault(&self, method: &str) -> Result<(), PuppetError> {
+        let fault = {
+            let mut world = self.world();
+            let fault = match &mut world.faults {
+                Some(faults) => faults.call(method),
+                None => return Ok(()),
+            };
+            if fault.logout {
+                if let Some(contact_id) = world.self_id.take() {
+                    debug!("Injecting a logout of {} before {}", contact_id, method);
+                    world.deliver(PuppetEvent::Logout(EventLogoutPayload {
+                        contact_id,
+                        data: "Injected logout".to_owned(),
+                    }));
+                }
+            }
+            fault
+        };
+        if let Some(latency) = fault.latency {
+            sleep(latency).await;
+        }
+        match fault.error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Get the file of a message, without injecting faults, which the caller has done.
+    fn file(&self, message_id: &str) -> Result<FileBox, PuppetError> {
+        match self.world().content(message_id)? {
+            MessageContent::File(file) => Ok(file.clone()),
+            _ => Err(PuppetError::InvalidPayload(format!(
+                "Message {} is not a file",
+                message_id
+            ))),
+        }
+    }
+}
 
-#[allow(dead_code)]
 #[async_trait]
 impl PuppetImpl for PuppetMock {
     async fn contact_self_name_set(&self, name: String) -> Result<(), PuppetError> {
-        unimplemented!()
+        debug!("contact_self_name_set(name = {})", name);
+        self.fault("contact_self_name_set").await?;
+        let mut world = self.world();
+        let self_id = world.self_id()?;
+        world.contact_mut(&self_id)?.name = name;
+        Ok(())
     }
 
     async fn contact_self_qr_code(&self) -> Result<String, PuppetError> {
-        unimplemented!()
+        debug!("contact_self_qr_code()");
+        self.fault("contact_self_qr_code").await?;
+        Ok(format!("mock://qrcode/{}", self.world().self_id()?))
     }
 
     async fn contact_self_signature_set(&self, signature: String) -> Result<(), PuppetError> {
-        unimplemented!()
+        debug!("contact_self_signature_set(signature = {})", signature);
+        self.fault("contact_self_signature_set").await?;
+        let mut world = self.world();
+        let self_id = world.self_id()?;
+        world.contact_mut(&self_id)?.signature = signature;
+        Ok(())
     }
 
     async fn tag_contact_add(&self, tag_id: String, contact_id: String) -> Result<(), PuppetError> {
-        unimplemented!()
+        debug!("tag_contact_add(tag_id = {}, contact_id = {})", tag_id, contact_id);
+        self.fault("tag_contact_add").await?;
+        let mut world = self.world();
+        world.contact_mut(&contact_id)?;
+        world.tags.entry(tag_id).or_default().insert(contact_id);
+        Ok(())
     }
 
     async fn tag_contact_remove(&self, tag_id: String, contact_id: String) -> Result<(), PuppetError> {
-        unimplemented!()
+        debug!("tag_contact_remove(tag_id = {}, contact_id = {})", tag_id, contact_id);
+        self.fault("tag_contact_remove").await?;
+        match self.world().tags.get_mut(&tag_id) {
+            Some(contacts) => {
+                contacts.remove(&contact_id);
+                Ok(())
+            }
+            None => Err(not_found("Tag", &tag_id)),
+        }
     }
 
     async fn tag_contact_delete(&self, tag_id: String) -> Result<(), PuppetError> {
-        unimplemented!()
+        debug!("tag_contact_delete(tag_id = {})", tag_id);
+        self.fault("tag_contact_delete").await?;
+        match self.world().tags.remove(&tag_id) {
+            Some(_) => Ok(()),
+            None => Err(not_found("Tag", &tag_id)),
+        }
     }
 
     async fn tag_contact_list(&self, contact_id: String) -> Result<Vec<String>, PuppetError> {
-        unimplemented!()
+        debug!("tag_contact_list(contact_id = {})", contact_id);
+        self.fault("tag_contact_list").await?;
+        Ok(self
+            .world()
+            .tags
+            .iter()
+            .filter(|(_, contacts)| contacts.contains(&contact_id))
+            .map(|(tag_id, _)| tag_id.clone())
+            .collect())
     }
 
     async fn tag_list(&self) -> Result<Vec<String>, PuppetError> {
-        unimplemented!()
+        debug!("tag_list()");
+        self.fault("tag_list").await?;
+        Ok(self.world().tags.keys().cloned().collect())
     }
 
     async fn contact_alias(&self, contact_id: String) -> Result<String, PuppetError> {
-        unimplemented!()
+        debug!("contact_alias(contact_id = {})", contact_id);
+        self.fault("contact_alias").await?;
+        Ok(self.world().contact_mut(&contact_id)?.alias.clone())
     }
 
     async fn contact_alias_set(&self, contact_id: String, alias: String) -> Result<(), PuppetError> {
-        unimplemented!()
+        debug!("contact_alias_set(contact_id = {}, alias = {})", contact_id, alias);
+        self.fault("contact_alias_set").await?;
+        self.world().contact_mut(&contact_id)?.alias = alias;
+        Ok(())
     }
 
     async fn contact_avatar(&self, contact_id: String) -> Result<FileBox, PuppetError> {
-        unimplemented!()
+        debug!("contact_avatar(contact_id = {})", contact_id);
+        self.fault("contact_avatar").await?;
+        Ok(FileBox::from(self.world().contact_mut(&contact_id)?.avatar.clone()))
     }
 
     async fn contact_avatar_set(&self, contact_id: String, file: FileBox) -> Result<(), PuppetError> {
-        unimplemented!()
+        debug!("contact_avatar_set(contact_id = {})", contact_id);
+        self.fault("contact_avatar_set").await?;
+        self.world().contact_mut(&contact_id)?.avatar = file.to_string();
+        Ok(())
     }
 
     async fn contact_phone_set(&self, contact_id: String, phone_list: Vec<String>) -> Result<(), PuppetError> {
-        unimplemented!()
+        debug!(
+            "contact_phone_set(contact_id = {}, phone_list = {:?})",
+            contact_id, phone_list
+        );
+        self.fault("contact_phone_set").await?;
+        self.world().contact_mut(&contact_id)?.phone = phone_list;
+        Ok(())
     }
 
     async fn contact_corporation_remark_set(
@@ -64,7 +181,18 @@ impl PuppetImpl for PuppetMock {
         contact_id: String,
         corporation_remark: Option<String>,
     ) -> Result<(), PuppetError> {
-        unimplemented!()
+        debug!(
+            "contact_corporation_remark_set(contact_id = {}, corporation_remark = {:?})",
+            contact_id, corporation_remark
+        );
+        self.fault("contact_corporation_remark_set").await?;
+        let mut world = self.world();
+        world.contact_mut(&contact_id)?;
+        match corporation_remark {
+            Some(corporation_remark) => world.corporation_remarks.insert(contact_id, corporation_remark),
+            None => world.corporation_remarks.remove(&contact_id),
+        };
+        Ok(())
     }
 
     async fn contact_description_set(
@@ -72,35 +200,76 @@ impl PuppetImpl for PuppetMock {
         contact_id: String,
         description: Option<String>,
     ) -> Result<(), PuppetError> {
-        unimplemented!()
+        debug!(
+            "contact_description_set(contact_id = {}, description = {:?})",
+            contact_id, description
+        );
+        self.fault("contact_description_set").await?;
+        self.world().contact_mut(&contact_id)?.description = description.unwrap_or_default();
+        Ok(())
     }
 
     async fn contact_list(&self) -> Result<Vec<String>, PuppetError> {
-        unimplemented!()
+        debug!("contact_list()");
+        self.fault("contact_list").await?;
+        Ok(self.world().contacts.keys().cloned().collect())
     }
 
     async fn contact_raw_payload(&self, contact_id: String) -> Result<ContactPayload, PuppetError> {
-        unimplemented!()
+        debug!("contact_raw_payload(contact_id = {})", contact_id);
+        self.fault("contact_raw_payload").await?;
+        Ok(self.world().contact_mut(&contact_id)?.clone())
     }
 
     async fn message_contact(&self, message_id: String) -> Result<String, PuppetError> {
-        unimplemented!()
+        debug!("message_contact(message_id = {})", message_id);
+        self.fault("message_contact").await?;
+        match self.world().content(&message_id)? {
+            MessageContent::Contact(contact_id) => Ok(contact_id.clone()),
+            _ => Err(PuppetError::InvalidPayload(format!(
+                "Message {} is not a contact card",
+                message_id
+            ))),
+        }
     }
 
     async fn message_file(&self, message_id: String) -> Result<FileBox, PuppetError> {
-        unimplemented!()
+        debug!("message_file(message_id = {})", message_id);
+        self.fault("message_file").await?;
+        self.file(&message_id)
     }
 
     async fn message_image(&self, message_id: String, image_type: ImageType) -> Result<FileBox, PuppetError> {
-        unimplemented!()
+        debug!(
+            "message_image(message_id = {}, image_type = {:?})",
+            message_id, image_type
+        );
+        self.fault("message_image").await?;
+        self.file(&message_id)
     }
 
     async fn message_mini_program(&self, message_id: String) -> Result<MiniProgramPayload, PuppetError> {
-        unimplemented!()
+        debug!("message_mini_program(message_id = {})", message_id);
+        self.fault("message_mini_program").await?;
+        match self.world().content(&message_id)? {
+            MessageContent::MiniProgram(payload) => Ok(payload.clone()),
+            _ => Err(PuppetError::InvalidPayload(format!(
+                "Message {} is not a mini program",
+                message_id
+            ))),
+        }
     }
 
     async fn message_url(&self, message_id: String) -> Result<UrlLinkPayload, PuppetError> {
-        unimplemented!()
+        debug!("message_url(message_id = {})", message_id);
+        self.fault("message_url").await?;
+        match self.world().content(&message_id)? {
+            MessageContent::UrlLink(payload) => Ok(payload.clone()),
+            _ => Err(PuppetError::InvalidPayload(format!(
+                "Message {} is not a url link",
+                message_id
+            ))),
+        }
     }
 
     async fn message_send_contact(
@@ -108,11 +277,22 @@ impl PuppetImpl for PuppetMock {
         conversation_id: String,
         contact_id: String,
     ) -> Result<Option<String>, PuppetError> {
-        unimplemented!()
+        debug!(
+            "message_send_contact(conversation_id = {}, contact_id = {})",
+            conversation_id, contact_id
+        );
+        self.fault("message_send_contact").await?;
+        let id = self
+            .world()
+            .send(conversation_id, MessageContent::Contact(contact_id))?;
+        Ok(Some(id))
     }
 
     async fn message_send_file(&self, conversation_id: String, file: FileBox) -> Result<Option<String>, PuppetError> {
-        unimplemented!()
+        debug!("message_send_file(conversation_id = {})", conversation_id);
+        self.fault("message_send_file").await?;
+        let id = self.world().send(conversation_id, MessageContent::File(file))?;
+        Ok(Some(id))
     }
 
     async fn message_send_mini_program(
@@ -120,7 +300,15 @@ impl PuppetImpl for PuppetMock {
         conversation_id: String,
         mini_program_payload: MiniProgramPayload,
     ) -> Result<Option<String>, PuppetError> {
-        unimplemented!()
+        debug!(
+            "message_send_mini_program(conversation_id = {}, mini_program_payload = {:?})",
+            conversation_id, mini_program_payload
+        );
+        self.fault("message_send_mini_program").await?;
+        let id = self
+            .world()
+            .send(conversation_id, MessageContent::MiniProgram(mini_program_payload))?;
+        Ok(Some(id))
     }
 
     async fn message_send_text(
@@ -129,7 +317,15 @@ impl PuppetImpl for PuppetMock {
         text: String,
         mention_id_list: Vec<String>,
     ) -> Result<Option<String>, PuppetError> {
-        unimplemented!()
+        debug!(
+            "message_send_text(conversation_id = {}, text = {}, mention_id_list = {:?})",
+            conversation_id, text, mention_id_list
+        );
+        self.fault("message_send_text").await?;
+        let id = self
+            .world()
+            .send(conversation_id, MessageContent::Text { text, mention_id_list })?;
+        Ok(Some(id))
     }
 
     async fn message_send_url(
@@ -137,94 +333,265 @@ impl PuppetImpl for PuppetMock {
         conversation_id: String,
         url_link_payload: UrlLinkPayload,
     ) -> Result<Option<String>, PuppetError> {
-        unimplemented!()
+        debug!(
+            "message_send_url(conversation_id = {}, url_link_payload = {:?})",
+            conversation_id, url_link_payload
+        );
+        self.fault("message_send_url").await?;
+        let id = self
+            .world()
+            .send(conversation_id, MessageContent::UrlLink(url_link_payload))?;
+        Ok(Some(id))
     }
 
     async fn message_raw_payload(&self, message_id: String) -> Result<MessagePayload, PuppetError> {
-        unimplemented!()
+        debug!("message_raw_payload(message_id = {})", message_id);
+        self.fault("message_raw_payload").await?;
+        match self.world().messages.get(&message_id) {
+            Some(payload) => Ok(payload.clone()),
+            None => Err(not_found("Message", &message_id)),
+        }
+    }
+
+    /// Recall a message the bot sent, which is then forgotten. Messages from others cannot be recalled.
+    async fn message_recall(&self, message_id: String) -> Result<bool, PuppetError> {
+        debug!("message_recall(message_id = {})", message_id);
+        self.fault("message_recall").await?;
+        let mut world = self.world();
+        if !world.messages.contains_key(&message_id) {
+            return Err(not_found("Message", &message_id));
+        }
+        match world.sent.iter().position(|sent| sent.id == message_id) {
+            Some(index) => {
+                world.sent.remove(index);
+                world.messages.remove(&message_id);
+                world.contents.remove(&message_id);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
     }
 
     async fn friendship_accept(&self, friendship_id: String) -> Result<(), PuppetError> {
-        unimplemented!()
+        debug!("friendship_accept(friendship_id = {})", friendship_id);
+        self.fault("friendship_accept").await?;
+        let mut world = self.world();
+        let contact_id = match world.friendships.get_mut(&friendship_id) {
+            Some(friendship) => {
+                friendship.friendship_type = FriendshipType::Confirm;
+                friendship.contact_id.clone()
+            }
+            None => return Err(not_found("Friendship", &friendship_id)),
+        };
+        world.contact_mut(&contact_id)?.friend = true;
+        Ok(())
     }
 
     async fn friendship_add(&self, contact_id: String, hello: Option<String>) -> Result<(), PuppetError> {
-        unimplemented!()
+        debug!("friendship_add(contact_id = {}, hello = {:?})", contact_id, hello);
+        self.fault("friendship_add").await?;
+        self.world().friendship_requests.push((contact_id, hello));
+        Ok(())
     }
 
     async fn friendship_search_phone(&self, phone: String) -> Result<Option<String>, PuppetError> {
-        unimplemented!()
+        debug!("friendship_search_phone(phone = {})", phone);
+        self.fault("friendship_search_phone").await?;
+        Ok(self
+            .world()
+            .contacts
+            .values()
+            .find(|contact| contact.phone.contains(&phone))
+            .map(|contact| contact.id.clone()))
     }
 
     async fn friendship_search_weixin(&self, weixin: String) -> Result<Option<String>, PuppetError> {
-        unimplemented!()
+        debug!("friendship_search_weixin(weixin = {})", weixin);
+        self.fault("friendship_search_weixin").await?;
+        Ok(self
+            .world()
+            .contacts
+            .values()
+            .find(|contact| contact.weixin == weixin)
+            .map(|contact| contact.id.clone()))
     }
 
     async fn friendship_raw_payload(&self, friendship_id: String) -> Result<FriendshipPayload, PuppetError> {
-        unimplemented!()
+        debug!("friendship_raw_payload(friendship_id = {})", friendship_id);
+        self.fault("friendship_raw_payload").await?;
+        match self.world().friendships.get(&friendship_id) {
+            Some(payload) => Ok(payload.clone()),
+            None => Err(not_found("Friendship", &friendship_id)),
+        }
     }
 
     async fn room_invitation_accept(&self, room_invitation_id: String) -> Result<(), PuppetError> {
-        unimplemented!()
+        debug!("room_invitation_accept(room_invitation_id = {})", room_invitation_id);
+        self.fault("room_invitation_accept").await?;
+        let mut world = self.world();
+        if !world.room_invitations.contains_key(&room_invitation_id) {
+            return Err(not_found("Room invitation", &room_invitation_id));
+        }
+        world.accepted_room_invitations.push(room_invitation_id);
+        Ok(())
     }
 
     async fn room_invitation_raw_payload(
         &self,
         room_invitation_id: String,
     ) -> Result<RoomInvitationPayload, PuppetError> {
-        unimplemented!()
+        debug!(
+            "room_invitation_raw_payload(room_invitation_id = {})",
+            room_invitation_id
+        );
+        self.fault("room_invitation_raw_payload").await?;
+        match self.world().room_invitations.get(&room_invitation_id) {
+            Some(payload) => Ok(payload.clone()),
+            None => Err(not_found("Room invitation", &room_invitation_id)),
+        }
     }
 
     async fn room_add(&self, room_id: String, contact_id: String) -> Result<(), PuppetError> {
-        unimplemented!()
+        debug!("room_add(room_id = {}, contact_id = {})", room_id, contact_id);
+        self.fault("room_add").await?;
+        let mut world = self.world();
+        let self_id = world.self_id()?;
+        let room = world.room_mut(&room_id)?;
+        if !room.member_id_list.contains(&contact_id) {
+            room.member_id_list.push(contact_id.clone());
+        }
+        world.emit(PuppetEvent::RoomJoin(EventRoomJoinPayload {
+            room_id,
+            inviter_id: self_id,
+            invitee_id_list: vec![contact_id],
+            timestamp: now(),
+        }));
+        Ok(())
     }
 
     async fn room_avatar(&self, room_id: String) -> Result<FileBox, PuppetError> {
-        unimplemented!()
+        debug!("room_avatar(room_id = {})", room_id);
+        self.fault("room_avatar").await?;
+        Ok(FileBox::from(self.world().room_mut(&room_id)?.avatar.clone()))
     }
 
     async fn room_create(&self, contact_id_list: Vec<String>, topic: Option<String>) -> Result<String, PuppetError> {
-        unimplemented!()
+        debug!(
+            "room_create(contact_id_list = {:?}, topic = {:?})",
+            contact_id_list, topic
+        );
+        self.fault("room_create").await?;
+        let mut world = self.world();
+        let self_id = world.self_id()?;
+        let room_id = world.next_id("room");
+        let mut member_id_list = vec![self_id.clone()];
+        member_id_list.extend(contact_id_list);
+        world.rooms.insert(
+            room_id.clone(),
+            RoomPayload {
+                id: room_id.clone(),
+                topic: topic.unwrap_or_default(),
+                avatar: String::new(),
+                member_id_list,
+                owner_id: self_id.clone(),
+                admin_id_list: vec![self_id],
+            },
+        );
+        Ok(room_id)
     }
 
     async fn room_del(&self, room_id: String, contact_id: String) -> Result<(), PuppetError> {
-        unimplemented!()
+        debug!("room_del(room_id = {}, contact_id = {})", room_id, contact_id);
+        self.fault("room_del").await?;
+        let mut world = self.world();
+        let self_id = world.self_id()?;
+        world.room_mut(&room_id)?.member_id_list.retain(|id| *id != contact_id);
+        world.emit(PuppetEvent::RoomLeave(EventRoomLeavePayload {
+            room_id,
+            remover_id: self_id,
+            removee_id_list: vec![contact_id],
+            timestamp: now(),
+        }));
+        Ok(())
     }
 
     async fn room_qr_code(&self, room_id: String) -> Result<String, PuppetError> {
-        unimplemented!()
+        debug!("room_qr_code(room_id = {})", room_id);
+        self.fault("room_qr_code").await?;
+        self.world().room_mut(&room_id)?;
+        Ok(format!("mock://qrcode/{}", room_id))
     }
 
     async fn room_quit(&self, room_id: String) -> Result<(), PuppetError> {
-        unimplemented!()
+        debug!("room_quit(room_id = {})", room_id);
+        self.fault("room_quit").await?;
+        let mut world = self.world();
+        let self_id = world.self_id()?;
+        world.room_mut(&room_id)?.member_id_list.retain(|id| *id != self_id);
+        world.emit(PuppetEvent::RoomLeave(EventRoomLeavePayload {
+            room_id,
+            remover_id: self_id.clone(),
+            removee_id_list: vec![self_id],
+            timestamp: now(),
+        }));
+        Ok(())
     }
 
     async fn room_topic(&self, room_id: String) -> Result<String, PuppetError> {
-        unimplemented!()
+        debug!("room_topic(room_id = {})", room_id);
+        self.fault("room_topic").await?;
+        Ok(self.world().room_mut(&room_id)?.topic.clone())
     }
 
     async fn room_topic_set(&self, room_id: String, topic: String) -> Result<(), PuppetError> {
-        unimplemented!()
+        debug!("room_topic_set(room_id = {}, topic = {})", room_id, topic);
+        self.fault("room_topic_set").await?;
+        let mut world = self.world();
+        let self_id = world.self_id()?;
+        let old_topic = std::mem::replace(&mut world.room_mut(&room_id)?.topic, topic.clone());
+        world.emit(PuppetEvent::RoomTopic(EventRoomTopicPayload {
+            room_id,
+            changer_id: self_id,
+            old_topic,
+            new_topic: topic,
+            timestamp: now(),
+        }));
+        Ok(())
     }
 
     async fn room_list(&self) -> Result<Vec<String>, PuppetError> {
-        unimplemented!()
+        debug!("room_list()");
+        self.fault("room_list").await?;
+        Ok(self.world().rooms.keys().cloned().collect())
     }
 
     async fn room_raw_payload(&self, room_id: String) -> Result<RoomPayload, PuppetError> {
-        unimplemented!()
+        debug!("room_raw_payload(room_id = {})", room_id);
+        self.fault("room_raw_payload").await?;
+        Ok(self.world().room_mut(&room_id)?.clone())
     }
 
     async fn room_announce(&self, room_id: String) -> Result<String, PuppetError> {
-        unimplemented!()
+        debug!("room_announce(room_id = {})", room_id);
+        self.fault("room_announce").await?;
+        let mut world = self.world();
+        world.room_mut(&room_id)?;
+        Ok(world.room_announcements.get(&room_id).cloned().unwrap_or_default())
     }
 
     async fn room_announce_set(&self, room_id: String, text: String) -> Result<(), PuppetError> {
-        unimplemented!()
+        debug!("room_announce_set(room_id = {}, text = {})", room_id, text);
+        self.fault("room_announce_set").await?;
+        let mut world = self.world();
+        world.room_mut(&room_id)?;
+        world.room_announcements.insert(room_id, text);
+        Ok(())
     }
 
     async fn room_member_list(&self, room_id: String) -> Result<Vec<String>, PuppetError> {
-        unimplemented!()
+        debug!("room_member_list(room_id = {})", room_id);
+        self.fault("room_member_list").await?;
+        Ok(self.world().room_mut(&room_id)?.member_id_list.clone())
     }
 
     async fn room_member_raw_payload(
@@ -232,26 +599,66 @@ impl PuppetImpl for PuppetMock {
         room_id: String,
         contact_id: String,
     ) -> Result<RoomMemberPayload, PuppetError> {
-        unimplemented!()
+        debug!(
+            "room_member_raw_payload(room_id = {}, contact_id = {})",
+            room_id, contact_id
+        );
+        self.fault("room_member_raw_payload").await?;
+        let mut world = self.world();
+        if !world.room_mut(&room_id)?.member_id_list.contains(&contact_id) {
+            return Err(not_found("Room member", &contact_id));
+        }
+        if let Some(member) = world.room_members.get(&(room_id, contact_id.clone())) {
+            return Ok(member.clone());
+        }
+        let (avatar, name) = match world.contacts.get(&contact_id) {
+            Some(contact) => (contact.avatar.clone(), contact.name.clone()),
+            None => (String::new(), String::new()),
+        };
+        Ok(RoomMemberPayload {
+            id: contact_id,
+            room_alias: String::new(),
+            inviter_id: String::new(),
+            avatar,
+            name,
+        })
     }
 
     async fn start(&self) -> Result<(), PuppetError> {
-        unimplemented!()
+        debug!("start()");
+        self.fault("start").await?;
+        Ok(())
     }
 
     async fn stop(&self) -> Result<(), PuppetError> {
-        unimplemented!()
+        debug!("stop()");
+        self.fault("stop").await?;
+        Ok(())
     }
 
     async fn ding(&self, data: String) -> Result<(), PuppetError> {
-        unimplemented!()
+        debug!("ding(data = {})", data);
+        self.fault("ding").await?;
+        self.world().emit(PuppetEvent::Dong(EventDongPayload { data }));
+        Ok(())
     }
 
     async fn version(&self) -> Result<String, PuppetError> {
-        unimplemented!()
+        debug!("version()");
+        self.fault("version").await?;
+        Ok(env!("CARGO_PKG_VERSION").to_owned())
     }
 
     async fn logout(&self) -> Result<(), PuppetError> {
-        unimplemented!()
+        debug!("logout()");
+        self.fault("logout").await?;
+        let mut world = self.world();
+        let contact_id = world.self_id()?;
+        world.self_id = None;
+        world.emit(PuppetEvent::Logout(EventLogoutPayload {
+            contact_id,
+            data: "logout".to_owned(),
+        }));
+        Ok(())
     }
 }
@@ -0,0 +1,556 @@
+use std::time::{Duration, Instant};
+use std::{error, fmt, fs, io, path::Path};
+
+use actix::clock::sleep;
+use log::debug;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{MessageContent, Mocker, SentMessage};
+
+/// How long the bot has to stay silent for a run to end.
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+/// A conversation script for the mock puppet, loaded from YAML or JSON.
+///
+/// ```yaml
+/// name: ding dong
+/// login: bot
+/// contacts:
+///   - id: alice
+///     name: Alice
+/// rooms:
+///   - id: room
+///     topic: Friends
+///     members: [bot, alice]
+/// events:
+///   - message: { from: alice, text: ding }
+///   - after_ms: 100
+///     message: { from: alice, room: room, text: ding, mentions: [bot] }
+///   - join: { room: room, inviter: alice, invitees: [bob] }
+///   - friendship: { contact: carol, hello: Hi }
+/// expect:
+///   - to: alice
+///     text: { equals: dong }
+///   - to: room
+///     text: { regex: "^dong" }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    #[serde(default)]
+    pub name: String,
+    /// The contact the bot logs in as before the events are played, if any.
+    #[serde(default)]
+    pub login: Option<String>,
+    #[serde(default)]
+    pub contacts: Vec<ScenarioContact>,
+    #[serde(default)]
+    pub rooms: Vec<ScenarioRoom>,
+    #[serde(default)]
+    pub events: Vec<ScenarioStep>,
+    /// The messages the bot should send, in order.
+    #[serde(default)]
+    pub expect: Vec<Expectation>,
+    /// How long to wait for the bot to send the expected messages and settle after the last event.
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_timeout_ms() -> u64 {
+    1000
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScenarioContact {
+    pub id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScenarioRoom {
+    pub id: String,
+    #[serde(default)]
+    pub topic: String,
+    #[serde(default)]
+    pub members: Vec<String>,
+}
+
+/// An inbound event, played `after_ms` milliseconds after the previous one.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScenarioStep {
+    #[serde(default)]
+    pub after_ms: u64,
+    #[serde(flatten)]
+    pub event: ScenarioEvent,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScenarioEvent {
+    Message {
+        from: String,
+        #[serde(default)]
+        room: Option<String>,
+        text: String,
+        #[serde(default)]
+        mentions: Vec<String>,
+    },
+    Join {
+        room: String,
+        inviter: String,
+        invitees: Vec<String>,
+    },
+    Leave {
+        room: String,
+        remover: String,
+        removees: Vec<String>,
+    },
+    Friendship {
+        contact: String,
+        #[serde(default)]
+        hello: String,
+    },
+}
+
+/// A message the bot is expected to send.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Expectation {
+    /// The contact or room the message is sent to.
+    pub to: String,
+    /// Any text matches if not set.
+    #[serde(default)]
+    pub text: Option<TextMatcher>,
+    #[serde(default)]
+    pub mentions: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextMatcher {
+    Equals(String),
+    Contains(String),
+    StartsWith(String),
+    EndsWith(String),
+    Regex(String),
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Yaml(serde_yaml::Error),
+    Regex(regex::Error),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io(e) => write!(fmt, "Failed to read scenario: {}", e),
+            ScenarioError::Json(e) => write!(fmt, "Invalid JSON scenario: {}", e),
+            ScenarioError::Yaml(e) => write!(fmt, "Invalid YAML scenario: {}", e),
+            ScenarioError::Regex(e) => write!(fmt, "Invalid text matcher: {}", e),
+        }
+    }
+}
+
+impl error::Error for ScenarioError {}
+
+impl TextMatcher {
+    fn matches(&self, text: &str) -> bool {
+        match self {
+            TextMatcher::Equals(expected) => text == expected,
+            TextMatcher::Contains(expected) => text.contains(expected.as_str()),
+            TextMatcher::StartsWith(expected) => text.starts_with(expected.as_str()),
+            TextMatcher::EndsWith(expected) => text.ends_with(expected.as_str()),
+            // Patterns are checked when the scenario is loaded.
+            TextMatcher::Regex(pattern) => Regex::new(pattern).map_or(false, |regex| regex.is_match(text)),
+        }
+    }
+}
+
+impl fmt::Display for TextMatcher {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextMatcher::Equals(expected) => write!(fmt, "{:?}", expected),
+            TextMatcher::Contains(expected) => write!(fmt, "contains {:?}", expected),
+            TextMatcher::StartsWith(expected) => write!(fmt, "starts with {:?}", expected),
+            TextMatcher::EndsWith(expected) => write!(fmt, "ends with {:?}", expected),
+            TextMatcher::Regex(pattern) => write!(fmt, "matches /{}/", pattern),
+        }
+    }
+}
+
+impl Expectation {
+    fn matches(&self, message: &SentMessage) -> bool {
+        if message.conversation_id != self.to {
+            return false;
+        }
+        match &message.content {
+            MessageContent::Text { text, mention_id_list } => {
+                self.text.as_ref().map_or(true, |matcher| matcher.matches(text))
+                    && self
+                        .mentions
+                        .as_ref()
+                        .map_or(true, |mentions| mentions == mention_id_list)
+            }
+            _ => self.text.is_none() && self.mentions.is_none(),
+        }
+    }
+}
+
+impl fmt::Display for Expectation {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "to {}: ", self.to)?;
+        match &self.text {
+            Some(matcher) => write!(fmt, "{}", matcher)?,
+            None => write!(fmt, "anything")?,
+        }
+        match &self.mentions {
+            Some(mentions) => write!(fmt, " mentioning {:?}", mentions),
+            None => Ok(()),
+        }
+    }
+}
+
+fn describe(message: &SentMessage) -> String {
+    let content = match &message.content {
+        MessageContent::Text { text, mention_id_list } if mention_id_list.is_empty() => format!("{:?}", text),
+        MessageContent::Text { text, mention_id_list } => format!("{:?} mentioning {:?}", text, mention_id_list),
+        MessageContent::Contact(contact_id) => format!("contact card of {}", contact_id),
+        MessageContent::File(_) => "file".to_owned(),
+        MessageContent::MiniProgram(_) => "mini program".to_owned(),
+        MessageContent::UrlLink(payload) => format!("url link {}", payload.url),
+    };
+    format!("to {}: {}", message.conversation_id, content)
+}
+
+impl Scenario {
+    pub fn from_yaml(yaml: &str) -> Result<Self, ScenarioError> {
+        serde_yaml::from_str::<Self>(yaml)
+            .map_err(ScenarioError::Yaml)
+            .and_then(Self::validated)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, ScenarioError> {
+        serde_json::from_str::<Self>(json)
+            .map_err(ScenarioError::Json)
+            .and_then(Self::validated)
+    }
+
+    /// Load a scenario file, it is read as JSON if its extension is `.json` and as YAML otherwise.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ScenarioError> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(ScenarioError::Io)?;
+        match path.extension() {
+            Some(extension) if extension == "json" => Self::from_json(&content),
+            _ => Self::from_yaml(&content),
+        }
+    }
+
+    fn validated(self) -> Result<Self, ScenarioError> {
+        for expectation in &self.expect {
+            if let Some(TextMatcher::Regex(pattern)) = &expectation.text {
+                Regex::new(pattern).map_err(ScenarioError::Regex)?;
+            }
+        }
+        Ok(self)
+    }
+
+    /// Play the scenario against the bot listening to the puppet of `mocker`.
+    ///
+    /// Messages sent by the bot before the run are not checked.
+    pub async fn run(&self, mocker: &Mocker) -> ScenarioReport {
+        debug!("Scenario::run(name = {})", self.name);
+        for contact in &self.contacts {
+            mocker.create_contact(&contact.id, contact.name.as_deref().unwrap_or(&contact.id));
+        }
+        for room in &self.rooms {
+            let members: Vec<&str> = room.members.iter().map(String::as_str).collect();
+            mocker.create_room(&room.id, &room.topic, &members);
+        }
+        if let Some(contact_id) = &self.login {
+            mocker.login(contact_id);
+        }
+        mocker.take_sent_messages();
+
+        for step in &self.events {
+            if step.after_ms > 0 {
+                sleep(Duration::from_millis(step.after_ms)).await;
+            }
+            self.play(mocker, &step.event);
+        }
+
+        // Wait for the expected number of messages, then for the bot to stop sending, so that extra messages
+        // are reported too.
+        let deadline = Instant::now() + Duration::from_millis(self.timeout_ms);
+        let (mut sent, mut last_sent_at) = (0, Instant::now());
+        while Instant::now() < deadline {
+            let now_sent = mocker.sent_messages().len();
+            if now_sent != sent {
+                sent = now_sent;
+                last_sent_at = Instant::now();
+            } else if sent >= self.expect.len() && last_sent_at.elapsed() >= SETTLE_TIME {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        ScenarioReport::new(self.name.clone(), &self.expect, mocker.take_sent_messages())
+    }
+
+    fn play(&self, mocker: &Mocker, event: &ScenarioEvent) {
+        match event {
+            ScenarioEvent::Message {
+                from,
+                room,
+                text,
+                mentions,
+            } => {
+                let mentions: Vec<&str> = mentions.iter().map(String::as_str).collect();
+                match room {
+                    Some(room) => mocker.receive_room_text(room, from, text, &mentions),
+                    None => mocker.receive_text(from, text),
+                };
+            }
+            ScenarioEvent::Join {
+                room,
+                inviter,
+                invitees,
+            } => {
+                let invitees: Vec<&str> = invitees.iter().map(String::as_str).collect();
+                mocker.join_room(room, &invitees, inviter);
+            }
+            ScenarioEvent::Leave {
+                room,
+                remover,
+                removees,
+            } => {
+                let removees: Vec<&str> = removees.iter().map(String::as_str).collect();
+                mocker.leave_room(room, &removees, remover);
+            }
+            ScenarioEvent::Friendship { contact, hello } => {
+                if mocker.contact(contact).is_none() {
+                    mocker.create_contact(contact, contact);
+                }
+                mocker.request_friendship(contact, hello);
+            }
+        }
+    }
+}
+
+/// The outcome of a scenario run.
+///
+/// It displays as a diff between the expected and the sent messages.
+#[derive(Debug, Clone)]
+pub struct ScenarioReport {
+    pub name: String,
+    pub passed: bool,
+    pub sent: Vec<SentMessage>,
+    pub diff: Vec<DiffLine>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffLine {
+    /// A sent message that met its expectation.
+    Same(String),
+    /// An expectation no sent message met.
+    Expected(String),
+    /// A sent message that was not expected.
+    Sent(String),
+}
+
+impl ScenarioReport {
+    /// Match the expectations in order against the sent messages, as many as possible, and diff the rest.
+    fn new(name: String, expect: &[Expectation], sent: Vec<SentMessage>) -> Self {
+        // matched[i][j] is the most expectations from `i` on that the messages from `j` on can meet in order.
+        let mut matched = vec![vec![0; sent.len() + 1]; expect.len() + 1];
+        for i in (0..expect.len()).rev() {
+            for j in (0..sent.len()).rev() {
+                matched[i][j] = if expect[i].matches(&sent[j]) {
+                    matched[i + 1][j + 1] + 1
+                } else {
+                    matched[i + 1][j].max(matched[i][j + 1])
+                };
+            }
+        }
+
+        let mut diff = vec![];
+        let (mut missing, mut extra) = (vec![], vec![]);
+        let (mut i, mut j) = (0, 0);
+        while i < expect.len() || j < sent.len() {
+            if i < expect.len() && j < sent.len() && expect[i].matches(&sent[j]) {
+                flush(&mut diff, &mut missing, &mut extra);
+                diff.push(DiffLine::Same(describe(&sent[j])));
+                i += 1;
+                j += 1;
+            } else if i < expect.len() && (j == sent.len() || matched[i + 1][j] >= matched[i][j + 1]) {
+                missing.push(DiffLine::Expected(expect[i].to_string()));
+                i += 1;
+            } else {
+                extra.push(DiffLine::Sent(describe(&sent[j])));
+                j += 1;
+            }
+        }
+        flush(&mut diff, &mut missing, &mut extra);
+        let passed = diff.iter().all(|line| matches!(line, DiffLine::Same(_)));
+        Self {
+            name,
+            passed,
+            sent,
+            diff,
+        }
+    }
+}
+
+/// Move the unmatched lines between two matches to the diff, pairing each expectation with a sent message.
+fn flush(diff: &mut Vec<DiffLine>, missing: &mut Vec<DiffLine>, extra: &mut Vec<DiffLine>) {
+    let (mut missing, mut extra) = (missing.drain(..), extra.drain(..));
+    loop {
+        match (missing.next(), extra.next()) {
+            (None, None) => break,
+            (expected, sent) => diff.extend(expected.into_iter().chain(sent)),
+        }
+    }
+}
+
+impl fmt::Display for ScenarioReport {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            fmt,
+            "Scenario {}: {}",
+            self.name,
+            if self.passed { "passed" } else { "failed" }
+        )?;
+        for line in &self.diff {
+            match line {
+                DiffLine::Same(message) => writeln!(fmt, "  {}", message)?,
+                DiffLine::Expected(expectation) => writeln!(fmt, "- {}", expectation)?,
+                DiffLine::Sent(message) => writeln!(fmt, "+ {}", message)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use wechaty_puppet::{Puppet, PuppetEvent, PuppetImpl};
+
+    use super::*;
+    use crate::PuppetMock;
+
+    const SCENARIO: &str = r#"
+name: ding dong
+login: bot
+contacts:
+  - id: alice
+    name: Alice
+rooms:
+  - id: room
+    topic: Friends
+    members: [bot, alice]
+events:
+  - message: { from: alice, text: ding }
+  - after_ms: 10
+    message: { from: alice, room: room, text: ding, mentions: [bot] }
+expect:
+  - to: alice
+    text: { equals: dong }
+  - to: room
+    text: { regex: "^do" }
+"#;
+
+    /// Start a bot answering every ding with `reply`.
+    fn ding_dong_bot(reply: &'static str) -> Mocker {
+        let puppet = Puppet::new(PuppetMock::new());
+        let mocker = Mocker::new(&puppet);
+        let mut events = Box::pin(puppet.events(16));
+        actix_rt::spawn(async move {
+            while let Some(Ok(event)) = events.next().await {
+                if let PuppetEvent::Message(payload) = event {
+                    let message = puppet.message_payload(payload.message_id).await.unwrap();
+                    if message.text == "ding" {
+                        let conversation_id = if message.room_id.is_empty() {
+                            message.from_id
+                        } else {
+                            message.room_id
+                        };
+                        puppet
+                            .message_send_text(conversation_id, reply.to_owned(), vec![])
+                            .await
+                            .unwrap();
+                    }
+                }
+            }
+        });
+        mocker
+    }
+
+    #[actix_rt::test]
+    async fn scenarios_pass_when_the_bot_replies_as_expected() {
+        let report = Scenario::from_yaml(SCENARIO).unwrap().run(&ding_dong_bot("dong")).await;
+        assert!(report.passed, "{}", report);
+        assert_eq!(
+            report.diff,
+            vec![
+                DiffLine::Same(r#"to alice: "dong""#.to_owned()),
+                DiffLine::Same(r#"to room: "dong""#.to_owned()),
+            ]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn scenarios_report_the_difference_otherwise() {
+        let mut scenario = Scenario::from_yaml(SCENARIO).unwrap();
+        scenario.timeout_ms = 100;
+        let report = scenario.run(&ding_dong_bot("pong")).await;
+        assert!(!report.passed);
+        assert_eq!(
+            report.to_string(),
+            "Scenario ding dong: failed\n\
+             - to alice: \"dong\"\n\
+             + to alice: \"pong\"\n\
+             - to room: matches /^do/\n\
+             + to room: \"pong\"\n"
+        );
+
+        // Expectations are matched in order, so a missing or an extra message only shows up by itself.
+        let mut scenario = Scenario::from_yaml(SCENARIO).unwrap();
+        scenario.expect.insert(
+            1,
+            Expectation {
+                to: "alice".to_owned(),
+                text: Some(TextMatcher::Equals("bye".to_owned())),
+                mentions: None,
+            },
+        );
+        let report = scenario.run(&ding_dong_bot("dong")).await;
+        assert_eq!(
+            report.diff,
+            vec![
+                DiffLine::Same(r#"to alice: "dong""#.to_owned()),
+                DiffLine::Expected(r#"to alice: "bye""#.to_owned()),
+                DiffLine::Same(r#"to room: "dong""#.to_owned()),
+            ]
+        );
+        let mut scenario = Scenario::from_yaml(SCENARIO).unwrap();
+        scenario.expect.remove(0);
+        let report = scenario.run(&ding_dong_bot("dong")).await;
+        assert!(!report.passed);
+        assert_eq!(
+            report.diff,
+            vec![
+                DiffLine::Sent(r#"to alice: "dong""#.to_owned()),
+                DiffLine::Same(r#"to room: "dong""#.to_owned()),
+            ]
+        );
+
+        let json = r#"{ "events": [], "expect": [{ "to": "alice", "text": { "regex": "(" } }] }"#;
+        assert!(matches!(Scenario::from_json(json), Err(ScenarioError::Regex(_))));
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix::Recipient;
+use log::error;
+use wechaty_puppet::*;
+
+use crate::faults::FaultInjector;
+
+/// What a message carries besides its payload.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessageContent {
+    Text { text: String, mention_id_list: Vec<String> },
+    Contact(String),
+    File(FileBox),
+    MiniProgram(MiniProgramPayload),
+    UrlLink(UrlLinkPayload),
+}
+
+/// A message sent by the bot through the mock puppet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentMessage {
+    pub id: String,
+    pub conversation_id: String,
+    pub content: MessageContent,
+}
+
+pub(crate) type WorldPtr = Arc<Mutex<MockWorld>>;
+
+/// The in-memory state shared by the mock puppet and its mocker.
+#[derive(Default)]
+pub(crate) struct MockWorld {
+    pub(crate) self_id: Option<String>,
+    pub(crate) contacts: BTreeMap<String, ContactPayload>,
+    pub(crate) corporation_remarks: HashMap<String, String>,
+    pub(crate) tags: BTreeMap<String, BTreeSet<String>>,
+    pub(crate) rooms: BTreeMap<String, RoomPayload>,
+    pub(crate) room_members: HashMap<(String, String), RoomMemberPayload>,
+    pub(crate) room_announcements: HashMap<String, String>,
+    pub(crate) room_invitations: HashMap<String, RoomInvitationPayload>,
+    pub(crate) accepted_room_invitations: Vec<String>,
+    pub(crate) friendships: HashMap<String, FriendshipPayload>,
+    pub(crate) friendship_requests: Vec<(String, Option<String>)>,
+    pub(crate) messages: HashMap<String, MessagePayload>,
+    pub(crate) contents: HashMap<String, MessageContent>,
+    pub(crate) sent: Vec<SentMessage>,
+    pub(crate) addr: Option<Recipient<PuppetEvent>>,
+    pub(crate) faults: Option<FaultInjector>,
+    id_counter: usize,
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+pub(crate) fn not_found(kind: &str, id: &str) -> PuppetError {
+    PuppetError::InvalidPayload(format!("{} {} not found", kind, id))
+}
+
+impl MockWorld {
+    pub(crate) fn next_id(&mut self, prefix: &str) -> String {
+        self.id_counter += 1;
+        format!("{}-{}", prefix, self.id_counter)
+    }
+
+    /// Emit an event, through the injected faults if any.
+    pub(crate) fn emit(&mut self, event: PuppetEvent) {
+        match &mut self.faults {
+            Some(faults) => {
+                for event in faults.event(event) {
+                    self.deliver(event);
+                }
+            }
+            None => self.deliver(event),
+        }
+    }
+
+    pub(crate) fn deliver(&self, event: PuppetEvent) {
+        match &self.addr {
+            Some(addr) => {
+                if let Err(e) = addr.do_send(event) {
+                    error!("Internal error: {}", e);
+                }
+            }
+            None => error!("Mock puppet has no mocker attached, event dropped: {:?}", event),
+        }
+    }
+
+    pub(crate) fn self_id(&self) -> Result<String, PuppetError> {
+        self.self_id
+            .clone()
+            .ok_or_else(|| PuppetError::InvalidState("Mock puppet is not logged in".to_owned()))
+    }
+
+    pub(crate) fn contact_mut(&mut self, contact_id: &str) -> Result<&mut ContactPayload, PuppetError> {
+        self.contacts
+            .get_mut(contact_id)
+            .ok_or_else(|| not_found("Contact", contact_id))
+    }
+
+    pub(crate) fn room_mut(&mut self, room_id: &str) -> Result<&mut RoomPayload, PuppetError> {
+        self.rooms.get_mut(room_id).ok_or_else(|| not_found("Room", room_id))
+    }
+
+    /// Store a message with its content, and return its id.
+    pub(crate) fn add_message(&mut self, mut payload: MessagePayload, content: MessageContent) -> String {
+        if payload.id.is_empty() {
+            payload.id = self.next_id("message");
+        }
+        let id = payload.id.clone();
+        self.messages.insert(id.clone(), payload);
+        self.contents.insert(id.clone(), content);
+        id
+    }
+
+    /// Record a message sent by the bot.
+    pub(crate) fn send(&mut self, conversation_id: String, content: MessageContent) -> Result<String, PuppetError> {
+        let from_id = self.self_id()?;
+        let (room_id, to_id) = if self.rooms.contains_key(&conversation_id) {
+            (conversation_id.clone(), String::new())
+        } else {
+            (String::new(), conversation_id.clone())
+        };
+        let (message_type, text, mention_id_list) = match &content {
+            MessageContent::Text { text, mention_id_list } => {
+                (MessageType::Text, text.clone(), mention_id_list.clone())
+            }
+            MessageContent::Contact(_) => (MessageType::Contact, String::new(), vec![]),
+            MessageContent::File(_) => (MessageType::Attachment, String::new(), vec![]),
+            MessageContent::MiniProgram(_) => (MessageType::MiniProgram, String::new(), vec![]),
+            MessageContent::UrlLink(_) => (MessageType::Url, String::new(), vec![]),
+        };
+        let payload = MessagePayload {
+            id: String::new(),
+            filename: String::new(),
+            text,
+            timestamp: now(),
+            message_type,
+            from_id,
+            mention_id_list,
+            room_id,
+            to_id,
+        };
+        let id = self.add_message(payload, content.clone());
+        self.sent.push(SentMessage {
+            id: id.clone(),
+            conversation_id,
+            content,
+        });
+        Ok(id)
+    }
+
+    pub(crate) fn content(&self, message_id: &str) -> Result<&MessageContent, PuppetError> {
+        self.contents
+            .get(message_id)
+            .ok_or_else(|| not_found("Message", message_id))
+    }
+}
@@ -0,0 +1,400 @@
+use num_traits::FromPrimitive;
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, json};
+use wechaty_grpc::puppet::{EventResponse, EventType};
+use wechaty_puppet::*;
+
+/// The JSON payload of an event response, shared by all event types.
+///
+/// Enums are kept as raw numbers, so that values unknown to this library decode to `Unknown` instead of
+/// failing the whole event.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct EventPayload {
+    pub data: Option<String>,
+    pub contact_id: Option<String>,
+    pub message_id: Option<String>,
+    pub room_invitation_id: Option<String>,
+    pub friendship_id: Option<String>,
+    pub qrcode: Option<String>,
+    pub status: Option<i32>,
+    pub timestamp: Option<u64>,
+    pub changer_id: Option<String>,
+    pub new_topic: Option<String>,
+    pub old_topic: Option<String>,
+    pub room_id: Option<String>,
+    pub removee_id_list: Option<Vec<String>>,
+    pub remover_id: Option<String>,
+    pub invitee_id_list: Option<Vec<String>>,
+    pub inviter_id: Option<String>,
+    pub payload_type: Option<i32>,
+    pub payload_id: Option<String>,
+}
+
+/// Decode an event response into a puppet event.
+///
+/// Event types unknown to this library are passed through as `PuppetEvent::Raw`.
+/// Return `Ok(None)` for events that carry nothing, and the reason if the response is malformed.
+pub(crate) fn decode_event(response: &EventResponse) -> Result<Option<PuppetEvent>, String> {
+    let event_type = match EventType::from_i32(response.r#type) {
+        Some(event_type) => event_type,
+        None => {
+            return Ok(Some(PuppetEvent::Raw(EventRawPayload {
+                event_type: response.r#type,
+                payload: response.payload.clone(),
+            })))
+        }
+    };
+    let payload: EventPayload = match from_str(&response.payload) {
+        Ok(payload) => payload,
+        Err(e) => {
+            return Err(format!(
+                "Malformed payload of event type {}, reason: {}",
+                response.r#type, e
+            ))
+        }
+    };
+
+    let event = match event_type {
+        EventType::Unspecified => return Ok(None),
+        EventType::Heartbeat => match payload.data {
+            Some(data) => PuppetEvent::Heartbeat(EventHeartbeatPayload { data }),
+            None => return Err("Heartbeat payload should have data".to_owned()),
+        },
+        EventType::Message => match payload.message_id {
+            Some(message_id) => PuppetEvent::Message(EventMessagePayload { message_id }),
+            None => return Err("Message payload should have message id".to_owned()),
+        },
+        EventType::Dong => match payload.data {
+            Some(data) => PuppetEvent::Dong(EventDongPayload { data }),
+            None => return Err("Dong payload should have data".to_owned()),
+        },
+        EventType::Error => match payload.data {
+            Some(data) => PuppetEvent::Error(EventErrorPayload { data }),
+            None => return Err("Error payload should have data".to_owned()),
+        },
+        EventType::Friendship => match payload.friendship_id {
+            Some(friendship_id) => PuppetEvent::Friendship(EventFriendshipPayload { friendship_id }),
+            None => return Err("Friendship payload should have friendship id".to_owned()),
+        },
+        EventType::RoomInvite => match payload.room_invitation_id {
+            Some(room_invitation_id) => PuppetEvent::RoomInvite(EventRoomInvitePayload { room_invitation_id }),
+            None => return Err("Room invite payload should have room invitation id".to_owned()),
+        },
+        EventType::RoomJoin => match (
+            payload.room_id,
+            payload.inviter_id,
+            payload.invitee_id_list,
+            payload.timestamp,
+        ) {
+            (Some(room_id), Some(inviter_id), Some(invitee_id_list), Some(timestamp)) => {
+                PuppetEvent::RoomJoin(EventRoomJoinPayload {
+                    room_id,
+                    inviter_id,
+                    invitee_id_list,
+                    timestamp,
+                })
+            }
+            _ => {
+                return Err(
+                    "Room join payload should have room id, inviter id, invitee id list and timestamp".to_owned(),
+                )
+            }
+        },
+        EventType::RoomLeave => match (
+            payload.room_id,
+            payload.remover_id,
+            payload.removee_id_list,
+            payload.timestamp,
+        ) {
+            (Some(room_id), Some(remover_id), Some(removee_id_list), Some(timestamp)) => {
+                PuppetEvent::RoomLeave(EventRoomLeavePayload {
+                    room_id,
+                    remover_id,
+                    removee_id_list,
+                    timestamp,
+                })
+            }
+            _ => {
+                return Err(
+                    "Room leave payload should have room id, remover id, removee id list and timestamp".to_owned(),
+                )
+            }
+        },
+        EventType::RoomTopic => match (
+            payload.room_id,
+            payload.changer_id,
+            payload.old_topic,
+            payload.new_topic,
+            payload.timestamp,
+        ) {
+            (Some(room_id), Some(changer_id), Some(old_topic), Some(new_topic), Some(timestamp)) => {
+                PuppetEvent::RoomTopic(EventRoomTopicPayload {
+                    room_id,
+                    changer_id,
+                    old_topic,
+                    new_topic,
+                    timestamp,
+                })
+            }
+            _ => {
+                return Err(
+                    "Room topic payload should have room id, changer id, old topic, new topic and timestamp".to_owned(),
+                )
+            }
+        },
+        EventType::Scan => match payload.status {
+            Some(status) => PuppetEvent::Scan(EventScanPayload {
+                status: ScanStatus::from_i32(status).unwrap_or(ScanStatus::Unknown),
+                qrcode: payload.qrcode,
+                data: payload.data,
+            }),
+            None => return Err("Scan payload should have scan status".to_owned()),
+        },
+        EventType::Ready => match payload.data {
+            Some(data) => PuppetEvent::Ready(EventReadyPayload { data }),
+            None => return Err("Ready payload should have data".to_owned()),
+        },
+        EventType::Reset => match payload.data {
+            Some(data) => PuppetEvent::Reset(EventResetPayload { data }),
+            None => return Err("Reset payload should have data".to_owned()),
+        },
+        EventType::Login => match payload.contact_id {
+            Some(contact_id) => PuppetEvent::Login(EventLoginPayload { contact_id }),
+            None => return Err("Login payload should have contact id".to_owned()),
+        },
+        EventType::Logout => match (payload.contact_id, payload.data) {
+            (Some(contact_id), Some(data)) => PuppetEvent::Logout(EventLogoutPayload { contact_id, data }),
+            _ => return Err("Logout payload should have contact id and data".to_owned()),
+        },
+        EventType::Dirty => match (payload.payload_type, payload.payload_id) {
+            (Some(payload_type), Some(payload_id)) => PuppetEvent::Dirty(EventDirtyPayload {
+                payload_type: PayloadType::from_i32(payload_type).unwrap_or(PayloadType::Unknown),
+                payload_id,
+            }),
+            _ => return Err("Dirty payload should have payload type and payload id".to_owned()),
+        },
+    };
+    Ok(Some(event))
+}
+
+/// Encode a puppet event into an event response, the reverse of `decode_event`.
+///
+/// Return `None` for events that only make sense locally, like state changes.
+pub(crate) fn encode_event(event: &PuppetEvent) -> Option<EventResponse> {
+    let (event_type, payload) = match event {
+        PuppetEvent::Dirty(payload) => (
+            EventType::Dirty,
+            json!({"payloadType": payload.payload_type.clone() as i32, "payloadId": payload.payload_id}),
+        ),
+        PuppetEvent::Dong(payload) => (EventType::Dong, json!({ "data": payload.data })),
+        PuppetEvent::Error(payload) => (EventType::Error, json!({ "data": payload.data })),
+        PuppetEvent::Friendship(payload) => (EventType::Friendship, json!({ "friendshipId": payload.friendship_id })),
+        PuppetEvent::Heartbeat(payload) => (EventType::Heartbeat, json!({ "data": payload.data })),
+        PuppetEvent::Login(payload) => (EventType::Login, json!({ "contactId": payload.contact_id })),
+        PuppetEvent::Logout(payload) => (
+            EventType::Logout,
+            json!({"contactId": payload.contact_id, "data": payload.data}),
+        ),
+        PuppetEvent::Message(payload) => (EventType::Message, json!({ "messageId": payload.message_id })),
+        PuppetEvent::Ready(payload) => (EventType::Ready, json!({ "data": payload.data })),
+        PuppetEvent::Reset(payload) => (EventType::Reset, json!({ "data": payload.data })),
+        PuppetEvent::RoomInvite(payload) => (
+            EventType::RoomInvite,
+            json!({ "roomInvitationId": payload.room_invitation_id }),
+        ),
+        PuppetEvent::RoomJoin(payload) => (
+            EventType::RoomJoin,
+            json!({
+                "roomId": payload.room_id,
+                "inviterId": payload.inviter_id,
+                "inviteeIdList": payload.invitee_id_list,
+                "timestamp": payload.timestamp,
+            }),
+        ),
+        PuppetEvent::RoomLeave(payload) => (
+            EventType::RoomLeave,
+            json!({
+                "roomId": payload.room_id,
+                "removerId": payload.remover_id,
+                "removeeIdList": payload.removee_id_list,
+                "timestamp": payload.timestamp,
+            }),
+        ),
+        PuppetEvent::RoomTopic(payload) => (
+            EventType::RoomTopic,
+            json!({
+                "roomId": payload.room_id,
+                "changerId": payload.changer_id,
+                "oldTopic": payload.old_topic,
+                "newTopic": payload.new_topic,
+                "timestamp": payload.timestamp,
+            }),
+        ),
+        PuppetEvent::Scan(payload) => (
+            EventType::Scan,
+            json!({
+                "status": payload.status.clone() as i32,
+                "qrcode": payload.qrcode,
+                "data": payload.data,
+            }),
+        ),
+        PuppetEvent::StateChange(_) => return None,
+        PuppetEvent::Raw(payload) => {
+            return Some(EventResponse {
+                r#type: payload.event_type,
+                payload: payload.payload.clone(),
+            })
+        }
+    };
+    Some(EventResponse {
+        r#type: event_type as i32,
+        payload: payload.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(r#type: i32, payload: &str) -> Result<Option<PuppetEvent>, String> {
+        decode_event(&EventResponse {
+            r#type,
+            payload: payload.to_owned(),
+        })
+    }
+
+    #[test]
+    fn unknown_enum_values_decode_to_unknown() {
+        match decode(22, r#"{"status": 42, "qrcode": "qrcode"}"#) {
+            Ok(Some(PuppetEvent::Scan(payload))) => assert_eq!(payload.status, ScanStatus::Unknown),
+            result => panic!("Expected scan event, got {:?}", result),
+        }
+        match decode(27, r#"{"payloadType": -1, "payloadId": "id"}"#) {
+            Ok(Some(PuppetEvent::Dirty(payload))) => assert_eq!(payload.payload_type, PayloadType::Unknown),
+            result => panic!("Expected dirty event, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn unknown_event_types_pass_through() {
+        for &r#type in &[-1, 4, 15, 28, i32::MIN, i32::MAX] {
+            match decode(r#type, "{\"data\": {\"nested\": true}}") {
+                Ok(Some(PuppetEvent::Raw(payload))) => {
+                    assert_eq!(payload.event_type, r#type);
+                    assert_eq!(payload.payload, "{\"data\": {\"nested\": true}}");
+                }
+                result => panic!("Expected raw event, got {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn encoded_events_decode_to_themselves() {
+        let events = vec![
+            PuppetEvent::Dirty(EventDirtyPayload {
+                payload_type: PayloadType::Room,
+                payload_id: "room".to_owned(),
+            }),
+            PuppetEvent::Logout(EventLogoutPayload {
+                contact_id: "contact".to_owned(),
+                data: "bye".to_owned(),
+            }),
+            PuppetEvent::Message(EventMessagePayload {
+                message_id: "message".to_owned(),
+            }),
+            PuppetEvent::RoomJoin(EventRoomJoinPayload {
+                room_id: "room".to_owned(),
+                inviter_id: "inviter".to_owned(),
+                invitee_id_list: vec!["a".to_owned(), "b".to_owned()],
+                timestamp: 1_600_000_000,
+            }),
+            PuppetEvent::RoomTopic(EventRoomTopicPayload {
+                room_id: "room".to_owned(),
+                changer_id: "changer".to_owned(),
+                old_topic: "old".to_owned(),
+                new_topic: "new".to_owned(),
+                timestamp: 1_600_000_000,
+            }),
+            PuppetEvent::Scan(EventScanPayload {
+                status: ScanStatus::Waiting,
+                qrcode: Some("qrcode".to_owned()),
+                data: None,
+            }),
+            PuppetEvent::Raw(EventRawPayload {
+                event_type: 99,
+                payload: "{}".to_owned(),
+            }),
+        ];
+        for event in events {
+            let decoded = decode_event(&encode_event(&event).unwrap()).unwrap().unwrap();
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", event));
+        }
+    }
+
+    #[test]
+    fn malformed_events_never_panic() {
+        let malformed_payloads = [
+            "",
+            "{",
+            "}",
+            "null",
+            "[]",
+            "42",
+            "\"data\"",
+            "{\"data\": 42}",
+            "{\"data\": null}",
+            "{\"status\": \"1\"}",
+            "{\"status\": 1.5}",
+            "{\"timestamp\": -1}",
+            "{\"timestamp\": 1e40}",
+            "{\"timestamp\": \"now\"}",
+            "{\"inviteeIdList\": \"contact\"}",
+            "{\"removeeIdList\": [1, 2]}",
+            "{\"payloadType\": 99999999999}",
+            "{\"roomId\": \"room\", \"inviterId\": \"contact\"}",
+            "\u{0}\u{1}\u{fffd}",
+        ];
+        for r#type in 0..28 {
+            if EventType::from_i32(r#type).is_none() {
+                continue;
+            }
+            for payload in malformed_payloads.iter() {
+                if let Ok(Some(event)) = decode(r#type, payload) {
+                    panic!("Malformed event {} {:?} decoded to {:?}", r#type, payload, event);
+                }
+            }
+        }
+
+        // Glue random fragments together, the same seed always gives the same corpus.
+        let fragments = [
+            "{",
+            "}",
+            "[",
+            "]",
+            ",",
+            ":",
+            "\"data\"",
+            "\"status\"",
+            "\"timestamp\"",
+            "\"roomId\"",
+            "\"x\"",
+            "1",
+            "-1",
+            "null",
+            "true",
+            "\\",
+            "\"",
+        ];
+        let mut seed: u64 = 0x5eed;
+        for _ in 0..2000 {
+            let mut payload = String::new();
+            for _ in 0..(seed % 12) {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                payload.push_str(fragments[(seed >> 33) as usize % fragments.len()]);
+            }
+            let r#type = (seed >> 40) as i32 % 32;
+            decode(r#type, &payload).ok();
+        }
+    }
+}
@@ -1,5 +1,8 @@
-mod from_payload_response;
+mod event_payload;
+mod puppet_server;
 mod puppet_service;
 mod service_endpoint;
+mod tls;
 
+pub use puppet_server::PuppetServer;
 pub use puppet_service::PuppetService;
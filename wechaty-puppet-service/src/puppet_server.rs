@@ -0,0 +1,1156 @@
+use std::net::SocketAddr;
+use std::pin::Pin;
+
+use async_trait::async_trait;
+use log::{debug, error, warn};
+use num_traits::FromPrimitive;
+use serde_json::from_str;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+use wechaty_grpc::puppet::*;
+use wechaty_grpc::puppet_server::{Puppet as PuppetRpc, PuppetServer as PuppetRpcServer};
+use wechaty_puppet::{
+    EventDirtyPayload, EventLoginPayload, FileBox, ImageType, PayloadType, Puppet, PuppetError, PuppetEvent, PuppetImpl,
+};
+
+use crate::event_payload::encode_event;
+
+const EVENT_BUFFER_SIZE: usize = 256;
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + Sync + 'static>>;
+
+/// A puppet service that serves any puppet over gRPC, so that bots in other languages can drive it.
+///
+/// ```ignore
+/// let server = PuppetServer::new(Puppet::new(puppet_impl));
+/// server.serve("0.0.0.0:8788".parse().unwrap(), Some(token)).await?;
+/// ```
+#[derive(Clone)]
+pub struct PuppetServer<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    puppet: Puppet<T>,
+}
+
+impl<T> PuppetServer<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    pub fn new(puppet: Puppet<T>) -> Self {
+        Self { puppet }
+    }
+
+    /// Get the tonic service, to be served with a custom `tonic::transport::Server`, e.g. one with TLS.
+    ///
+    /// When `token` is given, calls must carry the `authorization: Wechaty <token>` metadata.
+    pub fn into_service(self, token: Option<String>) -> PuppetRpcServer<Self> {
+        match token {
+            Some(token) => {
+                let expected = format!("Wechaty {}", token);
+                // Interceptors have to fail with `tonic::Status`.
+                #[allow(clippy::result_large_err)]
+                let interceptor = move |request: Request<()>| match request.metadata().get("authorization") {
+                    Some(authorization) if authorization == expected.as_str() => Ok(request),
+                    _ => Err(Status::unauthenticated("Invalid token")),
+                };
+                PuppetRpcServer::with_interceptor(self, interceptor)
+            }
+            None => PuppetRpcServer::new(self),
+        }
+    }
+
+    /// Serve on `addr` until the server fails.
+    pub async fn serve(self, addr: SocketAddr, token: Option<String>) -> Result<(), PuppetError> {
+        debug!("serve(addr = {})", addr);
+        match Server::builder()
+            .add_service(self.into_service(token))
+            .serve(addr)
+            .await
+        {
+            Ok(()) => Ok(()),
+            Err(e) => Err(PuppetError::Network(format!("Puppet server failed, reason: {}", e))),
+        }
+    }
+}
+
+fn status(error: PuppetError) -> Status {
+    match error {
+        PuppetError::InvalidToken => Status::unauthenticated(error.to_string()),
+        PuppetError::Network(_) => Status::unavailable(error.to_string()),
+        PuppetError::Unsupported(_) => Status::unimplemented(error.to_string()),
+        PuppetError::InvalidState(_) => Status::failed_precondition(error.to_string()),
+        PuppetError::Timeout(_) => Status::deadline_exceeded(error.to_string()),
+        _ => Status::internal(error.to_string()),
+    }
+}
+
+/// Forward puppet events to a client until it hangs up.
+///
+/// A login after subscribing but before the replay is both replayed and in the stream, so the stream's copy is
+/// skipped until the next logout.
+async fn forward_events<S>(
+    mut events: S,
+    sender: mpsc::Sender<Result<EventResponse, Status>>,
+    mut replayed_login: Option<String>,
+) where
+    S: Stream<Item = Result<PuppetEvent, PuppetError>> + Unpin,
+{
+    while let Some(event) = events.next().await {
+        match event {
+            Ok(PuppetEvent::Login(payload)) if replayed_login.as_ref() == Some(&payload.contact_id) => {
+                debug!("Skipping the login of {}, already replayed", payload.contact_id);
+            }
+            Ok(event) => {
+                if let PuppetEvent::Logout(_) = event {
+                    replayed_login = None;
+                }
+                if let Some(response) = encode_event(&event) {
+                    if sender.send(Ok(response)).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            Err(e) => warn!("Event client fell behind, reason: {}", e),
+        }
+    }
+}
+
+/// File boxes have no string form yet, so the calls passing them as strings are refused rather than answered
+/// with an empty file. Files go through the stream variants of the calls instead.
+fn filebox_string_unsupported(method: &str) -> Status {
+    Status::unimplemented(format!(
+        "{} passes a file box as a string, which is not supported yet",
+        method
+    ))
+}
+
+/// Split a file box into the chunks of a file stream: its name, then its contents.
+fn file_chunks(filebox: FileBox) -> Vec<FileBoxChunk> {
+    let name = FileBoxChunk {
+        payload: Some(file_box_chunk::Payload::Name(filebox.name().to_owned())),
+    };
+    std::iter::once(name)
+        .chain(filebox.buffer().chunks(FILE_CHUNK_SIZE).map(|data| FileBoxChunk {
+            payload: Some(file_box_chunk::Payload::Data(data.to_vec())),
+        }))
+        .collect()
+}
+
+#[async_trait]
+impl<T> PuppetRpc for PuppetServer<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    async fn start(&self, _request: Request<StartRequest>) -> Result<Response<StartResponse>, Status> {
+        debug!("start()");
+        match self.puppet.start().await {
+            Ok(()) => Ok(Response::new(StartResponse {})),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn stop(&self, _request: Request<StopRequest>) -> Result<Response<StopResponse>, Status> {
+        debug!("stop()");
+        match self.puppet.stop().await {
+            Ok(()) => Ok(Response::new(StopResponse {})),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn logout(&self, _request: Request<LogoutRequest>) -> Result<Response<LogoutResponse>, Status> {
+        debug!("logout()");
+        match self.puppet.logout().await {
+            Ok(()) => Ok(Response::new(LogoutResponse {})),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn ding(&self, request: Request<DingRequest>) -> Result<Response<DingResponse>, Status> {
+        let request = request.into_inner();
+        debug!("ding(data = {})", request.data);
+        match self.puppet.ding(request.data).await {
+            Ok(()) => Ok(Response::new(DingResponse {})),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn version(&self, _request: Request<VersionRequest>) -> Result<Response<VersionResponse>, Status> {
+        debug!("version()");
+        match self.puppet.version().await {
+            Ok(version) => Ok(Response::new(VersionResponse { version })),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    type EventStream = ResponseStream<EventResponse>;
+
+    /// Fan out the puppet events to a client.
+    ///
+    /// A client connecting to a logged in puppet gets the login event first.
+    async fn event(&self, _request: Request<EventRequest>) -> Result<Response<Self::EventStream>, Status> {
+        debug!("event()");
+        let (sender, receiver) = mpsc::channel(EVENT_BUFFER_SIZE);
+        let events = self.puppet.events(EVENT_BUFFER_SIZE);
+        let replayed_login = self.puppet.self_id();
+        if let Some(contact_id) = replayed_login.clone() {
+            let login = PuppetEvent::Login(EventLoginPayload { contact_id });
+            let response = encode_event(&login).ok_or_else(|| Status::internal("Failed to encode the login event"))?;
+            sender.send(Ok(response)).await.ok();
+        }
+        tokio::spawn(forward_events(events, sender, replayed_login));
+        Ok(Response::new(Box::pin(ReceiverStream::new(receiver))))
+    }
+
+    async fn dirty_payload(
+        &self,
+        request: Request<DirtyPayloadRequest>,
+    ) -> Result<Response<DirtyPayloadResponse>, Status> {
+        let request = request.into_inner();
+        debug!("dirty_payload(type = {}, id = {})", request.r#type, request.id);
+        let payload_type = PayloadType::from_i32(request.r#type).unwrap_or(PayloadType::Unknown);
+        match self
+            .puppet
+            .clone()
+            .dirty_payload(payload_type.clone(), request.id.clone())
+            .await
+        {
+            Ok(()) => {
+                let dirty = PuppetEvent::Dirty(EventDirtyPayload {
+                    payload_type,
+                    payload_id: request.id,
+                });
+                if let Err(e) = self.puppet.self_addr().do_send(dirty) {
+                    error!("Internal error: {}", e);
+                }
+                Ok(Response::new(DirtyPayloadResponse {}))
+            }
+            Err(PuppetError::UnknownPayloadType) => Err(Status::invalid_argument("Unknown payload type")),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn contact_self_qr_code(
+        &self,
+        _request: Request<ContactSelfQrCodeRequest>,
+    ) -> Result<Response<ContactSelfQrCodeResponse>, Status> {
+        debug!("contact_self_qr_code()");
+        match self.puppet.contact_self_qr_code().await {
+            Ok(qrcode) => Ok(Response::new(ContactSelfQrCodeResponse { qrcode })),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn contact_self_name(
+        &self,
+        request: Request<ContactSelfNameRequest>,
+    ) -> Result<Response<ContactSelfNameResponse>, Status> {
+        let request = request.into_inner();
+        debug!("contact_self_name(name = {})", request.name);
+        match self.puppet.contact_self_name_set(request.name).await {
+            Ok(()) => Ok(Response::new(ContactSelfNameResponse {})),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn contact_self_signature(
+        &self,
+        request: Request<ContactSelfSignatureRequest>,
+    ) -> Result<Response<ContactSelfSignatureResponse>, Status> {
+        let request = request.into_inner();
+        debug!("contact_self_signature(signature = {})", request.signature);
+        match self.puppet.contact_self_signature_set(request.signature).await {
+            Ok(()) => Ok(Response::new(ContactSelfSignatureResponse {})),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn contact_payload(
+        &self,
+        request: Request<ContactPayloadRequest>,
+    ) -> Result<Response<ContactPayloadResponse>, Status> {
+        let request = request.into_inner();
+        debug!("contact_payload(id = {})", request.id);
+        match self.puppet.contact_payload(request.id).await {
+            Ok(payload) => Ok(Response::new(payload.into())),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn contact_alias(
+        &self,
+        request: Request<ContactAliasRequest>,
+    ) -> Result<Response<ContactAliasResponse>, Status> {
+        let request = request.into_inner();
+        debug!("contact_alias(id = {}, alias = {:?})", request.id, request.alias);
+        match request.alias {
+            Some(alias) => match self.puppet.contact_alias_set(request.id, alias).await {
+                Ok(()) => Ok(Response::new(ContactAliasResponse { alias: None })),
+                Err(e) => Err(status(e)),
+            },
+            None => match self.puppet.contact_alias(request.id).await {
+                Ok(alias) => Ok(Response::new(ContactAliasResponse { alias: Some(alias) })),
+                Err(e) => Err(status(e)),
+            },
+        }
+    }
+
+    async fn contact_avatar(
+        &self,
+        request: Request<ContactAvatarRequest>,
+    ) -> Result<Response<ContactAvatarResponse>, Status> {
+        let request = request.into_inner();
+        debug!("contact_avatar(id = {}, filebox = {:?})", request.id, request.filebox);
+        Err(filebox_string_unsupported("contact_avatar"))
+    }
+
+    async fn contact_phone(
+        &self,
+        request: Request<ContactPhoneRequest>,
+    ) -> Result<Response<ContactPhoneResponse>, Status> {
+        let request = request.into_inner();
+        debug!(
+            "contact_phone(contact_id = {}, phone_list = {:?})",
+            request.contact_id, request.phone_list
+        );
+        match self
+            .puppet
+            .contact_phone_set(request.contact_id, request.phone_list.clone())
+            .await
+        {
+            Ok(()) => Ok(Response::new(ContactPhoneResponse {
+                phone_list: request.phone_list,
+            })),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn contact_corporation_remark(
+        &self,
+        request: Request<ContactCorporationRemarkRequest>,
+    ) -> Result<Response<ContactCorporationRemarkResponse>, Status> {
+        let request = request.into_inner();
+        debug!(
+            "contact_corporation_remark(contact_id = {}, corporation_remark = {:?})",
+            request.contact_id, request.corporation_remark
+        );
+        match self
+            .puppet
+            .contact_corporation_remark_set(request.contact_id, request.corporation_remark)
+            .await
+        {
+            Ok(()) => Ok(Response::new(ContactCorporationRemarkResponse {})),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn contact_description(
+        &self,
+        request: Request<ContactDescriptionRequest>,
+    ) -> Result<Response<ContactDescriptionResponse>, Status> {
+        let request = request.into_inner();
+        debug!(
+            "contact_description(contact_id = {}, description = {:?})",
+            request.contact_id, request.description
+        );
+        match self
+            .puppet
+            .contact_description_set(request.contact_id, request.description)
+            .await
+        {
+            Ok(()) => Ok(Response::new(ContactDescriptionResponse {})),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn contact_list(
+        &self,
+        _request: Request<ContactListRequest>,
+    ) -> Result<Response<ContactListResponse>, Status> {
+        debug!("contact_list()");
+        match self.puppet.contact_list().await {
+            Ok(ids) => Ok(Response::new(ContactListResponse { ids })),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn friendship_payload(
+        &self,
+        request: Request<FriendshipPayloadRequest>,
+    ) -> Result<Response<FriendshipPayloadResponse>, Status> {
+        let request = request.into_inner();
+        debug!("friendship_payload(id = {})", request.id);
+        match self.puppet.friendship_payload(request.id).await {
+            Ok(payload) => Ok(Response::new(payload.into())),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn friendship_search_phone(
+        &self,
+        request: Request<FriendshipSearchPhoneRequest>,
+    ) -> Result<Response<FriendshipSearchPhoneResponse>, Status> {
+        let request = request.into_inner();
+        debug!("friendship_search_phone(phone = {})", request.phone);
+        match self.puppet.friendship_search_phone(request.phone).await {
+            Ok(contact_id) => Ok(Response::new(FriendshipSearchPhoneResponse { contact_id })),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn friendship_search_weixin(
+        &self,
+        request: Request<FriendshipSearchWeixinRequest>,
+    ) -> Result<Response<FriendshipSearchWeixinResponse>, Status> {
+        let request = request.into_inner();
+        debug!("friendship_search_weixin(weixin = {})", request.weixin);
+        match self.puppet.friendship_search_weixin(request.weixin).await {
+            Ok(contact_id) => Ok(Response::new(FriendshipSearchWeixinResponse { contact_id })),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn friendship_add(
+        &self,
+        request: Request<FriendshipAddRequest>,
+    ) -> Result<Response<FriendshipAddResponse>, Status> {
+        let request = request.into_inner();
+        debug!(
+            "friendship_add(contact_id = {}, hello = {})",
+            request.contact_id, request.hello
+        );
+        let hello = if request.hello.is_empty() {
+            None
+        } else {
+            Some(request.hello)
+        };
+        match self.puppet.friendship_add(request.contact_id, hello).await {
+            Ok(()) => Ok(Response::new(FriendshipAddResponse {})),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn friendship_accept(
+        &self,
+        request: Request<FriendshipAcceptRequest>,
+    ) -> Result<Response<FriendshipAcceptResponse>, Status> {
+        let request = request.into_inner();
+        debug!("friendship_accept(id = {})", request.id);
+        match self.puppet.friendship_accept(request.id).await {
+            Ok(()) => Ok(Response::new(FriendshipAcceptResponse {})),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn message_file(
+        &self,
+        request: Request<MessageFileRequest>,
+    ) -> Result<Response<MessageFileResponse>, Status> {
+        let request = request.into_inner();
+        debug!("message_file(id = {})", request.id);
+        Err(filebox_string_unsupported("message_file"))
+    }
+
+    async fn message_image(
+        &self,
+        request: Request<MessageImageRequest>,
+    ) -> Result<Response<MessageImageResponse>, Status> {
+        let request = request.into_inner();
+        debug!("message_image(id = {}, type = {})", request.id, request.r#type);
+        Err(filebox_string_unsupported("message_image"))
+    }
+
+    async fn message_send_file(
+        &self,
+        request: Request<MessageSendFileRequest>,
+    ) -> Result<Response<MessageSendFileResponse>, Status> {
+        let request = request.into_inner();
+        debug!(
+            "message_send_file(conversation_id = {}, filebox = {})",
+            request.conversation_id, request.filebox
+        );
+        Err(filebox_string_unsupported("message_send_file"))
+    }
+
+    async fn message_payload(
+        &self,
+        request: Request<MessagePayloadRequest>,
+    ) -> Result<Response<MessagePayloadResponse>, Status> {
+        let request = request.into_inner();
+        debug!("message_payload(id = {})", request.id);
+        match self.puppet.message_payload(request.id).await {
+            Ok(payload) => Ok(Response::new(payload.into())),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn message_contact(
+        &self,
+        request: Request<MessageContactRequest>,
+    ) -> Result<Response<MessageContactResponse>, Status> {
+        let request = request.into_inner();
+        debug!("message_contact(id = {})", request.id);
+        match self.puppet.message_contact(request.id).await {
+            Ok(id) => Ok(Response::new(MessageContactResponse { id })),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    type MessageFileStreamStream = ResponseStream<MessageFileStreamResponse>;
+
+    async fn message_file_stream(
+        &self,
+        request: Request<MessageFileStreamRequest>,
+    ) -> Result<Response<Self::MessageFileStreamStream>, Status> {
+        let request = request.into_inner();
+        debug!("message_file_stream(id = {})", request.id);
+        match self.puppet.message_file(request.id).await {
+            Ok(filebox) => {
+                let chunks = file_chunks(filebox).into_iter().map(|chunk| MessageFileStreamResponse {
+                    file_box_chunk: Some(chunk),
+                });
+                Ok(Response::new(Box::pin(tokio_stream::iter(chunks.map(Ok)))))
+            }
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    type MessageImageStreamStream = ResponseStream<MessageImageStreamResponse>;
+
+    async fn message_image_stream(
+        &self,
+        request: Request<MessageImageStreamRequest>,
+    ) -> Result<Response<Self::MessageImageStreamStream>, Status> {
+        let request = request.into_inner();
+        debug!("message_image_stream(id = {}, type = {})", request.id, request.r#type);
+        let image_type = ImageType::from_i32(request.r#type).unwrap_or(ImageType::Unknown);
+        match self.puppet.message_image(request.id, image_type).await {
+            Ok(filebox) => {
+                let chunks = file_chunks(filebox)
+                    .into_iter()
+                    .map(|chunk| MessageImageStreamResponse {
+                        file_box_chunk: Some(chunk),
+                    });
+                Ok(Response::new(Box::pin(tokio_stream::iter(chunks.map(Ok)))))
+            }
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn message_mini_program(
+        &self,
+        request: Request<MessageMiniProgramRequest>,
+    ) -> Result<Response<MessageMiniProgramResponse>, Status> {
+        let request = request.into_inner();
+        debug!("message_mini_program(id = {})", request.id);
+        match self.puppet.message_mini_program(request.id).await {
+            Ok(payload) => Ok(Response::new(payload.into())),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn message_url(&self, request: Request<MessageUrlRequest>) -> Result<Response<MessageUrlResponse>, Status> {
+        let request = request.into_inner();
+        debug!("message_url(id = {})", request.id);
+        match self.puppet.message_url(request.id).await {
+            Ok(payload) => Ok(Response::new(payload.into())),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn message_send_contact(
+        &self,
+        request: Request<MessageSendContactRequest>,
+    ) -> Result<Response<MessageSendContactResponse>, Status> {
+        let request = request.into_inner();
+        debug!(
+            "message_send_contact(conversation_id = {}, contact_id = {})",
+            request.conversation_id, request.contact_id
+        );
+        match self
+            .puppet
+            .message_send_contact(request.conversation_id, request.contact_id)
+            .await
+        {
+            Ok(id) => Ok(Response::new(MessageSendContactResponse { id })),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    /// Send the file of a stream, which starts with the conversation id and goes on with the file name and contents.
+    async fn message_send_file_stream(
+        &self,
+        request: Request<Streaming<MessageSendFileStreamRequest>>,
+    ) -> Result<Response<MessageSendFileStreamResponse>, Status> {
+        let mut stream = request.into_inner();
+        let conversation_id = match stream.message().await? {
+            Some(MessageSendFileStreamRequest {
+                payload: Some(message_send_file_stream_request::Payload::ConversationId(conversation_id)),
+            }) => conversation_id,
+            _ => {
+                return Err(Status::invalid_argument(
+                    "File stream must start with the conversation id",
+                ))
+            }
+        };
+        debug!("message_send_file_stream(conversation_id = {})", conversation_id);
+        let mut name = None;
+        let mut data = vec![];
+        while let Some(request) = stream.message().await? {
+            match request.payload {
+                Some(message_send_file_stream_request::Payload::FileBoxChunk(chunk)) => match chunk.payload {
+                    Some(file_box_chunk::Payload::Name(chunk)) if name.is_none() => name = Some(chunk),
+                    Some(file_box_chunk::Payload::Data(chunk)) if name.is_some() => data.extend(chunk),
+                    _ => {
+                        return Err(Status::invalid_argument(
+                            "File stream must carry the file name, then the file contents",
+                        ))
+                    }
+                },
+                _ => {
+                    return Err(Status::invalid_argument(
+                        "File stream has more than one conversation id",
+                    ))
+                }
+            }
+        }
+        let filebox = match name {
+            Some(name) => FileBox::from_buffer(name, data),
+            None => return Err(Status::invalid_argument("File stream has no file name")),
+        };
+        match self.puppet.message_send_file(conversation_id, filebox).await {
+            Ok(id) => Ok(Response::new(MessageSendFileStreamResponse { id })),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn message_send_text(
+        &self,
+        request: Request<MessageSendTextRequest>,
+    ) -> Result<Response<MessageSendTextResponse>, Status> {
+        let request = request.into_inner();
+        debug!(
+            "message_send_text(conversation_id = {}, text = {}, mention_id_list = {:?})",
+            request.conversation_id, request.text, request.mentonal_ids
+        );
+        match self
+            .puppet
+            .message_send_text(request.conversation_id, request.text, request.mentonal_ids)
+            .await
+        {
+            Ok(id) => Ok(Response::new(MessageSendTextResponse { id })),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn message_send_mini_program(
+        &self,
+        request: Request<MessageSendMiniProgramRequest>,
+    ) -> Result<Response<MessageSendMiniProgramResponse>, Status> {
+        let request = request.into_inner();
+        debug!(
+            "message_send_mini_program(conversation_id = {}, mini_program = {})",
+            request.conversation_id, request.mini_program
+        );
+        let payload = match from_str(&request.mini_program) {
+            Ok(payload) => payload,
+            Err(e) => return Err(Status::invalid_argument(format!("Invalid mini program, reason: {}", e))),
+        };
+        match self
+            .puppet
+            .message_send_mini_program(request.conversation_id, payload)
+            .await
+        {
+            Ok(id) => Ok(Response::new(MessageSendMiniProgramResponse { id })),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn message_send_url(
+        &self,
+        request: Request<MessageSendUrlRequest>,
+    ) -> Result<Response<MessageSendUrlResponse>, Status> {
+        let request = request.into_inner();
+        debug!(
+            "message_send_url(conversation_id = {}, url_link = {})",
+            request.conversation_id, request.url_link
+        );
+        let payload = match from_str(&request.url_link) {
+            Ok(payload) => payload,
+            Err(e) => return Err(Status::invalid_argument(format!("Invalid url link, reason: {}", e))),
+        };
+        match self.puppet.message_send_url(request.conversation_id, payload).await {
+            Ok(id) => Ok(Response::new(MessageSendUrlResponse { id })),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn message_recall(
+        &self,
+        request: Request<MessageRecallRequest>,
+    ) -> Result<Response<MessageRecallResponse>, Status> {
+        let request = request.into_inner();
+        debug!("message_recall(id = {})", request.id);
+        match self.puppet.message_recall(request.id).await {
+            Ok(success) => Ok(Response::new(MessageRecallResponse { success })),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn room_payload(
+        &self,
+        request: Request<RoomPayloadRequest>,
+    ) -> Result<Response<RoomPayloadResponse>, Status> {
+        let request = request.into_inner();
+        debug!("room_payload(id = {})", request.id);
+        match self.puppet.room_payload(request.id).await {
+            Ok(payload) => Ok(Response::new(payload.into())),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn room_list(&self, _request: Request<RoomListRequest>) -> Result<Response<RoomListResponse>, Status> {
+        debug!("room_list()");
+        match self.puppet.room_list().await {
+            Ok(ids) => Ok(Response::new(RoomListResponse { ids })),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn room_add(&self, request: Request<RoomAddRequest>) -> Result<Response<RoomAddResponse>, Status> {
+        let request = request.into_inner();
+        debug!("room_add(id = {}, contact_id = {})", request.id, request.contact_id);
+        match self.puppet.room_add(request.id, request.contact_id).await {
+            Ok(()) => Ok(Response::new(RoomAddResponse {})),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn room_avatar(&self, request: Request<RoomAvatarRequest>) -> Result<Response<RoomAvatarResponse>, Status> {
+        let request = request.into_inner();
+        debug!("room_avatar(id = {})", request.id);
+        Err(filebox_string_unsupported("room_avatar"))
+    }
+
+    async fn room_create(&self, request: Request<RoomCreateRequest>) -> Result<Response<RoomCreateResponse>, Status> {
+        let request = request.into_inner();
+        debug!(
+            "room_create(contact_ids = {:?}, topic = {})",
+            request.contact_ids, request.topic
+        );
+        let topic = if request.topic.is_empty() {
+            None
+        } else {
+            Some(request.topic)
+        };
+        match self.puppet.room_create(request.contact_ids, topic).await {
+            Ok(id) => Ok(Response::new(RoomCreateResponse { id })),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn room_del(&self, request: Request<RoomDelRequest>) -> Result<Response<RoomDelResponse>, Status> {
+        let request = request.into_inner();
+        debug!("room_del(id = {}, contact_id = {})", request.id, request.contact_id);
+        match self.puppet.room_del(request.id, request.contact_id).await {
+            Ok(()) => Ok(Response::new(RoomDelResponse {})),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn room_quit(&self, request: Request<RoomQuitRequest>) -> Result<Response<RoomQuitResponse>, Status> {
+        let request = request.into_inner();
+        debug!("room_quit(id = {})", request.id);
+        match self.puppet.room_quit(request.id).await {
+            Ok(()) => Ok(Response::new(RoomQuitResponse {})),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn room_topic(&self, request: Request<RoomTopicRequest>) -> Result<Response<RoomTopicResponse>, Status> {
+        let request = request.into_inner();
+        debug!("room_topic(id = {}, topic = {:?})", request.id, request.topic);
+        match request.topic {
+            Some(topic) => match self.puppet.room_topic_set(request.id, topic).await {
+                Ok(()) => Ok(Response::new(RoomTopicResponse { topic: None })),
+                Err(e) => Err(status(e)),
+            },
+            None => match self.puppet.room_topic(request.id).await {
+                Ok(topic) => Ok(Response::new(RoomTopicResponse { topic: Some(topic) })),
+                Err(e) => Err(status(e)),
+            },
+        }
+    }
+
+    async fn room_qr_code(&self, request: Request<RoomQrCodeRequest>) -> Result<Response<RoomQrCodeResponse>, Status> {
+        let request = request.into_inner();
+        debug!("room_qr_code(id = {})", request.id);
+        match self.puppet.room_qr_code(request.id).await {
+            Ok(qrcode) => Ok(Response::new(RoomQrCodeResponse { qrcode })),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn room_announce(
+        &self,
+        request: Request<RoomAnnounceRequest>,
+    ) -> Result<Response<RoomAnnounceResponse>, Status> {
+        let request = request.into_inner();
+        debug!("room_announce(id = {}, text = {:?})", request.id, request.text);
+        match request.text {
+            Some(text) => match self.puppet.room_announce_set(request.id, text).await {
+                Ok(()) => Ok(Response::new(RoomAnnounceResponse { text: None })),
+                Err(e) => Err(status(e)),
+            },
+            None => match self.puppet.room_announce(request.id).await {
+                Ok(text) => Ok(Response::new(RoomAnnounceResponse { text: Some(text) })),
+                Err(e) => Err(status(e)),
+            },
+        }
+    }
+
+    async fn room_member_payload(
+        &self,
+        request: Request<RoomMemberPayloadRequest>,
+    ) -> Result<Response<RoomMemberPayloadResponse>, Status> {
+        let request = request.into_inner();
+        debug!(
+            "room_member_payload(id = {}, member_id = {})",
+            request.id, request.member_id
+        );
+        match self.puppet.room_member_payload(request.id, request.member_id).await {
+            Ok(payload) => Ok(Response::new(payload.into())),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn room_member_list(
+        &self,
+        request: Request<RoomMemberListRequest>,
+    ) -> Result<Response<RoomMemberListResponse>, Status> {
+        let request = request.into_inner();
+        debug!("room_member_list(id = {})", request.id);
+        match self.puppet.room_member_list(request.id).await {
+            Ok(member_ids) => Ok(Response::new(RoomMemberListResponse { member_ids })),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn room_invitation_payload(
+        &self,
+        request: Request<RoomInvitationPayloadRequest>,
+    ) -> Result<Response<RoomInvitationPayloadResponse>, Status> {
+        let request = request.into_inner();
+        debug!("room_invitation_payload(id = {})", request.id);
+        match self.puppet.room_invitation_payload(request.id).await {
+            Ok(payload) => Ok(Response::new(payload.into())),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn room_invitation_accept(
+        &self,
+        request: Request<RoomInvitationAcceptRequest>,
+    ) -> Result<Response<RoomInvitationAcceptResponse>, Status> {
+        let request = request.into_inner();
+        debug!("room_invitation_accept(id = {})", request.id);
+        match self.puppet.room_invitation_accept(request.id).await {
+            Ok(()) => Ok(Response::new(RoomInvitationAcceptResponse {})),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn tag_contact_add(
+        &self,
+        request: Request<TagContactAddRequest>,
+    ) -> Result<Response<TagContactAddResponse>, Status> {
+        let request = request.into_inner();
+        debug!(
+            "tag_contact_add(id = {}, contact_id = {})",
+            request.id, request.contact_id
+        );
+        match self.puppet.tag_contact_add(request.id, request.contact_id).await {
+            Ok(()) => Ok(Response::new(TagContactAddResponse {})),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn tag_contact_remove(
+        &self,
+        request: Request<TagContactRemoveRequest>,
+    ) -> Result<Response<TagContactRemoveResponse>, Status> {
+        let request = request.into_inner();
+        debug!(
+            "tag_contact_remove(id = {}, contact_id = {})",
+            request.id, request.contact_id
+        );
+        match self.puppet.tag_contact_remove(request.id, request.contact_id).await {
+            Ok(()) => Ok(Response::new(TagContactRemoveResponse {})),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn tag_contact_delete(
+        &self,
+        request: Request<TagContactDeleteRequest>,
+    ) -> Result<Response<TagContactDeleteResponse>, Status> {
+        let request = request.into_inner();
+        debug!("tag_contact_delete(id = {})", request.id);
+        match self.puppet.tag_contact_delete(request.id).await {
+            Ok(()) => Ok(Response::new(TagContactDeleteResponse {})),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    async fn tag_contact_list(
+        &self,
+        request: Request<TagContactListRequest>,
+    ) -> Result<Response<TagContactListResponse>, Status> {
+        let request = request.into_inner();
+        debug!("tag_contact_list(contact_id = {:?})", request.contact_id);
+        let result = match request.contact_id {
+            Some(contact_id) => self.puppet.tag_contact_list(contact_id).await,
+            None => self.puppet.tag_list().await,
+        };
+        match result {
+            Ok(ids) => Ok(Response::new(TagContactListResponse { ids })),
+            Err(e) => Err(status(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::{Channel, Endpoint};
+    use tonic::Code;
+    use wechaty_grpc::puppet_client::PuppetClient;
+    use wechaty_puppet::{EventLogoutPayload, EventMessagePayload, PuppetOptions};
+    use wechaty_puppet_mock::{MessageContent, Mocker, PuppetMock};
+
+    use super::*;
+    use crate::event_payload::decode_event;
+    use crate::puppet_service::authorized_client;
+    use crate::PuppetService;
+
+    const TOKEN: &str = "test-token";
+
+    /// Serve a mock puppet on a local port, and return it with the endpoint.
+    async fn serve() -> (Puppet<PuppetMock>, String) {
+        let puppet = Puppet::new(PuppetMock::new());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let service = PuppetServer::new(puppet.clone()).into_service(Some(TOKEN.to_owned()));
+        tokio::spawn(
+            Server::builder()
+                .add_service(service)
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        (puppet, endpoint)
+    }
+
+    /// Connect a raw client, for the calls `PuppetService` does not make.
+    async fn client(endpoint: &str) -> PuppetClient<Channel> {
+        let channel = Endpoint::from_shared(endpoint.to_owned())
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        authorized_client(channel, Some(TOKEN.to_owned())).unwrap()
+    }
+
+    fn options(endpoint: &str, token: &str) -> PuppetOptions {
+        PuppetOptions {
+            endpoint: Some(endpoint.to_owned()),
+            token: Some(token.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[actix_rt::test]
+    async fn clients_need_the_token() {
+        let (_puppet, endpoint) = serve().await;
+        match PuppetService::new(options(&endpoint, "wrong-token")).await {
+            Err(PuppetError::Network(_)) => {}
+            result => panic!("Expected rejection, got {:?}", result.map(|_| ())),
+        }
+        assert!(PuppetService::new(options(&endpoint, TOKEN)).await.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn events_reach_clients() {
+        let (puppet, endpoint) = serve().await;
+        puppet
+            .self_addr()
+            .send(PuppetEvent::Login(EventLoginPayload {
+                contact_id: "bot".to_owned(),
+            }))
+            .await
+            .unwrap();
+
+        let client = PuppetService::new(options(&endpoint, TOKEN)).await.unwrap();
+        let mut events = client.events(16);
+        puppet
+            .self_addr()
+            .send(PuppetEvent::Message(EventMessagePayload {
+                message_id: "message".to_owned(),
+            }))
+            .await
+            .unwrap();
+
+        let mut received = vec![];
+        while received.len() < 2 {
+            match tokio::time::timeout(Duration::from_secs(5), events.next()).await {
+                Ok(Some(Ok(PuppetEvent::StateChange(_)))) => {}
+                Ok(Some(Ok(event))) => received.push(format!("{:?}", event)),
+                result => panic!("Expected events, got {:?}", result),
+            }
+        }
+        assert!(received[0].starts_with("Login"), "{:?}", received);
+        assert!(received[1].starts_with("Message"), "{:?}", received);
+        assert_eq!(client.self_id(), Some("bot".to_owned()));
+    }
+
+    #[actix_rt::test]
+    async fn files_are_streamed_in_chunks() {
+        let (puppet, endpoint) = serve().await;
+        let mocker = Mocker::new(&puppet);
+        mocker.create_contact("alice", "Alice");
+        mocker.login("bot");
+        let mut client = client(&endpoint).await;
+
+        let file = b"remember the milk";
+        let chunk = |payload| MessageSendFileStreamRequest {
+            payload: Some(message_send_file_stream_request::Payload::FileBoxChunk(FileBoxChunk {
+                payload: Some(payload),
+            })),
+        };
+        let conversation_id = || MessageSendFileStreamRequest {
+            payload: Some(message_send_file_stream_request::Payload::ConversationId(
+                "alice".to_owned(),
+            )),
+        };
+        let requests = vec![
+            conversation_id(),
+            chunk(file_box_chunk::Payload::Name("notes.txt".to_owned())),
+            chunk(file_box_chunk::Payload::Data(file[..8].to_vec())),
+            chunk(file_box_chunk::Payload::Data(file[8..].to_vec())),
+        ];
+        let id = client
+            .message_send_file_stream(tokio_stream::iter(requests))
+            .await
+            .unwrap()
+            .into_inner()
+            .id
+            .unwrap();
+        let expected = FileBox::from_buffer("notes.txt".to_owned(), file.to_vec());
+        assert_eq!(mocker.sent_messages()[0].content, MessageContent::File(expected));
+
+        let mut stream = client
+            .message_file_stream(MessageFileStreamRequest { id: id.clone() })
+            .await
+            .unwrap()
+            .into_inner();
+        let mut chunks = vec![];
+        while let Some(response) = stream.message().await.unwrap() {
+            chunks.push(response.file_box_chunk.and_then(|chunk| chunk.payload).unwrap());
+        }
+        assert_eq!(
+            chunks,
+            vec![
+                file_box_chunk::Payload::Name("notes.txt".to_owned()),
+                file_box_chunk::Payload::Data(file.to_vec()),
+            ]
+        );
+
+        for requests in [
+            vec![chunk(file_box_chunk::Payload::Data(file.to_vec()))],
+            vec![conversation_id(), chunk(file_box_chunk::Payload::Data(file.to_vec()))],
+            vec![conversation_id()],
+        ] {
+            match client.message_send_file_stream(tokio_stream::iter(requests)).await {
+                Err(status) => assert_eq!(status.code(), Code::InvalidArgument),
+                Ok(response) => panic!("Expected rejection, got {:?}", response),
+            }
+        }
+
+        // File boxes have no string form, the calls passing them as strings are refused.
+        match client.message_file(MessageFileRequest { id }).await {
+            Err(status) => assert_eq!(status.code(), Code::Unimplemented),
+            Ok(response) => panic!("Expected rejection, got {:?}", response),
+        }
+        match client.room_avatar(RoomAvatarRequest { id: "room".to_owned() }).await {
+            Err(status) => assert_eq!(status.code(), Code::Unimplemented),
+            Ok(response) => panic!("Expected rejection, got {:?}", response),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn replayed_logins_are_not_forwarded_twice() {
+        let login = |contact_id: &str| {
+            PuppetEvent::Login(EventLoginPayload {
+                contact_id: contact_id.to_owned(),
+            })
+        };
+        let logout = PuppetEvent::Logout(EventLogoutPayload {
+            contact_id: "bot".to_owned(),
+            data: String::new(),
+        });
+        let message = PuppetEvent::Message(EventMessagePayload {
+            message_id: "a".to_owned(),
+        });
+        let events = vec![login("bot"), message.clone(), logout.clone(), login("bot")];
+        let (sender, receiver) = mpsc::channel(EVENT_BUFFER_SIZE);
+        forward_events(
+            tokio_stream::iter(events.into_iter().map(Ok)),
+            sender,
+            Some("bot".to_owned()),
+        )
+        .await;
+
+        // Events are not comparable, their debug forms are.
+        let forwarded: Vec<String> = ReceiverStream::new(receiver)
+            .map(|response| format!("{:?}", decode_event(&response.unwrap()).unwrap().unwrap()))
+            .collect()
+            .await;
+        let expected: Vec<String> = [message, logout, login("bot")]
+            .iter()
+            .map(|event| format!("{:?}", event))
+            .collect();
+        assert_eq!(forwarded, expected);
+    }
+
+    #[actix_rt::test]
+    async fn messages_are_recalled_by_the_puppet() {
+        let (puppet, endpoint) = serve().await;
+        let mocker = Mocker::new(&puppet);
+        mocker.create_contact("alice", "Alice");
+        mocker.login("bot");
+        let client = client(&endpoint).await;
+
+        let sent_id = puppet
+            .message_send_text("alice".to_owned(), "oops".to_owned(), vec![])
+            .await
+            .unwrap()
+            .unwrap();
+        let received_id = mocker.receive_text("alice", "hi");
+        let recall = |id: String| {
+            let mut client = client.clone();
+            async move { client.message_recall(MessageRecallRequest { id }).await }
+        };
+        assert!(!recall(received_id).await.unwrap().into_inner().success);
+        assert!(recall(sent_id).await.unwrap().into_inner().success);
+        assert!(mocker.sent_messages().is_empty());
+        match recall("unknown".to_owned()).await {
+            Err(status) => assert_eq!(status.code(), Code::Internal),
+            Ok(response) => panic!("Expected an error, got {:?}", response),
+        }
+    }
+}
@@ -1,34 +1,109 @@
-use actix::{Actor, Addr, AsyncContext, Context, Handler, Message, Recipient, StreamHandler};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use actix::{
+    Actor, ActorFutureExt, Addr, AsyncContext, Context, Handler, Message, Recipient, SpawnHandle, StreamHandler,
+    WrapFuture,
+};
 use async_trait::async_trait;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
+use lru::LruCache;
 use num_traits::cast::ToPrimitive;
-use serde::{Deserialize, Serialize};
-use serde_json::{from_str, to_string};
-use tonic::{transport::Channel, Status, Streaming};
+use serde_json::to_string;
+use tonic::metadata::MetadataValue;
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Request, Status, Streaming};
 use wechaty_grpc::puppet::*;
 use wechaty_grpc::puppet_client::PuppetClient;
+use wechaty_puppet::ImageType;
 use wechaty_puppet::*;
-use wechaty_puppet::{ImageType, PayloadType};
 
-use crate::from_payload_response::FromPayloadResponse;
+use crate::event_payload::decode_event;
 use crate::service_endpoint::discover;
+use crate::tls::client_tls_config;
+
+const RECONNECT_BASE_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+const RECENT_EVENTS_CAPACITY: usize = 256;
+/// How long after resubscribing events already delivered are treated as the replay of the host.
+const REPLAY_WINDOW: Duration = Duration::from_secs(10);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// File transfers and room creation get this many times the default deadline, unless overridden.
+const SLOW_METHOD_TIMEOUT_FACTOR: u32 = 5;
+const SLOW_METHODS: &[&str] = &[
+    "contact_avatar",
+    "contact_avatar_set",
+    "message_file",
+    "message_image",
+    "message_send_file",
+    "room_avatar",
+    "room_create",
+    "room_qr_code",
+];
 
 #[derive(Clone)]
 pub struct PuppetService {
     client_: PuppetClient<Channel>,
     addr: Addr<PuppetServiceInner>,
+    reconnect_count: Arc<AtomicUsize>,
+    timeouts: Arc<CallTimeouts>,
+}
+
+/// Deadlines of the calls to the puppet service.
+struct CallTimeouts {
+    default: Duration,
+    overrides: HashMap<String, Duration>,
+}
+
+impl CallTimeouts {
+    fn new(options: &PuppetOptions) -> Self {
+        Self {
+            default: options.timeout.map_or(DEFAULT_TIMEOUT, Duration::from_secs),
+            overrides: options
+                .method_timeouts
+                .iter()
+                .map(|(method, timeout)| (method.clone(), Duration::from_secs(*timeout)))
+                .collect(),
+        }
+    }
+
+    fn get(&self, method: &str) -> Duration {
+        match self.overrides.get(method) {
+            Some(timeout) => *timeout,
+            None if SLOW_METHODS.contains(&method) => self.default * SLOW_METHOD_TIMEOUT_FACTOR,
+            None => self.default,
+        }
+    }
+}
+
+/// Await the call, failing with `PuppetError::Timeout` once the deadline has passed.
+async fn with_deadline<F, R>(method: &str, deadline: Duration, call: F) -> Result<R, PuppetError>
+where
+    F: Future<Output = R>,
+{
+    match tokio::time::timeout(deadline, call).await {
+        Ok(result) => Ok(result),
+        Err(_) => Err(PuppetError::Timeout(format!("{} after {:?}", method, deadline))),
+    }
 }
 
 impl PuppetService {
     /// Create puppet instance from puppet options.
     ///
     /// First use endpoint, if endpoint is not given, try token instead.
+    ///
+    /// The token, if given, is sent as `authorization: Wechaty <token>` metadata with every call.
     pub async fn new(options: PuppetOptions) -> Result<Puppet<Self>, PuppetError> {
-        let endpoint = if let Some(endpoint) = options.endpoint {
+        let endpoint = if let Some(endpoint) = options.endpoint.clone() {
             endpoint
         } else {
-            if let Some(token) = options.token {
-                match discover(token).await {
+            if let Some(token) = options.token.clone() {
+                match discover(token, &options).await {
                     Ok(endpoint) => endpoint,
                     Err(e) => return Err(e),
                 }
@@ -37,17 +112,26 @@ impl PuppetService {
             }
         };
 
-        match PuppetClient::connect(endpoint.clone()).await {
-            Ok(mut client) => {
+        let connect_timeout = options
+            .connect_timeout
+            .map_or(DEFAULT_CONNECT_TIMEOUT, Duration::from_secs);
+        match connect(&endpoint, options.tls.as_ref(), connect_timeout).await {
+            Ok(channel) => {
                 info!("Connected to endpoint {}", endpoint);
-                let response = client.event(EventRequest {}).await;
+                let timeouts = Arc::new(CallTimeouts::new(&options));
+                let mut client = authorized_client(channel, options.token)?;
+                let response = with_deadline("event", connect_timeout, client.event(EventRequest {})).await?;
                 match response {
                     Ok(response) => {
                         info!("Subscribed to event stream");
-                        let addr = PuppetServiceInner::new().start();
+                        let reconnect_count = Arc::new(AtomicUsize::new(0));
+                        let addr =
+                            PuppetServiceInner::new(client.clone(), reconnect_count.clone(), connect_timeout).start();
                         let puppet_service = Self {
                             client_: client,
                             addr: addr.clone(),
+                            reconnect_count,
+                            timeouts,
                         };
                         let puppet = Puppet::new(puppet_service);
                         let callback_addr = puppet.self_addr();
@@ -61,16 +145,77 @@ impl PuppetService {
                     ))),
                 }
             }
-            Err(e) => Err(PuppetError::Network(format!(
-                "Failed to establish RPC connection, reason: {}",
-                e
-            ))),
+            Err(e) => Err(e),
         }
     }
 
     fn client(&self) -> PuppetClient<Channel> {
         self.client_.clone()
     }
+
+    /// Await a call to the puppet service with the deadline of the method.
+    async fn timed<F, R>(&self, method: &str, call: F) -> Result<R, PuppetError>
+    where
+        F: Future<Output = R>,
+    {
+        with_deadline(method, self.timeouts.get(method), call).await
+    }
+
+    /// Get how many times the event stream has been re-established after it was lost.
+    pub fn reconnect_count(&self) -> usize {
+        self.reconnect_count.load(Ordering::SeqCst)
+    }
+}
+
+/// Open a channel to the endpoint, over TLS if TLS options are given.
+async fn connect(
+    endpoint: &str,
+    tls: Option<&PuppetTlsOptions>,
+    connect_timeout: Duration,
+) -> Result<Channel, PuppetError> {
+    let mut builder = match Endpoint::from_shared(endpoint.to_owned()) {
+        Ok(builder) => builder,
+        Err(e) => {
+            return Err(PuppetError::Network(format!(
+                "Invalid endpoint {}, reason: {}",
+                endpoint, e
+            )))
+        }
+    };
+    if let Some(tls) = tls {
+        let host = builder.uri().host().unwrap_or_default().to_owned();
+        builder = match builder.tls_config(client_tls_config(tls, &host)?) {
+            Ok(builder) => builder,
+            Err(e) => return Err(PuppetError::Network(format!("Invalid TLS config, reason: {}", e))),
+        };
+    }
+    match with_deadline("connect", connect_timeout, builder.connect()).await? {
+        Ok(channel) => Ok(channel),
+        Err(e) => Err(PuppetError::Network(format!(
+            "Failed to establish RPC connection, reason: {}",
+            e
+        ))),
+    }
+}
+
+/// Create a client on the channel, sending the token with every call.
+pub(crate) fn authorized_client(channel: Channel, token: Option<String>) -> Result<PuppetClient<Channel>, PuppetError> {
+    match token {
+        Some(token) => {
+            let authorization = match MetadataValue::from_str(&format!("Wechaty {}", token)) {
+                Ok(authorization) => authorization,
+                Err(_) => return Err(PuppetError::InvalidToken),
+            };
+            // Interceptors have to fail with `tonic::Status`.
+            #[allow(clippy::result_large_err)]
+            let interceptor = move |mut request: Request<()>| {
+                request.metadata_mut().insert("authorization", authorization.clone());
+                Ok(request)
+            };
+            Ok(PuppetClient::with_interceptor(channel, interceptor))
+        }
+        None => Ok(PuppetClient::new(channel)),
+    }
 }
 
 #[derive(Message)]
@@ -78,24 +223,154 @@ impl PuppetService {
 enum PuppetServiceInternalMessage {
     SetupCallback(Recipient<PuppetEvent>),
     SetupStream(Streaming<EventResponse>),
+    /// Resubscribe to the event stream if it was dropped by `Stop`.
+    Start,
+    /// Drop the event stream and stop reconnecting.
+    Stop,
 }
 
-#[derive(Clone, Debug)]
 struct PuppetServiceInner {
+    client: PuppetClient<Channel>,
     callback_addr: Option<Recipient<PuppetEvent>>,
+    stream_handle: Option<SpawnHandle>,
+    /// The pending reconnect delay or resubscription, at most one at a time.
+    reconnect_handle: Option<SpawnHandle>,
+    reconnect_attempts: u32,
+    reconnect_count: Arc<AtomicUsize>,
+    subscribe_timeout: Duration,
+    stopped: bool,
+    recent_events: LruCache<(i32, String), ()>,
+    last_session_event: Option<(i32, String)>,
+    /// Until when events already delivered are skipped, set when resubscribing.
+    replay_deadline: Option<Instant>,
+}
+
+/// Delay before the given reconnect attempt, doubling from `RECONNECT_BASE_DELAY` up to `RECONNECT_MAX_DELAY`.
+fn reconnect_delay(attempt: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+    RECONNECT_BASE_DELAY
+        .checked_mul(factor)
+        .map_or(RECONNECT_MAX_DELAY, |delay| delay.min(RECONNECT_MAX_DELAY))
 }
 
 impl PuppetServiceInner {
-    fn new() -> Self {
-        Self { callback_addr: None }
+    fn new(client: PuppetClient<Channel>, reconnect_count: Arc<AtomicUsize>, subscribe_timeout: Duration) -> Self {
+        Self {
+            client,
+            callback_addr: None,
+            stream_handle: None,
+            reconnect_handle: None,
+            reconnect_attempts: 0,
+            reconnect_count,
+            subscribe_timeout,
+            stopped: false,
+            recent_events: LruCache::new(RECENT_EVENTS_CAPACITY),
+            last_session_event: None,
+            replay_deadline: None,
+        }
     }
 
-    fn emit(&self, msg: PuppetEvent) {
-        match self.callback_addr.as_ref().unwrap().do_send(msg) {
-            Err(e) => {
-                error!("Internal error: {}", e)
+    /// Drop the current event stream and subscribe again after a backoff delay.
+    fn reconnect(&mut self, ctx: &mut Context<Self>) {
+        self.cancel_stream(ctx);
+        self.cancel_reconnect(ctx);
+        if self.stopped {
+            info!("Event stream closed after stop");
+            return;
+        }
+        self.reconnect_attempts += 1;
+        let delay = reconnect_delay(self.reconnect_attempts);
+        warn!(
+            "Event stream lost, reconnecting in {:?} (attempt {})",
+            delay, self.reconnect_attempts
+        );
+        self.emit(PuppetEvent::Reset(EventResetPayload {
+            data: format!("Event stream lost, reconnect attempt {}", self.reconnect_attempts),
+        }));
+        self.reconnect_handle = Some(ctx.run_later(delay, |this, ctx| {
+            this.reconnect_handle = None;
+            this.resubscribe(ctx);
+        }));
+    }
+
+    fn cancel_stream(&mut self, ctx: &mut Context<Self>) {
+        if let Some(handle) = self.stream_handle.take() {
+            ctx.cancel_future(handle);
+        }
+    }
+
+    fn cancel_reconnect(&mut self, ctx: &mut Context<Self>) {
+        if let Some(handle) = self.reconnect_handle.take() {
+            ctx.cancel_future(handle);
+        }
+    }
+
+    /// Subscribe to the event stream again, skipping the replayed events for a while.
+    fn resubscribe(&mut self, ctx: &mut Context<Self>) {
+        if self.stopped {
+            return;
+        }
+        self.cancel_reconnect(ctx);
+        let mut client = self.client.clone();
+        let subscribe_timeout = self.subscribe_timeout;
+        let subscription =
+            async move { with_deadline("event", subscribe_timeout, client.event(EventRequest {})).await };
+        let handle = ctx.spawn(subscription.into_actor(self).map(|response, this, ctx| {
+            this.reconnect_handle = None;
+            let response = match response {
+                Ok(Ok(response)) => Ok(response),
+                Ok(Err(e)) => Err(e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            match response {
+                Ok(_) if this.stopped => info!("Drop event stream resubscribed after stop"),
+                Ok(response) => {
+                    info!("Resubscribed to event stream");
+                    this.reconnect_count.fetch_add(1, Ordering::SeqCst);
+                    this.replay_deadline = Some(Instant::now() + REPLAY_WINDOW);
+                    this.cancel_stream(ctx);
+                    this.stream_handle = Some(ctx.add_stream(response.into_inner()));
+                }
+                Err(e) => {
+                    this.emit(PuppetEvent::Error(EventErrorPayload {
+                        data: format!("Failed to re-establish event stream, reason: {}", e),
+                    }));
+                    this.reconnect(ctx);
+                }
+            }
+        }));
+        self.reconnect_handle = Some(handle);
+    }
+
+    /// Check whether the event is the replay of one already delivered.
+    ///
+    /// After resubscribing, the host replays part of its state (login, messages, ...), which must not reach
+    /// the handlers twice. Events are only skipped within `REPLAY_WINDOW` of resubscribing, and events
+    /// that legitimately repeat, such as heartbeats, dirty notices, scans and raw events, never are.
+    fn is_duplicate(&mut self, response: &EventResponse) -> bool {
+        let replaying = matches!(self.replay_deadline, Some(deadline) if Instant::now() < deadline);
+        let key = (response.r#type, response.payload.clone());
+        match EventType::from_i32(response.r#type) {
+            Some(EventType::Heartbeat)
+            | Some(EventType::Dong)
+            | Some(EventType::Error)
+            | Some(EventType::Reset)
+            | Some(EventType::Dirty)
+            | Some(EventType::Ready)
+            | Some(EventType::Scan)
+            | None => false,
+            Some(EventType::Login) | Some(EventType::Logout) => {
+                let duplicate = replaying && self.last_session_event.as_ref() == Some(&key);
+                self.last_session_event = Some(key);
+                duplicate
             }
-            Ok(_) => {}
+            _ => self.recent_events.put(key, ()).is_some() && replaying,
+        }
+    }
+
+    fn emit(&self, msg: PuppetEvent) {
+        if let Err(e) = self.callback_addr.as_ref().unwrap().do_send(msg) {
+            error!("Internal error: {}", e)
         }
     }
 }
@@ -121,236 +396,56 @@ impl Handler<PuppetServiceInternalMessage> for PuppetServiceInner {
                 self.callback_addr = Some(callback_addr);
             }
             PuppetServiceInternalMessage::SetupStream(stream) => {
-                ctx.add_stream(stream);
+                self.stream_handle = Some(ctx.add_stream(stream));
+            }
+            PuppetServiceInternalMessage::Start => {
+                if self.stopped {
+                    self.stopped = false;
+                    self.reconnect_attempts = 0;
+                    self.resubscribe(ctx);
+                }
+            }
+            PuppetServiceInternalMessage::Stop => {
+                self.stopped = true;
+                self.cancel_stream(ctx);
+                self.cancel_reconnect(ctx);
             }
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "camelCase")]
-struct EventPayload {
-    pub data: Option<String>,
-    pub contact_id: Option<String>,
-    pub message_id: Option<String>,
-    pub room_invitation_id: Option<String>,
-    pub friendship_id: Option<String>,
-    pub qrcode: Option<String>,
-    pub status: Option<ScanStatus>,
-    pub timestamp: Option<u64>,
-    pub changer_id: Option<String>,
-    pub new_topic: Option<String>,
-    pub old_topic: Option<String>,
-    pub room_id: Option<String>,
-    pub removee_id_list: Option<Vec<String>>,
-    pub remover_id: Option<String>,
-    pub invitee_id_list: Option<Vec<String>>,
-    pub inviter_id: Option<String>,
-    pub payload_type: Option<PayloadType>,
-    pub payload_id: Option<String>,
-}
-
 impl StreamHandler<Result<EventResponse, Status>> for PuppetServiceInner {
-    fn handle(&mut self, item: Result<EventResponse, Status>, _ctx: &mut Self::Context) {
+    fn handle(&mut self, item: Result<EventResponse, Status>, ctx: &mut Self::Context) {
         match item {
             Ok(response) => {
-                let payload: EventPayload = from_str(&response.payload).unwrap();
+                self.reconnect_attempts = 0;
+                if self.is_duplicate(&response) {
+                    debug!("Skip duplicate event response, {:?}", response);
+                    return;
+                }
                 info!("Receive event response, {:?}", response);
-
-                match response.r#type {
-                    0 => {
-                        // Unspecified
-                    }
-                    1 => {
-                        // Heartbeat
-                        if payload.data == None {
-                            error!("Heartbeat payload should have data");
-                        } else {
-                            self.emit(PuppetEvent::Heartbeat(EventHeartbeatPayload {
-                                data: payload.data.unwrap(),
-                            }));
-                        }
-                    }
-                    2 => {
-                        // Message
-                        if payload.message_id == None {
-                            error!("Message payload should have message id");
-                        } else {
-                            self.emit(PuppetEvent::Message(EventMessagePayload {
-                                message_id: payload.message_id.unwrap(),
-                            }));
-                        }
-                    }
-                    3 => {
-                        // Dong
-                        if payload.data == None {
-                            error!("Dong payload should have data");
-                        } else {
-                            self.emit(PuppetEvent::Dong(EventDongPayload {
-                                data: payload.data.unwrap(),
-                            }));
-                        }
-                    }
-                    16 => {
-                        // Error
-                        if payload.data == None {
-                            error!("Error payload should have data");
-                        } else {
-                            self.emit(PuppetEvent::Error(EventErrorPayload {
-                                data: payload.data.unwrap(),
-                            }));
-                        }
-                    }
-                    17 => {
-                        // Friendship
-                        if payload.friendship_id == None {
-                            error!("Friendship payload should have friendship id");
-                        } else {
-                            self.emit(PuppetEvent::Friendship(EventFriendshipPayload {
-                                friendship_id: payload.friendship_id.unwrap(),
-                            }));
-                        }
-                    }
-                    18 => {
-                        // Room invite
-                        if payload.room_invitation_id == None {
-                            error!("Room invite payload should have room invitation id");
-                        } else {
-                            self.emit(PuppetEvent::RoomInvite(EventRoomInvitePayload {
-                                room_invitation_id: payload.room_invitation_id.unwrap(),
-                            }));
-                        }
-                    }
-                    19 => {
-                        // Room join
-                        if payload.room_id == None
-                            || payload.invitee_id_list == None
-                            || payload.inviter_id == None
-                            || payload.timestamp == None
-                        {
-                            error!("Room join payload should have room id, inviter id, invitee id list and timestamp");
-                        } else {
-                            self.emit(PuppetEvent::RoomJoin(EventRoomJoinPayload {
-                                room_id: payload.room_id.unwrap(),
-                                inviter_id: payload.inviter_id.unwrap(),
-                                invitee_id_list: payload.invitee_id_list.unwrap(),
-                                timestamp: payload.timestamp.unwrap(),
-                            }));
-                        }
-                    }
-                    20 => {
-                        // Room leave
-                        if payload.room_id == None
-                            || payload.removee_id_list == None
-                            || payload.remover_id == None
-                            || payload.timestamp == None
-                        {
-                            error!("Room leave payload should have room id, remover id, removee id list and timestamp");
-                        } else {
-                            self.emit(PuppetEvent::RoomLeave(EventRoomLeavePayload {
-                                room_id: payload.room_id.unwrap(),
-                                remover_id: payload.remover_id.unwrap(),
-                                removee_id_list: payload.removee_id_list.unwrap(),
-                                timestamp: payload.timestamp.unwrap(),
-                            }));
-                        }
-                    }
-                    21 => {
-                        // Room topic
-                        if payload.room_id == None
-                            || payload.changer_id == None
-                            || payload.old_topic == None
-                            || payload.new_topic == None
-                            || payload.timestamp == None
-                        {
-                            error!("Room topic payload should have room id, changer id, old topic, new topic and timestamp");
-                        } else {
-                            self.emit(PuppetEvent::RoomTopic(EventRoomTopicPayload {
-                                room_id: payload.room_id.unwrap(),
-                                changer_id: payload.changer_id.unwrap(),
-                                old_topic: payload.old_topic.unwrap(),
-                                new_topic: payload.new_topic.unwrap(),
-                                timestamp: payload.timestamp.unwrap(),
-                            }));
-                        }
-                    }
-                    22 => {
-                        // Scan
-                        if payload.status == None {
-                            error!("Scan payload should have scan status");
-                        } else {
-                            self.emit(PuppetEvent::Scan(EventScanPayload {
-                                status: payload.status.unwrap(),
-                                qrcode: payload.qrcode,
-                                data: payload.data,
-                            }));
-                        }
-                    }
-                    23 => {
-                        // Ready
-                        if payload.data == None {
-                            error!("Ready payload should have data");
-                        } else {
-                            self.emit(PuppetEvent::Ready(EventReadyPayload {
-                                data: payload.data.unwrap(),
-                            }));
-                        }
-                    }
-                    24 => {
-                        // Reset
-                        if payload.data == None {
-                            error!("Reset payload should have data");
-                        } else {
-                            self.emit(PuppetEvent::Reset(EventResetPayload {
-                                data: payload.data.unwrap(),
-                            }));
-                        }
-                    }
-                    25 => {
-                        // Log in
-                        if payload.contact_id == None {
-                            error!("Login payload should have contact id");
-                        } else {
-                            self.emit(PuppetEvent::Login(EventLoginPayload {
-                                contact_id: payload.contact_id.unwrap(),
-                            }));
-                        }
-                    }
-                    26 => {
-                        // Log out
-                        if payload.contact_id == None || payload.data == None {
-                            error!("Logout payload should have contact id and data");
-                        } else {
-                            self.emit(PuppetEvent::Logout(EventLogoutPayload {
-                                contact_id: payload.contact_id.unwrap(),
-                                data: payload.data.unwrap(),
-                            }));
-                        }
-                    }
-                    27 => {
-                        // Dirty
-                        if payload.payload_type == None || payload.payload_id == None {
-                            error!("Dirty payload should have payload type and payload id");
-                        } else {
-                            self.emit(PuppetEvent::Dirty(EventDirtyPayload {
-                                payload_type: payload.payload_type.unwrap(),
-                                payload_id: payload.payload_id.unwrap(),
-                            }));
-                        }
-                    }
-                    _ => {
-                        error!("Invalid event type: {}", response.r#type);
+                match decode_event(&response) {
+                    Ok(Some(event)) => self.emit(event),
+                    Ok(None) => {}
+                    Err(reason) => {
+                        error!("Failed to decode event response, reason: {}", reason);
+                        self.emit(PuppetEvent::Error(EventErrorPayload { data: reason }));
                     }
                 }
             }
             Err(e) => {
                 error!("Network error: {}", e);
+                self.emit(PuppetEvent::Error(EventErrorPayload {
+                    data: format!("Event stream error: {}", e),
+                }));
+                self.reconnect(ctx);
             }
         }
     }
 
-    fn finished(&mut self, _ctx: &mut Self::Context) {
+    fn finished(&mut self, ctx: &mut Self::Context) {
         info!("Stream finished");
+        self.reconnect(ctx);
     }
 }
 
@@ -358,7 +453,13 @@ impl StreamHandler<Result<EventResponse, Status>> for PuppetServiceInner {
 impl PuppetImpl for PuppetService {
     async fn contact_self_name_set(&self, name: String) -> Result<(), PuppetError> {
         debug!("contact_self_name_set(name = {})", name);
-        match self.client().contact_self_name(ContactSelfNameRequest { name }).await {
+        match self
+            .timed(
+                "contact_self_name_set",
+                self.client().contact_self_name(ContactSelfNameRequest { name }),
+            )
+            .await?
+        {
             Ok(_) => Ok(()),
             Err(_) => Err(PuppetError::Network("Failed to set contact self name".to_owned())),
         }
@@ -366,7 +467,13 @@ impl PuppetImpl for PuppetService {
 
     async fn contact_self_qr_code(&self) -> Result<String, PuppetError> {
         debug!("contact_self_qr_code()");
-        match self.client().contact_self_qr_code(ContactSelfQrCodeRequest {}).await {
+        match self
+            .timed(
+                "contact_self_qr_code",
+                self.client().contact_self_qr_code(ContactSelfQrCodeRequest {}),
+            )
+            .await?
+        {
             Ok(response) => Ok(response.into_inner().qrcode),
             Err(_) => Err(PuppetError::Network("Failed to get contact self qrcode".to_owned())),
         }
@@ -375,9 +482,12 @@ impl PuppetImpl for PuppetService {
     async fn contact_self_signature_set(&self, signature: String) -> Result<(), PuppetError> {
         debug!("contact_self_signature_set(signature = {})", signature);
         match self
-            .client()
-            .contact_self_signature(ContactSelfSignatureRequest { signature })
-            .await
+            .timed(
+                "contact_self_signature_set",
+                self.client()
+                    .contact_self_signature(ContactSelfSignatureRequest { signature }),
+            )
+            .await?
         {
             Ok(_) => Ok(()),
             Err(_) => Err(PuppetError::Network("Failed to set contact self signature".to_owned())),
@@ -387,12 +497,14 @@ impl PuppetImpl for PuppetService {
     async fn tag_contact_add(&self, tag_id: String, contact_id: String) -> Result<(), PuppetError> {
         debug!("tag_contact_add(tag_id = {}, contact_id = {})", tag_id, contact_id);
         match self
-            .client()
-            .tag_contact_add(TagContactAddRequest {
-                id: tag_id.clone(),
-                contact_id: contact_id.clone(),
-            })
-            .await
+            .timed(
+                "tag_contact_add",
+                self.client().tag_contact_add(TagContactAddRequest {
+                    id: tag_id.clone(),
+                    contact_id: contact_id.clone(),
+                }),
+            )
+            .await?
         {
             Ok(_) => Ok(()),
             Err(_) => Err(PuppetError::Network(format!(
@@ -405,12 +517,14 @@ impl PuppetImpl for PuppetService {
     async fn tag_contact_remove(&self, tag_id: String, contact_id: String) -> Result<(), PuppetError> {
         debug!("tag_contact_remove(tag_id = {}, contact_id = {})", tag_id, contact_id);
         match self
-            .client()
-            .tag_contact_remove(TagContactRemoveRequest {
-                id: tag_id.clone(),
-                contact_id: contact_id.clone(),
-            })
-            .await
+            .timed(
+                "tag_contact_remove",
+                self.client().tag_contact_remove(TagContactRemoveRequest {
+                    id: tag_id.clone(),
+                    contact_id: contact_id.clone(),
+                }),
+            )
+            .await?
         {
             Ok(_) => Ok(()),
             Err(_) => Err(PuppetError::Network(format!(
@@ -423,9 +537,12 @@ impl PuppetImpl for PuppetService {
     async fn tag_contact_delete(&self, tag_id: String) -> Result<(), PuppetError> {
         debug!("tag_contact_delete(tag_id = {})", tag_id);
         match self
-            .client()
-            .tag_contact_delete(TagContactDeleteRequest { id: tag_id.clone() })
-            .await
+            .timed(
+                "tag_contact_delete",
+                self.client()
+                    .tag_contact_delete(TagContactDeleteRequest { id: tag_id.clone() }),
+            )
+            .await?
         {
             Ok(_) => Ok(()),
             Err(_) => Err(PuppetError::Network(format!("Failed to remove tag {}", tag_id))),
@@ -435,11 +552,13 @@ impl PuppetImpl for PuppetService {
     async fn tag_contact_list(&self, contact_id: String) -> Result<Vec<String>, PuppetError> {
         debug!("tag_contact_list(contact_id = {})", contact_id);
         match self
-            .client()
-            .tag_contact_list(TagContactListRequest {
-                contact_id: Some(contact_id.clone()),
-            })
-            .await
+            .timed(
+                "tag_contact_list",
+                self.client().tag_contact_list(TagContactListRequest {
+                    contact_id: Some(contact_id.clone()),
+                }),
+            )
+            .await?
         {
             Ok(response) => Ok(response.into_inner().ids),
             Err(_) => Err(PuppetError::Network(format!(
@@ -452,26 +571,31 @@ impl PuppetImpl for PuppetService {
     async fn tag_list(&self) -> Result<Vec<String>, PuppetError> {
         debug!("tag_list()");
         match self
-            .client()
-            .tag_contact_list(TagContactListRequest { contact_id: None })
-            .await
+            .timed(
+                "tag_list",
+                self.client()
+                    .tag_contact_list(TagContactListRequest { contact_id: None }),
+            )
+            .await?
         {
             Ok(response) => Ok(response.into_inner().ids),
-            Err(_) => Err(PuppetError::Network(format!("Failed to get tags"))),
+            Err(_) => Err(PuppetError::Network("Failed to get tags".to_owned())),
         }
     }
 
     async fn contact_alias(&self, contact_id: String) -> Result<String, PuppetError> {
         debug!("contact_alias(contact_id = {})", contact_id);
         match self
-            .client()
-            .contact_alias(ContactAliasRequest {
-                id: contact_id.clone(),
-                alias: None,
-            })
-            .await
+            .timed(
+                "contact_alias",
+                self.client().contact_alias(ContactAliasRequest {
+                    id: contact_id.clone(),
+                    alias: None,
+                }),
+            )
+            .await?
         {
-            Ok(response) => Ok(response.into_inner().alias.unwrap()),
+            Ok(response) => Ok(response.into_inner().alias.unwrap_or_default()),
             Err(_) => Err(PuppetError::Network(format!(
                 "Failed to get alias of contact {}",
                 contact_id
@@ -482,12 +606,14 @@ impl PuppetImpl for PuppetService {
     async fn contact_alias_set(&self, contact_id: String, alias: String) -> Result<(), PuppetError> {
         debug!("contact_alias_set(contact_id = {}, alias = {})", contact_id, alias);
         match self
-            .client()
-            .contact_alias(ContactAliasRequest {
-                id: contact_id.clone(),
-                alias: Some(alias.clone()),
-            })
-            .await
+            .timed(
+                "contact_alias_set",
+                self.client().contact_alias(ContactAliasRequest {
+                    id: contact_id.clone(),
+                    alias: Some(alias.clone()),
+                }),
+            )
+            .await?
         {
             Ok(_) => Ok(()),
             Err(_) => Err(PuppetError::Network(format!(
@@ -500,14 +626,22 @@ impl PuppetImpl for PuppetService {
     async fn contact_avatar(&self, contact_id: String) -> Result<FileBox, PuppetError> {
         debug!("contact_avatar(contact_id = {})", contact_id);
         match self
-            .client()
-            .contact_avatar(ContactAvatarRequest {
-                id: contact_id.clone(),
-                filebox: None,
-            })
-            .await
+            .timed(
+                "contact_avatar",
+                self.client().contact_avatar(ContactAvatarRequest {
+                    id: contact_id.clone(),
+                    filebox: None,
+                }),
+            )
+            .await?
         {
-            Ok(response) => Ok(FileBox::from(response.into_inner().filebox.unwrap())),
+            Ok(response) => match response.into_inner().filebox {
+                Some(filebox) => Ok(FileBox::from(filebox)),
+                None => Err(PuppetError::InvalidPayload(format!(
+                    "Avatar of contact {} should have a file box",
+                    contact_id
+                ))),
+            },
             Err(_) => Err(PuppetError::Network(format!(
                 "Failed to get avatar of contact {}",
                 contact_id
@@ -518,12 +652,14 @@ impl PuppetImpl for PuppetService {
     async fn contact_avatar_set(&self, contact_id: String, file: FileBox) -> Result<(), PuppetError> {
         debug!("contact_avatar_set(contact_id = {}, file = {})", contact_id, file);
         match self
-            .client()
-            .contact_avatar(ContactAvatarRequest {
-                id: contact_id.clone(),
-                filebox: Some(file.to_string()),
-            })
-            .await
+            .timed(
+                "contact_avatar_set",
+                self.client().contact_avatar(ContactAvatarRequest {
+                    id: contact_id.clone(),
+                    filebox: Some(file.to_string()),
+                }),
+            )
+            .await?
         {
             Ok(_) => Ok(()),
             Err(_) => Err(PuppetError::Network(format!(
@@ -539,12 +675,14 @@ impl PuppetImpl for PuppetService {
             contact_id, phone_list
         );
         match self
-            .client()
-            .contact_phone(ContactPhoneRequest {
-                contact_id: contact_id.clone(),
-                phone_list,
-            })
-            .await
+            .timed(
+                "contact_phone_set",
+                self.client().contact_phone(ContactPhoneRequest {
+                    contact_id: contact_id.clone(),
+                    phone_list,
+                }),
+            )
+            .await?
         {
             Ok(_) => Ok(()),
             Err(_) => Err(PuppetError::Network(format!(
@@ -564,12 +702,15 @@ impl PuppetImpl for PuppetService {
             contact_id, corporation_remark
         );
         match self
-            .client()
-            .contact_corporation_remark(ContactCorporationRemarkRequest {
-                contact_id: contact_id.clone(),
-                corporation_remark,
-            })
-            .await
+            .timed(
+                "contact_corporation_remark_set",
+                self.client()
+                    .contact_corporation_remark(ContactCorporationRemarkRequest {
+                        contact_id: contact_id.clone(),
+                        corporation_remark,
+                    }),
+            )
+            .await?
         {
             Ok(_) => Ok(()),
             Err(_) => Err(PuppetError::Network(format!(
@@ -589,12 +730,14 @@ impl PuppetImpl for PuppetService {
             contact_id, description
         );
         match self
-            .client()
-            .contact_description(ContactDescriptionRequest {
-                contact_id: contact_id.clone(),
-                description,
-            })
-            .await
+            .timed(
+                "contact_description_set",
+                self.client().contact_description(ContactDescriptionRequest {
+                    contact_id: contact_id.clone(),
+                    description,
+                }),
+            )
+            .await?
         {
             Ok(_) => Ok(()),
             Err(_) => Err(PuppetError::Network(format!(
@@ -606,20 +749,26 @@ impl PuppetImpl for PuppetService {
 
     async fn contact_list(&self) -> Result<Vec<String>, PuppetError> {
         debug!("contact_list()");
-        match self.client().contact_list(ContactListRequest {}).await {
+        match self
+            .timed("contact_list", self.client().contact_list(ContactListRequest {}))
+            .await?
+        {
             Ok(response) => Ok(response.into_inner().ids),
-            Err(_) => Err(PuppetError::Network(format!("Failed to get contacts"))),
+            Err(_) => Err(PuppetError::Network("Failed to get contacts".to_owned())),
         }
     }
 
     async fn contact_raw_payload(&self, contact_id: String) -> Result<ContactPayload, PuppetError> {
         debug!("contact_raw_payload(contact_id = {})", contact_id);
         match self
-            .client()
-            .contact_payload(ContactPayloadRequest { id: contact_id.clone() })
-            .await
+            .timed(
+                "contact_raw_payload",
+                self.client()
+                    .contact_payload(ContactPayloadRequest { id: contact_id.clone() }),
+            )
+            .await?
         {
-            Ok(response) => Ok(ContactPayload::from_payload_response(response.into_inner())),
+            Ok(response) => response.into_inner().try_into(),
             Err(_) => Err(PuppetError::Network(format!(
                 "Failed to get raw payload for contact {}",
                 contact_id
@@ -630,9 +779,12 @@ impl PuppetImpl for PuppetService {
     async fn message_contact(&self, message_id: String) -> Result<String, PuppetError> {
         debug!("message_contact(message_id = {})", message_id);
         match self
-            .client()
-            .message_contact(MessageContactRequest { id: message_id.clone() })
-            .await
+            .timed(
+                "message_contact",
+                self.client()
+                    .message_contact(MessageContactRequest { id: message_id.clone() }),
+            )
+            .await?
         {
             Ok(response) => Ok(response.into_inner().id),
             Err(_) => Err(PuppetError::Network(format!(
@@ -645,9 +797,12 @@ impl PuppetImpl for PuppetService {
     async fn message_file(&self, message_id: String) -> Result<FileBox, PuppetError> {
         debug!("message_file(message_id = {})", message_id);
         match self
-            .client()
-            .message_file(MessageFileRequest { id: message_id.clone() })
-            .await
+            .timed(
+                "message_file",
+                self.client()
+                    .message_file(MessageFileRequest { id: message_id.clone() }),
+            )
+            .await?
         {
             Ok(response) => Ok(FileBox::from(response.into_inner().filebox)),
             Err(_) => Err(PuppetError::Network(format!(
@@ -659,13 +814,18 @@ impl PuppetImpl for PuppetService {
 
     async fn message_image(&self, message_id: String, image_type: ImageType) -> Result<FileBox, PuppetError> {
         debug!("message_image(message_id = {})", message_id);
+        let image_type = image_type
+            .to_i32()
+            .ok_or_else(|| PuppetError::InvalidPayload(format!("Invalid image type {:?}", image_type)))?;
         match self
-            .client()
-            .message_image(MessageImageRequest {
-                id: message_id.clone(),
-                r#type: image_type.to_i32().unwrap(),
-            })
-            .await
+            .timed(
+                "message_image",
+                self.client().message_image(MessageImageRequest {
+                    id: message_id.clone(),
+                    r#type: image_type,
+                }),
+            )
+            .await?
         {
             Ok(response) => Ok(FileBox::from(response.into_inner().filebox)),
             Err(_) => Err(PuppetError::Network(format!(
@@ -678,11 +838,14 @@ impl PuppetImpl for PuppetService {
     async fn message_mini_program(&self, message_id: String) -> Result<MiniProgramPayload, PuppetError> {
         debug!("message_mini_program(message_id = {})", message_id);
         match self
-            .client()
-            .message_mini_program(MessageMiniProgramRequest { id: message_id.clone() })
-            .await
+            .timed(
+                "message_mini_program",
+                self.client()
+                    .message_mini_program(MessageMiniProgramRequest { id: message_id.clone() }),
+            )
+            .await?
         {
-            Ok(response) => Ok(from_str(&response.into_inner().mini_program).unwrap()),
+            Ok(response) => response.into_inner().try_into(),
             Err(_) => Err(PuppetError::Network(format!(
                 "Failed to get mini_program of message {}",
                 message_id
@@ -693,11 +856,13 @@ impl PuppetImpl for PuppetService {
     async fn message_url(&self, message_id: String) -> Result<UrlLinkPayload, PuppetError> {
         debug!("message_url(message_id = {})", message_id);
         match self
-            .client()
-            .message_url(MessageUrlRequest { id: message_id.clone() })
-            .await
+            .timed(
+                "message_url",
+                self.client().message_url(MessageUrlRequest { id: message_id.clone() }),
+            )
+            .await?
         {
-            Ok(response) => Ok(from_str(&response.into_inner().url_link).unwrap()),
+            Ok(response) => response.into_inner().try_into(),
             Err(_) => Err(PuppetError::Network(format!(
                 "Failed to get url link of message {}",
                 message_id
@@ -715,12 +880,14 @@ impl PuppetImpl for PuppetService {
             conversation_id, contact_id
         );
         match self
-            .client()
-            .message_send_contact(MessageSendContactRequest {
-                conversation_id: conversation_id.clone(),
-                contact_id: contact_id.clone(),
-            })
-            .await
+            .timed(
+                "message_send_contact",
+                self.client().message_send_contact(MessageSendContactRequest {
+                    conversation_id: conversation_id.clone(),
+                    contact_id: contact_id.clone(),
+                }),
+            )
+            .await?
         {
             Ok(response) => Ok(response.into_inner().id),
             Err(_) => Err(PuppetError::Network(format!(
@@ -736,12 +903,14 @@ impl PuppetImpl for PuppetService {
             conversation_id, file
         );
         match self
-            .client()
-            .message_send_file(MessageSendFileRequest {
-                conversation_id: conversation_id.clone(),
-                filebox: file.to_string(),
-            })
-            .await
+            .timed(
+                "message_send_file",
+                self.client().message_send_file(MessageSendFileRequest {
+                    conversation_id: conversation_id.clone(),
+                    filebox: file.to_string(),
+                }),
+            )
+            .await?
         {
             Ok(response) => Ok(response.into_inner().id),
             Err(_) => Err(PuppetError::Network(format!(
@@ -760,13 +929,17 @@ impl PuppetImpl for PuppetService {
             "message_send_file(conversation_id = {}, mini_program_payload = {:?})",
             conversation_id, mini_program_payload
         );
+        let mini_program = to_string(&mini_program_payload)
+            .map_err(|e| PuppetError::InvalidPayload(format!("Invalid mini program, reason: {}", e)))?;
         match self
-            .client()
-            .message_send_mini_program(MessageSendMiniProgramRequest {
-                conversation_id: conversation_id.clone(),
-                mini_program: to_string::<MiniProgramPayload>(&mini_program_payload).unwrap(),
-            })
-            .await
+            .timed(
+                "message_send_mini_program",
+                self.client().message_send_mini_program(MessageSendMiniProgramRequest {
+                    conversation_id: conversation_id.clone(),
+                    mini_program,
+                }),
+            )
+            .await?
         {
             Ok(response) => Ok(response.into_inner().id),
             Err(_) => Err(PuppetError::Network(format!(
@@ -787,13 +960,15 @@ impl PuppetImpl for PuppetService {
             conversation_id, text, mention_id_list
         );
         match self
-            .client()
-            .message_send_text(MessageSendTextRequest {
-                conversation_id: conversation_id.clone(),
-                text,
-                mentonal_ids: mention_id_list,
-            })
-            .await
+            .timed(
+                "message_send_text",
+                self.client().message_send_text(MessageSendTextRequest {
+                    conversation_id: conversation_id.clone(),
+                    text,
+                    mentonal_ids: mention_id_list,
+                }),
+            )
+            .await?
         {
             Ok(response) => Ok(response.into_inner().id),
             Err(_) => Err(PuppetError::Network(format!(
@@ -812,13 +987,17 @@ impl PuppetImpl for PuppetService {
             "message_send_url(conversation_id = {}, url_link_payload = {:?})",
             conversation_id, url_link_payload
         );
+        let url_link = to_string(&url_link_payload)
+            .map_err(|e| PuppetError::InvalidPayload(format!("Invalid url link, reason: {}", e)))?;
         match self
-            .client()
-            .message_send_url(MessageSendUrlRequest {
-                conversation_id: conversation_id.clone(),
-                url_link: to_string::<UrlLinkPayload>(&url_link_payload).unwrap(),
-            })
-            .await
+            .timed(
+                "message_send_url",
+                self.client().message_send_url(MessageSendUrlRequest {
+                    conversation_id: conversation_id.clone(),
+                    url_link,
+                }),
+            )
+            .await?
         {
             Ok(response) => Ok(response.into_inner().id),
             Err(_) => Err(PuppetError::Network(format!(
@@ -828,14 +1007,32 @@ impl PuppetImpl for PuppetService {
         }
     }
 
+    async fn message_recall(&self, message_id: String) -> Result<bool, PuppetError> {
+        debug!("message_recall(message_id = {})", message_id);
+        match self
+            .timed(
+                "message_recall",
+                self.client()
+                    .message_recall(MessageRecallRequest { id: message_id.clone() }),
+            )
+            .await?
+        {
+            Ok(response) => Ok(response.into_inner().success),
+            Err(_) => Err(PuppetError::Network(format!("Failed to recall message {}", message_id))),
+        }
+    }
+
     async fn message_raw_payload(&self, message_id: String) -> Result<MessagePayload, PuppetError> {
         debug!("message_raw_payload(message_id = {})", message_id);
         match self
-            .client()
-            .message_payload(MessagePayloadRequest { id: message_id.clone() })
-            .await
+            .timed(
+                "message_raw_payload",
+                self.client()
+                    .message_payload(MessagePayloadRequest { id: message_id.clone() }),
+            )
+            .await?
         {
-            Ok(response) => Ok(MessagePayload::from_payload_response(response.into_inner())),
+            Ok(response) => response.into_inner().try_into(),
             Err(_) => Err(PuppetError::Network(format!(
                 "Failed to get raw payload for message {}",
                 message_id
@@ -846,11 +1043,13 @@ impl PuppetImpl for PuppetService {
     async fn friendship_accept(&self, friendship_id: String) -> Result<(), PuppetError> {
         debug!("friendship_accept(friendship_id = {})", friendship_id);
         match self
-            .client()
-            .friendship_accept(FriendshipAcceptRequest {
-                id: friendship_id.clone(),
-            })
-            .await
+            .timed(
+                "friendship_accept",
+                self.client().friendship_accept(FriendshipAcceptRequest {
+                    id: friendship_id.clone(),
+                }),
+            )
+            .await?
         {
             Ok(_) => Ok(()),
             Err(_) => Err(PuppetError::Network(format!(
@@ -863,12 +1062,14 @@ impl PuppetImpl for PuppetService {
     async fn friendship_add(&self, contact_id: String, hello: Option<String>) -> Result<(), PuppetError> {
         debug!("friendship_add(contact_id = {}, hello = {:?})", contact_id, hello);
         match self
-            .client()
-            .friendship_add(FriendshipAddRequest {
-                contact_id: contact_id.clone(),
-                hello: if let Some(hello) = hello { hello } else { String::new() },
-            })
-            .await
+            .timed(
+                "friendship_add",
+                self.client().friendship_add(FriendshipAddRequest {
+                    contact_id: contact_id.clone(),
+                    hello: hello.unwrap_or_default(),
+                }),
+            )
+            .await?
         {
             Ok(_) => Ok(()),
             Err(_) => Err(PuppetError::Network(format!("Failed to add contact {}", contact_id))),
@@ -878,9 +1079,12 @@ impl PuppetImpl for PuppetService {
     async fn friendship_search_phone(&self, phone: String) -> Result<Option<String>, PuppetError> {
         debug!("friendship_search_phone(phone = {})", phone);
         match self
-            .client()
-            .friendship_search_phone(FriendshipSearchPhoneRequest { phone: phone.clone() })
-            .await
+            .timed(
+                "friendship_search_phone",
+                self.client()
+                    .friendship_search_phone(FriendshipSearchPhoneRequest { phone: phone.clone() }),
+            )
+            .await?
         {
             Ok(response) => Ok(response.into_inner().contact_id),
             Err(_) => Err(PuppetError::Network(format!("Failed to search phone {}", phone))),
@@ -890,9 +1094,12 @@ impl PuppetImpl for PuppetService {
     async fn friendship_search_weixin(&self, weixin: String) -> Result<Option<String>, PuppetError> {
         debug!("friendship_search_weixin(weixin = {})", weixin);
         match self
-            .client()
-            .friendship_search_weixin(FriendshipSearchWeixinRequest { weixin: weixin.clone() })
-            .await
+            .timed(
+                "friendship_search_weixin",
+                self.client()
+                    .friendship_search_weixin(FriendshipSearchWeixinRequest { weixin: weixin.clone() }),
+            )
+            .await?
         {
             Ok(response) => Ok(response.into_inner().contact_id),
             Err(_) => Err(PuppetError::Network(format!("Failed to search weixin {}", weixin))),
@@ -902,14 +1109,16 @@ impl PuppetImpl for PuppetService {
     async fn friendship_raw_payload(&self, friendship_id: String) -> Result<FriendshipPayload, PuppetError> {
         debug!("friendship_raw_payload(friendship_id = {})", friendship_id);
         match self
-            .client()
-            .friendship_payload(FriendshipPayloadRequest {
-                id: friendship_id.clone(),
-                payload: None,
-            })
-            .await
+            .timed(
+                "friendship_raw_payload",
+                self.client().friendship_payload(FriendshipPayloadRequest {
+                    id: friendship_id.clone(),
+                    payload: None,
+                }),
+            )
+            .await?
         {
-            Ok(response) => Ok(FriendshipPayload::from_payload_response(response.into_inner())),
+            Ok(response) => response.into_inner().try_into(),
             Err(_) => Err(PuppetError::Network(format!(
                 "Failed to get raw payload for friendship {}",
                 friendship_id
@@ -920,11 +1129,13 @@ impl PuppetImpl for PuppetService {
     async fn room_invitation_accept(&self, room_invitation_id: String) -> Result<(), PuppetError> {
         debug!("room_invitation_accept(room_invitation_id = {})", room_invitation_id);
         match self
-            .client()
-            .room_invitation_accept(RoomInvitationAcceptRequest {
-                id: room_invitation_id.clone(),
-            })
-            .await
+            .timed(
+                "room_invitation_accept",
+                self.client().room_invitation_accept(RoomInvitationAcceptRequest {
+                    id: room_invitation_id.clone(),
+                }),
+            )
+            .await?
         {
             Ok(_) => Ok(()),
             Err(_) => Err(PuppetError::Network(format!(
@@ -943,14 +1154,16 @@ impl PuppetImpl for PuppetService {
             room_invitation_id
         );
         match self
-            .client()
-            .room_invitation_payload(RoomInvitationPayloadRequest {
-                id: room_invitation_id.clone(),
-                payload: None,
-            })
-            .await
+            .timed(
+                "room_invitation_raw_payload",
+                self.client().room_invitation_payload(RoomInvitationPayloadRequest {
+                    id: room_invitation_id.clone(),
+                    payload: None,
+                }),
+            )
+            .await?
         {
-            Ok(response) => Ok(RoomInvitationPayload::from_payload_response(response.into_inner())),
+            Ok(response) => response.into_inner().try_into(),
             Err(_) => Err(PuppetError::Network(format!(
                 "Failed to get raw payload for room invitation {}",
                 room_invitation_id
@@ -961,12 +1174,14 @@ impl PuppetImpl for PuppetService {
     async fn room_add(&self, room_id: String, contact_id: String) -> Result<(), PuppetError> {
         debug!("room_add(room_id = {}, contact_id = {})", room_id, contact_id);
         match self
-            .client()
-            .room_add(RoomAddRequest {
-                id: room_id.clone(),
-                contact_id: contact_id.clone(),
-            })
-            .await
+            .timed(
+                "room_add",
+                self.client().room_add(RoomAddRequest {
+                    id: room_id.clone(),
+                    contact_id: contact_id.clone(),
+                }),
+            )
+            .await?
         {
             Ok(_) => Ok(()),
             Err(_) => Err(PuppetError::Network(format!(
@@ -979,9 +1194,11 @@ impl PuppetImpl for PuppetService {
     async fn room_avatar(&self, room_id: String) -> Result<FileBox, PuppetError> {
         debug!("room_avatar(room_id = {})", room_id);
         match self
-            .client()
-            .room_avatar(RoomAvatarRequest { id: room_id.clone() })
-            .await
+            .timed(
+                "room_avatar",
+                self.client().room_avatar(RoomAvatarRequest { id: room_id.clone() }),
+            )
+            .await?
         {
             Ok(response) => Ok(FileBox::from(response.into_inner().filebox)),
             Err(_) => Err(PuppetError::Network(format!(
@@ -997,27 +1214,31 @@ impl PuppetImpl for PuppetService {
             contact_id_list, topic
         );
         match self
-            .client()
-            .room_create(RoomCreateRequest {
-                contact_ids: contact_id_list,
-                topic: if let Some(topic) = topic { topic } else { String::new() },
-            })
-            .await
+            .timed(
+                "room_create",
+                self.client().room_create(RoomCreateRequest {
+                    contact_ids: contact_id_list,
+                    topic: topic.unwrap_or_default(),
+                }),
+            )
+            .await?
         {
             Ok(response) => Ok(response.into_inner().id),
-            Err(_) => Err(PuppetError::Network(format!("Failed to create room"))),
+            Err(_) => Err(PuppetError::Network("Failed to create room".to_owned())),
         }
     }
 
     async fn room_del(&self, room_id: String, contact_id: String) -> Result<(), PuppetError> {
         debug!("room_del(room_id = {}, contact_id = {})", room_id, contact_id);
         match self
-            .client()
-            .room_del(RoomDelRequest {
-                id: room_id.clone(),
-                contact_id: contact_id.clone(),
-            })
-            .await
+            .timed(
+                "room_del",
+                self.client().room_del(RoomDelRequest {
+                    id: room_id.clone(),
+                    contact_id: contact_id.clone(),
+                }),
+            )
+            .await?
         {
             Ok(_) => Ok(()),
             Err(_) => Err(PuppetError::Network(format!(
@@ -1030,9 +1251,11 @@ impl PuppetImpl for PuppetService {
     async fn room_qr_code(&self, room_id: String) -> Result<String, PuppetError> {
         debug!("room_qr_code(room_id = {})", room_id);
         match self
-            .client()
-            .room_qr_code(RoomQrCodeRequest { id: room_id.clone() })
-            .await
+            .timed(
+                "room_qr_code",
+                self.client().room_qr_code(RoomQrCodeRequest { id: room_id.clone() }),
+            )
+            .await?
         {
             Ok(response) => Ok(response.into_inner().qrcode),
             Err(_) => Err(PuppetError::Network(format!(
@@ -1044,7 +1267,13 @@ impl PuppetImpl for PuppetService {
 
     async fn room_quit(&self, room_id: String) -> Result<(), PuppetError> {
         debug!("room_quit(room_id = {})", room_id);
-        match self.client().room_quit(RoomQuitRequest { id: room_id.clone() }).await {
+        match self
+            .timed(
+                "room_quit",
+                self.client().room_quit(RoomQuitRequest { id: room_id.clone() }),
+            )
+            .await?
+        {
             Ok(_) => Ok(()),
             Err(_) => Err(PuppetError::Network(format!("Failed to quit room {}", room_id))),
         }
@@ -1053,14 +1282,16 @@ impl PuppetImpl for PuppetService {
     async fn room_topic(&self, room_id: String) -> Result<String, PuppetError> {
         debug!("room_topic(room_id = {})", room_id);
         match self
-            .client()
-            .room_topic(RoomTopicRequest {
-                id: room_id.clone(),
-                topic: None,
-            })
-            .await
+            .timed(
+                "room_topic",
+                self.client().room_topic(RoomTopicRequest {
+                    id: room_id.clone(),
+                    topic: None,
+                }),
+            )
+            .await?
         {
-            Ok(response) => Ok(response.into_inner().topic.unwrap()),
+            Ok(response) => Ok(response.into_inner().topic.unwrap_or_default()),
             Err(_) => Err(PuppetError::Network(format!("Failed to get topic of room {}", room_id))),
         }
     }
@@ -1068,12 +1299,14 @@ impl PuppetImpl for PuppetService {
     async fn room_topic_set(&self, room_id: String, topic: String) -> Result<(), PuppetError> {
         debug!("room_topic_set(room_id = {}, topic = {})", room_id, topic);
         match self
-            .client()
-            .room_topic(RoomTopicRequest {
-                id: room_id.clone(),
-                topic: Some(topic),
-            })
-            .await
+            .timed(
+                "room_topic_set",
+                self.client().room_topic(RoomTopicRequest {
+                    id: room_id.clone(),
+                    topic: Some(topic),
+                }),
+            )
+            .await?
         {
             Ok(_) => Ok(()),
             Err(_) => Err(PuppetError::Network(format!(
@@ -1085,20 +1318,25 @@ impl PuppetImpl for PuppetService {
 
     async fn room_list(&self) -> Result<Vec<String>, PuppetError> {
         debug!("room_list()");
-        match self.client().room_list(RoomListRequest {}).await {
+        match self
+            .timed("room_list", self.client().room_list(RoomListRequest {}))
+            .await?
+        {
             Ok(response) => Ok(response.into_inner().ids),
-            Err(_) => Err(PuppetError::Network(format!("Failed to get rooms"))),
+            Err(_) => Err(PuppetError::Network("Failed to get rooms".to_owned())),
         }
     }
 
     async fn room_raw_payload(&self, room_id: String) -> Result<RoomPayload, PuppetError> {
         debug!("room_raw_payload(room_id = {})", room_id);
         match self
-            .client()
-            .room_payload(RoomPayloadRequest { id: room_id.clone() })
-            .await
+            .timed(
+                "room_raw_payload",
+                self.client().room_payload(RoomPayloadRequest { id: room_id.clone() }),
+            )
+            .await?
         {
-            Ok(response) => Ok(RoomPayload::from_payload_response(response.into_inner())),
+            Ok(response) => response.into_inner().try_into(),
             Err(_) => Err(PuppetError::Network(format!(
                 "Failed to get raw payload for room {}",
                 room_id
@@ -1109,14 +1347,16 @@ impl PuppetImpl for PuppetService {
     async fn room_announce(&self, room_id: String) -> Result<String, PuppetError> {
         debug!("room_announce(room_id = {})", room_id);
         match self
-            .client()
-            .room_announce(RoomAnnounceRequest {
-                id: room_id.clone(),
-                text: None,
-            })
-            .await
+            .timed(
+                "room_announce",
+                self.client().room_announce(RoomAnnounceRequest {
+                    id: room_id.clone(),
+                    text: None,
+                }),
+            )
+            .await?
         {
-            Ok(response) => Ok(response.into_inner().text.unwrap()),
+            Ok(response) => Ok(response.into_inner().text.unwrap_or_default()),
             Err(_) => Err(PuppetError::Network(format!(
                 "Failed to get announce of room {}",
                 room_id
@@ -1127,12 +1367,14 @@ impl PuppetImpl for PuppetService {
     async fn room_announce_set(&self, room_id: String, text: String) -> Result<(), PuppetError> {
         debug!("room_announce(room_id = {}, text = {})", room_id, text);
         match self
-            .client()
-            .room_announce(RoomAnnounceRequest {
-                id: room_id.clone(),
-                text: Some(text),
-            })
-            .await
+            .timed(
+                "room_announce_set",
+                self.client().room_announce(RoomAnnounceRequest {
+                    id: room_id.clone(),
+                    text: Some(text),
+                }),
+            )
+            .await?
         {
             Ok(_) => Ok(()),
             Err(_) => Err(PuppetError::Network(format!(
@@ -1145,9 +1387,12 @@ impl PuppetImpl for PuppetService {
     async fn room_member_list(&self, room_id: String) -> Result<Vec<String>, PuppetError> {
         debug!("room_member_list(room_id = {})", room_id);
         match self
-            .client()
-            .room_member_list(RoomMemberListRequest { id: room_id.clone() })
-            .await
+            .timed(
+                "room_member_list",
+                self.client()
+                    .room_member_list(RoomMemberListRequest { id: room_id.clone() }),
+            )
+            .await?
         {
             Ok(response) => Ok(response.into_inner().member_ids),
             Err(_) => Err(PuppetError::Network(format!(
@@ -1167,14 +1412,16 @@ impl PuppetImpl for PuppetService {
             room_id, contact_id
         );
         match self
-            .client()
-            .room_member_payload(RoomMemberPayloadRequest {
-                id: room_id.clone(),
-                member_id: contact_id.clone(),
-            })
-            .await
+            .timed(
+                "room_member_raw_payload",
+                self.client().room_member_payload(RoomMemberPayloadRequest {
+                    id: room_id.clone(),
+                    member_id: contact_id.clone(),
+                }),
+            )
+            .await?
         {
-            Ok(response) => Ok(RoomMemberPayload::from_payload_response(response.into_inner())),
+            Ok(response) => response.into_inner().try_into(),
             Err(_) => Err(PuppetError::Network(format!(
                 "Failed to get raw payload for member {} of room {}",
                 contact_id, room_id
@@ -1184,62 +1431,521 @@ impl PuppetImpl for PuppetService {
 
     async fn start(&self) -> Result<(), PuppetError> {
         debug!("start()");
-        match self.client().start(StartRequest {}).await {
-            Ok(_) => Ok(()),
-            Err(_) => Err(PuppetError::Network(format!("Failed to start puppet"))),
+        match self.timed("start", self.client().start(StartRequest {})).await? {
+            Ok(_) => {
+                if let Err(e) = self.addr.send(PuppetServiceInternalMessage::Start).await {
+                    error!("Internal error: {}", e);
+                }
+                Ok(())
+            }
+            Err(_) => Err(PuppetError::Network("Failed to start puppet".to_owned())),
         }
     }
 
+    /// Stop the puppet, and the event stream along with it.
     async fn stop(&self) -> Result<(), PuppetError> {
         debug!("stop()");
-        match self.client().stop(StopRequest {}).await {
-            Ok(_) => Ok(()),
-            Err(_) => Err(PuppetError::Network(format!("Failed to stop puppet"))),
+        match self.timed("stop", self.client().stop(StopRequest {})).await? {
+            Ok(_) => {
+                if let Err(e) = self.addr.send(PuppetServiceInternalMessage::Stop).await {
+                    error!("Internal error: {}", e);
+                }
+                Ok(())
+            }
+            Err(_) => Err(PuppetError::Network("Failed to stop puppet".to_owned())),
         }
     }
 
     async fn ding(&self, data: String) -> Result<(), PuppetError> {
         debug!("ding(data = {})", data);
-        match self.client().ding(DingRequest { data }).await {
+        match self.timed("ding", self.client().ding(DingRequest { data })).await? {
             Ok(_) => Ok(()),
-            Err(_) => Err(PuppetError::Network(format!("Failed to ding"))),
+            Err(_) => Err(PuppetError::Network("Failed to ding".to_owned())),
         }
     }
 
     async fn version(&self) -> Result<String, PuppetError> {
         debug!("version()");
-        match self.client().version(VersionRequest {}).await {
+        match self.timed("version", self.client().version(VersionRequest {})).await? {
             Ok(response) => Ok(response.into_inner().version),
-            Err(_) => Err(PuppetError::Network(format!("Failed to get puppet version"))),
+            Err(_) => Err(PuppetError::Network("Failed to get puppet version".to_owned())),
         }
     }
 
     async fn logout(&self) -> Result<(), PuppetError> {
         debug!("logout()");
-        match self.client().logout(LogoutRequest {}).await {
+        match self.timed("logout", self.client().logout(LogoutRequest {})).await? {
             Ok(_) => Ok(()),
-            Err(_) => Err(PuppetError::Network(format!("Failed to logout"))),
+            Err(_) => Err(PuppetError::Network("Failed to logout".to_owned())),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::fs::read;
+    use std::pin::Pin;
+    use std::sync::Mutex;
+
+    use tokio::sync::mpsc;
+    use tokio_stream::wrappers::{TcpListenerStream, UnboundedReceiverStream};
+    use tokio_stream::{Stream, StreamExt};
+    use tonic::codegen::{http, ok, BoxFuture, HttpBody, Never, Ready, Service, StdError};
+    use tonic::transport::{Identity, NamedService, Server, ServerTlsConfig};
+
     use super::*;
+    use crate::event_payload::encode_event;
+
+    const TOKEN: &str = "test-token";
+
+    fn fixture(name: &str) -> String {
+        format!("{}/fixtures/tls/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    /// Answers `Version` calls with the authorization metadata of the request.
+    #[derive(Clone)]
+    struct AuthorizationEcho;
+
+    impl tonic::server::UnaryService<VersionRequest> for AuthorizationEcho {
+        type Response = VersionResponse;
+        type Future = Ready<Result<tonic::Response<VersionResponse>, Status>>;
+
+        fn call(&mut self, request: tonic::Request<VersionRequest>) -> Self::Future {
+            let version = request
+                .metadata()
+                .get("authorization")
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_owned();
+            ok(tonic::Response::new(VersionResponse { version }))
+        }
+    }
+
+    impl<B> Service<http::Request<B>> for AuthorizationEcho
+    where
+        B: HttpBody + Send + Sync + 'static,
+        B::Error: Into<StdError> + Send + 'static,
+    {
+        type Response = http::Response<tonic::body::BoxBody>;
+        type Error = Never;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, _cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: http::Request<B>) -> Self::Future {
+            let service = self.clone();
+            Box::pin(async move {
+                let mut grpc = tonic::server::Grpc::new(tonic::codec::ProstCodec::default());
+                Ok(grpc.unary(service, request).await)
+            })
+        }
+    }
+
+    impl NamedService for AuthorizationEcho {
+        const NAME: &'static str = "wechaty.Puppet";
+    }
+
+    /// Serve `AuthorizationEcho` over TLS on a local port, and return its endpoint.
+    async fn serve_tls() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("https://{}", listener.local_addr().unwrap());
+        let identity = Identity::from_pem(
+            read(fixture("server.pem")).unwrap(),
+            read(fixture("server.key")).unwrap(),
+        );
+        let server = Server::builder()
+            .tls_config(ServerTlsConfig::new().identity(identity))
+            .unwrap()
+            .add_service(AuthorizationEcho)
+            .serve_with_incoming(TcpListenerStream::new(listener));
+        tokio::spawn(server);
+        endpoint
+    }
+
+    async fn version(endpoint: &str, tls: PuppetTlsOptions) -> Result<String, PuppetError> {
+        let channel = connect(endpoint, Some(&tls), DEFAULT_CONNECT_TIMEOUT).await?;
+        let mut client = authorized_client(channel, Some(TOKEN.to_owned()))?;
+        match client.version(VersionRequest {}).await {
+            Ok(response) => Ok(response.into_inner().version),
+            Err(e) => Err(PuppetError::Network(e.to_string())),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn can_send_token_over_tls() {
+        let endpoint = serve_tls().await;
+        let tls = PuppetTlsOptions {
+            ca_cert_path: Some(fixture("ca.pem")),
+            server_name: Some("puppet.test".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(version(&endpoint, tls).await.unwrap(), format!("Wechaty {}", TOKEN));
+    }
+
+    #[actix_rt::test]
+    async fn cannot_connect_to_untrusted_server_unless_insecure() {
+        let endpoint = serve_tls().await;
+        let tls = PuppetTlsOptions {
+            server_name: Some("puppet.test".to_owned()),
+            ..Default::default()
+        };
+        assert!(version(&endpoint, tls.clone()).await.is_err());
+
+        let tls = PuppetTlsOptions { insecure: true, ..tls };
+        assert_eq!(version(&endpoint, tls).await.unwrap(), format!("Wechaty {}", TOKEN));
+    }
+
+    #[actix_rt::test]
+    async fn ip_endpoints_need_a_server_name() {
+        for endpoint in ["https://127.0.0.1:8788", "https://[::1]:8788"] {
+            match connect(endpoint, Some(&PuppetTlsOptions::default()), Duration::from_millis(100)).await {
+                Err(PuppetError::Network(message)) => assert!(message.contains("server name"), "{}", message),
+                Err(e) => panic!("Expected a missing server name, got {}", e),
+                Ok(_) => panic!("Expected a missing server name, got a channel"),
+            }
+        }
+    }
+
+    #[test]
+    fn reconnect_delay_backs_off_exponentially() {
+        assert_eq!(reconnect_delay(1), Duration::from_secs(1));
+        assert_eq!(reconnect_delay(2), Duration::from_secs(2));
+        assert_eq!(reconnect_delay(5), Duration::from_secs(16));
+        assert_eq!(reconnect_delay(7), RECONNECT_MAX_DELAY);
+        assert_eq!(reconnect_delay(u32::MAX), RECONNECT_MAX_DELAY);
+    }
+
+    #[test]
+    fn slow_methods_get_longer_deadlines() {
+        let mut options = PuppetOptions {
+            timeout: Some(10),
+            ..Default::default()
+        };
+        options.method_timeouts.insert("room_create".to_owned(), 20);
+        let timeouts = CallTimeouts::new(&options);
+        assert_eq!(timeouts.get("message_send_text"), Duration::from_secs(10));
+        assert_eq!(timeouts.get("message_send_file"), Duration::from_secs(50));
+        assert_eq!(timeouts.get("room_create"), Duration::from_secs(20));
+    }
+
+    #[actix_rt::test]
+    async fn method_timeouts_override_the_deadline_of_their_method() {
+        let (script, _senders) = EventScript::new(0);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(
+            Server::builder()
+                .add_service(script)
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let mut options = PuppetOptions {
+            endpoint: Some(endpoint),
+            timeout: Some(60),
+            ..Default::default()
+        };
+        options.method_timeouts.insert("tag_list".to_owned(), 1);
+        let puppet = PuppetService::new(options).await.unwrap();
+
+        // `tag_list` calls the `TagContactList` RPC, the override is keyed by the method all the same.
+        let call = puppet.tag_list();
+        match tokio::time::timeout(Duration::from_secs(10), call).await {
+            Ok(Err(PuppetError::Timeout(message))) => assert!(message.starts_with("tag_list"), "{}", message),
+            Ok(result) => panic!("Expected timeout, got {:?}", result),
+            Err(_) => panic!("Expected the override to apply, the call outlived it"),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn hung_calls_time_out() {
+        let call = tokio::time::sleep(Duration::from_secs(60));
+        match with_deadline("ding", Duration::from_millis(10), call).await {
+            Err(PuppetError::Timeout(_)) => {}
+            result => panic!("Expected timeout, got {:?}", result),
+        }
+
+        // A server that accepts connections but never completes the TLS handshake.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("https://{}", listener.local_addr().unwrap());
+        let tls = PuppetTlsOptions {
+            server_name: Some("puppet.test".to_owned()),
+            insecure: true,
+            ..Default::default()
+        };
+        match connect(&endpoint, Some(&tls), Duration::from_millis(100)).await {
+            Err(PuppetError::Timeout(_)) => {}
+            Err(e) => panic!("Expected timeout, got {}", e),
+            Ok(_) => panic!("Expected timeout, got a channel"),
+        }
+    }
 
     #[actix_rt::test]
     async fn cannot_create_puppet_service_with_invalid_token() {
         let invalid_token = uuid::Uuid::new_v4().to_string();
+        // Resolve through an empty endpoint map, so that the token is unknown without reaching the network.
+        let path = std::env::temp_dir().join(format!("endpoint-map-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "{}").unwrap();
 
-        match PuppetService::new(PuppetOptions {
-            endpoint: None,
-            timeout: None,
+        let result = PuppetService::new(PuppetOptions {
             token: Some(invalid_token),
+            endpoint_map_path: Some(path.to_string_lossy().into_owned()),
+            ..Default::default()
+        })
+        .await;
+        std::fs::remove_file(path).unwrap();
+        match result {
+            Err(PuppetError::InvalidToken) => {}
+            result => panic!("Expected invalid token, got {:?}", result.map(|_| ())),
+        }
+    }
+
+    type EventStream = Pin<Box<dyn Stream<Item = Result<EventResponse, Status>> + Send + Sync>>;
+
+    /// Serves the event streams of a script in turn, one per subscription, and answers `Start` and `Stop`.
+    ///
+    /// A stream ends when the test drops its sender.
+    #[derive(Clone)]
+    struct EventScript {
+        streams: Arc<Mutex<Vec<mpsc::UnboundedReceiver<EventResponse>>>>,
+        subscriptions: Arc<AtomicUsize>,
+    }
+
+    impl EventScript {
+        fn new(stream_count: usize) -> (Self, Vec<mpsc::UnboundedSender<EventResponse>>) {
+            let (senders, receivers) = (0..stream_count).map(|_| mpsc::unbounded_channel()).unzip();
+            let script = Self {
+                streams: Arc::new(Mutex::new(receivers)),
+                subscriptions: Arc::new(AtomicUsize::new(0)),
+            };
+            (script, senders)
+        }
+
+        fn subscriptions(&self) -> usize {
+            self.subscriptions.load(Ordering::SeqCst)
+        }
+    }
+
+    impl tonic::server::UnaryService<StartRequest> for EventScript {
+        type Response = StartResponse;
+        type Future = Ready<Result<tonic::Response<StartResponse>, Status>>;
+
+        fn call(&mut self, _request: tonic::Request<StartRequest>) -> Self::Future {
+            ok(tonic::Response::new(StartResponse {}))
+        }
+    }
+
+    impl tonic::server::UnaryService<StopRequest> for EventScript {
+        type Response = StopResponse;
+        type Future = Ready<Result<tonic::Response<StopResponse>, Status>>;
+
+        fn call(&mut self, _request: tonic::Request<StopRequest>) -> Self::Future {
+            ok(tonic::Response::new(StopResponse {}))
+        }
+    }
+
+    /// Tag lists never come back, so that the calls hang.
+    impl tonic::server::UnaryService<TagContactListRequest> for EventScript {
+        type Response = TagContactListResponse;
+        type Future = BoxFuture<tonic::Response<TagContactListResponse>, Status>;
+
+        fn call(&mut self, _request: tonic::Request<TagContactListRequest>) -> Self::Future {
+            Box::pin(std::future::pending())
+        }
+    }
+
+    impl tonic::server::ServerStreamingService<EventRequest> for EventScript {
+        type Response = EventResponse;
+        type ResponseStream = EventStream;
+        type Future = Ready<Result<tonic::Response<EventStream>, Status>>;
+
+        fn call(&mut self, _request: tonic::Request<EventRequest>) -> Self::Future {
+            self.subscriptions.fetch_add(1, Ordering::SeqCst);
+            let mut streams = self.streams.lock().unwrap();
+            let stream: EventStream = if streams.is_empty() {
+                Box::pin(tokio_stream::pending())
+            } else {
+                Box::pin(UnboundedReceiverStream::new(streams.remove(0)).map(Ok))
+            };
+            ok(tonic::Response::new(stream))
+        }
+    }
+
+    impl<B> Service<http::Request<B>> for EventScript
+    where
+        B: HttpBody + Send + Sync + 'static,
+        B::Error: Into<StdError> + Send + 'static,
+    {
+        type Response = http::Response<tonic::body::BoxBody>;
+        type Error = Never;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, _cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: http::Request<B>) -> Self::Future {
+            let service = self.clone();
+            Box::pin(async move {
+                let mut grpc = tonic::server::Grpc::new(tonic::codec::ProstCodec::default());
+                let response = match request.uri().path() {
+                    "/wechaty.Puppet/Start" => {
+                        tonic::server::Grpc::new(tonic::codec::ProstCodec::<StartResponse, StartRequest>::default())
+                            .unary(service, request)
+                            .await
+                    }
+                    "/wechaty.Puppet/Stop" => {
+                        tonic::server::Grpc::new(tonic::codec::ProstCodec::<StopResponse, StopRequest>::default())
+                            .unary(service, request)
+                            .await
+                    }
+                    "/wechaty.Puppet/TagContactList" => {
+                        tonic::server::Grpc::new(tonic::codec::ProstCodec::<
+                            TagContactListResponse,
+                            TagContactListRequest,
+                        >::default())
+                        .unary(service, request)
+                        .await
+                    }
+                    _ => grpc.server_streaming(service, request).await,
+                };
+                Ok(response)
+            })
+        }
+    }
+
+    impl NamedService for EventScript {
+        const NAME: &'static str = "wechaty.Puppet";
+    }
+
+    fn send(sender: &mpsc::UnboundedSender<EventResponse>, event: PuppetEvent) {
+        sender.send(encode_event(&event).unwrap()).unwrap();
+    }
+
+    fn login(contact_id: &str) -> PuppetEvent {
+        PuppetEvent::Login(EventLoginPayload {
+            contact_id: contact_id.to_owned(),
+        })
+    }
+
+    fn message(message_id: &str) -> PuppetEvent {
+        PuppetEvent::Message(EventMessagePayload {
+            message_id: message_id.to_owned(),
+        })
+    }
+
+    fn dirty(payload_id: &str) -> PuppetEvent {
+        PuppetEvent::Dirty(EventDirtyPayload {
+            payload_type: wechaty_puppet::PayloadType::Contact,
+            payload_id: payload_id.to_owned(),
+        })
+    }
+
+    /// Wait for the next event that is not a state change, and describe it.
+    async fn next_event<S>(events: &mut S) -> String
+    where
+        S: Stream<Item = Result<PuppetEvent, PuppetError>> + Unpin,
+    {
+        loop {
+            match tokio::time::timeout(Duration::from_secs(5), events.next()).await {
+                Ok(Some(Ok(PuppetEvent::StateChange(_)))) => {}
+                Ok(Some(Ok(PuppetEvent::Login(payload)))) => return format!("login {}", payload.contact_id),
+                Ok(Some(Ok(PuppetEvent::Message(payload)))) => return format!("message {}", payload.message_id),
+                Ok(Some(Ok(PuppetEvent::Dirty(payload)))) => return format!("dirty {}", payload.payload_id),
+                Ok(Some(Ok(PuppetEvent::Reset(_)))) => return "reset".to_owned(),
+                result => panic!("Expected an event, got {:?}", result),
+            }
+        }
+    }
+
+    #[actix_rt::test]
+    async fn replayed_events_are_skipped_after_resubscribing_until_stopped() {
+        let (script, mut senders) = EventScript::new(2);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(
+            Server::builder()
+                .add_service(script.clone())
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let puppet = PuppetService::new(PuppetOptions {
+            endpoint: Some(endpoint),
+            ..Default::default()
         })
         .await
-        {
-            Err(e) => println!("Failed to create puppet service: {}", e),
-            Ok(_) => println!("Create puppet service successfully"),
+        .unwrap();
+        let mut events = Box::pin(puppet.events(16));
+        puppet.start().await.unwrap();
+
+        // Events repeating outside of a replay are delivered.
+        let first = senders.remove(0);
+        for event in [login("bot"), message("a"), message("a"), dirty("alice")] {
+            send(&first, event);
+        }
+        drop(first);
+        // The host replays its state after the stream is re-established.
+        let second = senders.remove(0);
+        for event in [login("bot"), message("a"), dirty("alice"), message("b")] {
+            send(&second, event);
         }
+        let mut received = vec![];
+        while received.last().map(String::as_str) != Some("message b") {
+            received.push(next_event(&mut events).await);
+        }
+        assert_eq!(
+            received,
+            vec![
+                "login bot",
+                "message a",
+                "message a",
+                "dirty alice",
+                "reset",
+                "dirty alice",
+                "message b"
+            ]
+        );
+        assert_eq!(puppet.puppet_impl().reconnect_count(), 1);
+
+        // Once stopped, the end of the stream does not lead to resubscribing.
+        puppet.stop().await.unwrap();
+        drop(second);
+        tokio::time::sleep(reconnect_delay(1) + Duration::from_millis(500)).await;
+        assert_eq!(script.subscriptions(), 2);
+
+        puppet.start().await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while script.subscriptions() < 3 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn restarting_during_a_reconnect_delay_subscribes_once() {
+        let (script, mut senders) = EventScript::new(1);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(
+            Server::builder()
+                .add_service(script.clone())
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let puppet = PuppetService::new(PuppetOptions {
+            endpoint: Some(endpoint),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let mut events = Box::pin(puppet.events(16));
+        puppet.start().await.unwrap();
+
+        drop(senders.remove(0));
+        assert_eq!(next_event(&mut events).await, "reset");
+        puppet.stop().await.unwrap();
+        puppet.start().await.unwrap();
+        tokio::time::sleep(reconnect_delay(1) + Duration::from_millis(500)).await;
+        assert_eq!(script.subscriptions(), 2);
     }
 }
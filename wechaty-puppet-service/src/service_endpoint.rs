@@ -1,5 +1,15 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::debug;
+use once_cell::sync::Lazy;
+use reqwest::StatusCode;
 use serde::Deserialize;
 use wechaty_puppet::error::PuppetError;
+use wechaty_puppet::PuppetOptions;
 
 #[derive(Debug, Deserialize)]
 struct Endpoint {
@@ -7,31 +17,223 @@ struct Endpoint {
     port: usize,
 }
 
-const WECHATY_ENDPOINT_RESOLUTION_SERVICE_URI: &'static str = "https://api.chatie.io/v0/hosties/";
-const ENDPOINT_SERVICE_ERROR: &'static str = "Endpoint service error";
-
-pub async fn discover(token: String) -> Result<String, PuppetError> {
-    match reqwest::get(&format!("{}{}", WECHATY_ENDPOINT_RESOLUTION_SERVICE_URI, token)).await {
-        Ok(res) => match res.json::<Endpoint>().await {
-            Ok(endpoint) => {
-                if endpoint.port == 0 {
-                    Err(PuppetError::InvalidToken)
-                } else {
-                    Ok(format!("grpc://{}:{}", endpoint.ip, endpoint.port))
-                }
+const WECHATY_ENDPOINT_RESOLUTION_SERVICE_URI: &str = "https://api.chatie.io/v0/hosties/";
+const DISCOVERY_URL_ENV: &str = "WECHATY_PUPPET_SERVICE_DISCOVERY_URL";
+const ENDPOINT_MAP_ENV: &str = "WECHATY_PUPPET_SERVICE_ENDPOINT_MAP";
+const ENDPOINT_CACHE_TTL: Duration = Duration::from_secs(600);
+
+static ENDPOINT_CACHE: Lazy<EndpointCache> = Lazy::new(|| EndpointCache::new(ENDPOINT_CACHE_TTL));
+
+/// Successful resolutions, keyed by the discovery request uri.
+struct EndpointCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl EndpointCache {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn get(&self, uri: &str) -> Option<String> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(uri) {
+            Some((endpoint, resolved_at)) if resolved_at.elapsed() < self.ttl => Some(endpoint.clone()),
+            Some(_) => {
+                entries.remove(uri);
+                None
             }
-            Err(_) => Err(PuppetError::Network(ENDPOINT_SERVICE_ERROR.to_owned())),
-        },
-        Err(_) => Err(PuppetError::Network(ENDPOINT_SERVICE_ERROR.to_owned())),
+            None => None,
+        }
+    }
+
+    fn insert(&self, uri: String, endpoint: String) {
+        self.entries.lock().unwrap().insert(uri, (endpoint, Instant::now()));
+    }
+}
+
+/// Resolve the endpoint of the puppet service that serves the token.
+///
+/// The mapping file (`options.endpoint_map_path`, or `WECHATY_PUPPET_SERVICE_ENDPOINT_MAP`) is a JSON object
+/// from tokens to endpoints. When it is given, it is the only source, so that air-gapped deployments never
+/// reach out to the network. Otherwise the discovery service at `options.discovery_url`, or
+/// `WECHATY_PUPPET_SERVICE_DISCOVERY_URL`, or the public Wechaty service is asked.
+///
+/// Return `PuppetError::InvalidToken` if the token is unknown, and `PuppetError::Network` if the discovery
+/// service is unreachable or misbehaves.
+pub async fn discover(token: String, options: &PuppetOptions) -> Result<String, PuppetError> {
+    debug!("discover(token = {})", token);
+    if let Some(path) = options
+        .endpoint_map_path
+        .clone()
+        .or_else(|| env::var(ENDPOINT_MAP_ENV).ok())
+    {
+        return lookup(&path, &token);
+    }
+    let discovery_url = options
+        .discovery_url
+        .clone()
+        .or_else(|| env::var(DISCOVERY_URL_ENV).ok())
+        .unwrap_or_else(|| WECHATY_ENDPOINT_RESOLUTION_SERVICE_URI.to_owned());
+    let uri = format!("{}/{}", discovery_url.trim_end_matches('/'), token);
+    resolve(uri, &ENDPOINT_CACHE).await
+}
+
+fn lookup(path: &str, token: &str) -> Result<String, PuppetError> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            return Err(PuppetError::Network(format!(
+                "Failed to read endpoint map {}, reason: {}",
+                path, e
+            )))
+        }
+    };
+    match serde_json::from_str::<HashMap<String, String>>(&content) {
+        Ok(mut endpoints) => endpoints.remove(token).ok_or(PuppetError::InvalidToken),
+        Err(e) => Err(PuppetError::Network(format!(
+            "Invalid endpoint map {}, reason: {}",
+            path, e
+        ))),
+    }
+}
+
+async fn resolve(uri: String, cache: &EndpointCache) -> Result<String, PuppetError> {
+    if let Some(endpoint) = cache.get(&uri) {
+        return Ok(endpoint);
+    }
+    let response = match reqwest::get(&uri).await {
+        Ok(response) => response,
+        Err(e) => {
+            return Err(PuppetError::Network(format!(
+                "Endpoint service unreachable, reason: {}",
+                e
+            )))
+        }
+    };
+    match response.status() {
+        StatusCode::NOT_FOUND => return Err(PuppetError::InvalidToken),
+        status if !status.is_success() => {
+            return Err(PuppetError::Network(format!(
+                "Endpoint service error, status: {}",
+                status
+            )))
+        }
+        _ => {}
+    }
+    match response.json::<Endpoint>().await {
+        Ok(endpoint) => {
+            if endpoint.port == 0 || endpoint.ip.is_empty() {
+                Err(PuppetError::InvalidToken)
+            } else {
+                let endpoint = format!("grpc://{}:{}", endpoint.ip, endpoint.port);
+                cache.insert(uri, endpoint.clone());
+                Ok(endpoint)
+            }
+        }
+        Err(e) => Err(PuppetError::Network(format!(
+            "Invalid endpoint service response, reason: {}",
+            e
+        ))),
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
     use super::*;
 
+    /// Serve the same HTTP response to at most `times` requests, and return the discovery url.
+    async fn serve(status: &'static str, body: &'static str, times: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v0/hosties/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            for _ in 0..times {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = [0; 1024];
+                assert!(socket.read(&mut buf).await.unwrap() > 0);
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        url
+    }
+
+    fn options(discovery_url: String) -> PuppetOptions {
+        PuppetOptions {
+            discovery_url: Some(discovery_url),
+            ..Default::default()
+        }
+    }
+
+    #[actix_rt::test]
+    async fn can_discover_and_cache_endpoint() {
+        let url = serve("200 OK", r#"{"ip":"10.0.0.1","port":8788}"#, 1).await;
+        let token = uuid::Uuid::new_v4().to_string();
+        let endpoint = discover(token.clone(), &options(url.clone())).await.unwrap();
+        assert_eq!(endpoint, "grpc://10.0.0.1:8788");
+        // The stand-in is gone after the first request, so this one must come from the cache.
+        assert_eq!(discover(token, &options(url)).await.unwrap(), endpoint);
+    }
+
     #[actix_rt::test]
-    async fn can_discover() {
-        println!("{:?}", discover("123".to_owned()).await);
+    async fn can_tell_unknown_token_from_unreachable_service() {
+        let url = serve("200 OK", r#"{"ip":"0.0.0.0","port":0}"#, 1).await;
+        match discover("unknown".to_owned(), &options(url)).await {
+            Err(PuppetError::InvalidToken) => {}
+            result => panic!("Expected invalid token, got {:?}", result),
+        }
+
+        let url = serve("404 Not Found", "", 1).await;
+        match discover("unknown".to_owned(), &options(url)).await {
+            Err(PuppetError::InvalidToken) => {}
+            result => panic!("Expected invalid token, got {:?}", result),
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+        match discover("token".to_owned(), &options(url)).await {
+            Err(PuppetError::Network(_)) => {}
+            result => panic!("Expected network error, got {:?}", result),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn can_discover_from_endpoint_map() {
+        let path = env::temp_dir().join(format!("endpoint-map-{}.json", uuid::Uuid::new_v4()));
+        fs::write(&path, r#"{"known": "grpc://192.168.1.2:8788"}"#).unwrap();
+        let options = PuppetOptions {
+            endpoint_map_path: Some(path.to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            discover("known".to_owned(), &options).await.unwrap(),
+            "grpc://192.168.1.2:8788"
+        );
+        match discover("unknown".to_owned(), &options).await {
+            Err(PuppetError::InvalidToken) => {}
+            result => panic!("Expected invalid token, got {:?}", result),
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn cached_endpoints_expire() {
+        let cache = EndpointCache::new(Duration::from_millis(10));
+        cache.insert("uri".to_owned(), "grpc://10.0.0.1:8788".to_owned());
+        assert_eq!(cache.get("uri"), Some("grpc://10.0.0.1:8788".to_owned()));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(cache.get("uri"), None);
     }
 }
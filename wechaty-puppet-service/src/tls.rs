@@ -0,0 +1,98 @@
+use std::fs;
+use std::io::Cursor;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use rustls::{Certificate, ClientConfig, RootCertStore, ServerCertVerified, ServerCertVerifier, TLSError};
+use tonic::transport::{ClientTlsConfig, Identity};
+use wechaty_puppet::{PuppetError, PuppetTlsOptions};
+
+const ALPN_H2: &[u8] = b"h2";
+
+/// Accepts any server certificate, used by the insecure mode.
+struct InsecureVerifier;
+
+impl ServerCertVerifier for InsecureVerifier {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        _presented_certs: &[Certificate],
+        _dns_name: webpki::DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+fn read(path: &str) -> Result<Vec<u8>, PuppetError> {
+    fs::read(path).map_err(|e| PuppetError::Network(format!("Failed to read {}, reason: {}", path, e)))
+}
+
+/// Build the client TLS config for a puppet service whose endpoint host is `host`.
+pub(crate) fn client_tls_config(options: &PuppetTlsOptions, host: &str) -> Result<ClientTlsConfig, PuppetError> {
+    let domain_name = match &options.server_name {
+        Some(server_name) => server_name.clone(),
+        // Rustls only verifies certificates against DNS names, so an IP endpoint needs one to check.
+        None if host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .is_ok() =>
+        {
+            return Err(PuppetError::Network(format!(
+                "Endpoint host {} is an IP address, set the TLS server name to verify the server certificate against",
+                host
+            )))
+        }
+        None => host.to_owned(),
+    };
+    let identity = match (&options.client_cert_path, &options.client_key_path) {
+        (Some(cert_path), Some(key_path)) => Some((read(cert_path)?, read(key_path)?)),
+        (None, None) => None,
+        _ => {
+            return Err(PuppetError::Network(
+                "Client certificate and client key must be given together".to_owned(),
+            ))
+        }
+    };
+
+    if options.insecure {
+        let mut config = ClientConfig::new();
+        config.set_protocols(&[ALPN_H2.to_vec()]);
+        config.dangerous().set_certificate_verifier(Arc::new(InsecureVerifier));
+        if let Some((cert, key)) = identity {
+            let cert_chain = certs(&mut Cursor::new(&cert))
+                .map_err(|_| PuppetError::Network("Invalid client certificate".to_owned()))?;
+            let key = pkcs8_private_keys(&mut Cursor::new(&key))
+                .ok()
+                .and_then(|mut keys| keys.pop())
+                .or_else(|| {
+                    rsa_private_keys(&mut Cursor::new(&key))
+                        .ok()
+                        .and_then(|mut keys| keys.pop())
+                })
+                .ok_or_else(|| PuppetError::Network("Invalid client key".to_owned()))?;
+            config
+                .set_single_client_cert(cert_chain, key)
+                .map_err(|e| PuppetError::Network(format!("Invalid client certificate, reason: {}", e)))?;
+        }
+        return Ok(ClientTlsConfig::new()
+            .domain_name(domain_name)
+            .rustls_client_config(config));
+    }
+
+    let mut config = ClientTlsConfig::new().domain_name(domain_name);
+    if let Some(ca_cert_path) = &options.ca_cert_path {
+        let ca_cert = read(ca_cert_path)?;
+        // Tonic panics on malformed CA certificates, so check them beforehand.
+        if certs(&mut Cursor::new(&ca_cert)).map_or(true, |certs| certs.is_empty()) {
+            return Err(PuppetError::Network(format!("Invalid CA certificate {}", ca_cert_path)));
+        }
+        config = config.ca_certificate(tonic::transport::Certificate::from_pem(ca_cert));
+    }
+    if let Some((cert, key)) = identity {
+        config = config.identity(Identity::from_pem(cert, key));
+    }
+    Ok(config)
+}
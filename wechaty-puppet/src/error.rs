@@ -7,6 +7,10 @@ pub enum PuppetError {
     Unsupported(String),
     UnknownPayloadType,
     UnknownMessageType,
+    Lagged(u64),
+    InvalidState(String),
+    InvalidPayload(String),
+    Timeout(String),
 }
 
 impl fmt::Debug for PuppetError {
@@ -23,6 +27,10 @@ impl fmt::Display for PuppetError {
             PuppetError::Unsupported(function) => write!(fmt, "Unsupported function: {}", function),
             PuppetError::UnknownPayloadType => write!(fmt, "Unknown payload type"),
             PuppetError::UnknownMessageType => write!(fmt, "Unknown message type"),
+            PuppetError::Lagged(skipped) => write!(fmt, "Event stream lagged, {} events skipped", skipped),
+            PuppetError::InvalidState(reason) => write!(fmt, "Invalid puppet state: {}", reason),
+            PuppetError::InvalidPayload(reason) => write!(fmt, "Invalid payload: {}", reason),
+            PuppetError::Timeout(call) => write!(fmt, "Timed out: {}", call),
         }
     }
 }
@@ -37,4 +37,6 @@ pub enum PuppetEvent {
     RoomLeave(EventRoomLeavePayload),
     RoomTopic(EventRoomTopicPayload),
     Scan(EventScanPayload),
+    StateChange(EventStateChangePayload),
+    Raw(EventRawPayload),
 }
@@ -0,0 +1,575 @@
+//! Conversions between the puppet schemas and the messages of the Wechaty gRPC protocol.
+//!
+//! Messages from the wire are checked with `TryFrom`; enum values unknown to this library decode to the
+//! `Unknown` variant, so that a newer server does not fail whole fetches. Payloads always convert to
+//! messages with `From`.
+
+use std::convert::TryFrom;
+
+use num_traits::FromPrimitive;
+use wechaty_grpc::puppet as grpc;
+
+use crate::{
+    ContactGender, ContactPayload, ContactType, FriendshipPayload, FriendshipSceneType, FriendshipType, MessagePayload,
+    MessageType, MiniProgramPayload, PuppetError, RoomInvitationPayload, RoomMemberPayload, RoomPayload,
+    UrlLinkPayload,
+};
+
+fn enum_value<T: FromPrimitive>(value: i32, unknown: T) -> T {
+    T::from_i32(value).unwrap_or(unknown)
+}
+
+/// The gRPC message types are numbered differently from `MessageType`, so map them one by one.
+fn message_type_from_grpc(value: i32) -> MessageType {
+    match grpc::MessageType::from_i32(value) {
+        Some(grpc::MessageType::Unspecified) => MessageType::Unknown,
+        Some(grpc::MessageType::Attachment) => MessageType::Attachment,
+        Some(grpc::MessageType::Audio) => MessageType::Audio,
+        Some(grpc::MessageType::Contact) => MessageType::Contact,
+        Some(grpc::MessageType::Emoticon) => MessageType::Emoticon,
+        Some(grpc::MessageType::Image) => MessageType::Image,
+        Some(grpc::MessageType::Text) => MessageType::Text,
+        Some(grpc::MessageType::Video) => MessageType::Video,
+        Some(grpc::MessageType::ChatHistory) => MessageType::ChatHistory,
+        Some(grpc::MessageType::Location) => MessageType::Location,
+        Some(grpc::MessageType::MiniProgram) => MessageType::MiniProgram,
+        Some(grpc::MessageType::Transfer) => MessageType::Transfer,
+        Some(grpc::MessageType::RedEnvelope) => MessageType::RedEnvelope,
+        Some(grpc::MessageType::Recalled) => MessageType::Recalled,
+        Some(grpc::MessageType::Url) => MessageType::Url,
+        None => MessageType::Unknown,
+    }
+}
+
+/// The protocol has no group notes, they are sent as unspecified.
+fn message_type_to_grpc(message_type: MessageType) -> grpc::MessageType {
+    match message_type {
+        MessageType::Unknown | MessageType::GroupNote => grpc::MessageType::Unspecified,
+        MessageType::Attachment => grpc::MessageType::Attachment,
+        MessageType::Audio => grpc::MessageType::Audio,
+        MessageType::Contact => grpc::MessageType::Contact,
+        MessageType::ChatHistory => grpc::MessageType::ChatHistory,
+        MessageType::Emoticon => grpc::MessageType::Emoticon,
+        MessageType::Image => grpc::MessageType::Image,
+        MessageType::Text => grpc::MessageType::Text,
+        MessageType::Location => grpc::MessageType::Location,
+        MessageType::MiniProgram => grpc::MessageType::MiniProgram,
+        MessageType::Transfer => grpc::MessageType::Transfer,
+        MessageType::RedEnvelope => grpc::MessageType::RedEnvelope,
+        MessageType::Recalled => grpc::MessageType::Recalled,
+        MessageType::Url => grpc::MessageType::Url,
+        MessageType::Video => grpc::MessageType::Video,
+    }
+}
+
+impl TryFrom<grpc::ContactPayloadResponse> for ContactPayload {
+    type Error = PuppetError;
+
+    fn try_from(response: grpc::ContactPayloadResponse) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: response.id,
+            gender: enum_value(response.gender, ContactGender::Unknown),
+            contact_type: enum_value(response.r#type, ContactType::Unknown),
+            name: response.name,
+            avatar: response.avatar,
+            address: response.address,
+            alias: response.alias,
+            city: response.city,
+            friend: response.friend,
+            province: response.province,
+            signature: response.signature,
+            star: response.star,
+            weixin: response.weixin,
+            corporation: response.corporation,
+            title: response.title,
+            description: response.description,
+            coworker: response.coworker,
+            phone: response.phone,
+        })
+    }
+}
+
+impl From<ContactPayload> for grpc::ContactPayloadResponse {
+    fn from(payload: ContactPayload) -> Self {
+        Self {
+            id: payload.id,
+            gender: payload.gender as i32,
+            r#type: payload.contact_type as i32,
+            name: payload.name,
+            avatar: payload.avatar,
+            address: payload.address,
+            alias: payload.alias,
+            city: payload.city,
+            friend: payload.friend,
+            province: payload.province,
+            signature: payload.signature,
+            star: payload.star,
+            weixin: payload.weixin,
+            corporation: payload.corporation,
+            title: payload.title,
+            description: payload.description,
+            coworker: payload.coworker,
+            phone: payload.phone,
+        }
+    }
+}
+
+/// The protocol carries no friendship timestamp, so it is left as 0 rather than made up.
+impl TryFrom<grpc::FriendshipPayloadResponse> for FriendshipPayload {
+    type Error = PuppetError;
+
+    fn try_from(response: grpc::FriendshipPayloadResponse) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: response.id,
+            contact_id: response.contact_id,
+            hello: response.hello,
+            timestamp: 0,
+            scene: enum_value(response.scene, FriendshipSceneType::Unknown),
+            stranger: response.stranger,
+            ticket: response.ticket,
+            friendship_type: enum_value(response.r#type, FriendshipType::Unknown),
+        })
+    }
+}
+
+impl From<FriendshipPayload> for grpc::FriendshipPayloadResponse {
+    fn from(payload: FriendshipPayload) -> Self {
+        Self {
+            id: payload.id,
+            contact_id: payload.contact_id,
+            hello: payload.hello,
+            r#type: payload.friendship_type as i32,
+            stranger: payload.stranger,
+            ticket: payload.ticket,
+            scene: payload.scene as i32,
+        }
+    }
+}
+
+impl TryFrom<grpc::MessagePayloadResponse> for MessagePayload {
+    type Error = PuppetError;
+
+    fn try_from(response: grpc::MessagePayloadResponse) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: response.id,
+            filename: response.filename,
+            text: response.text,
+            timestamp: response.timestamp,
+            message_type: message_type_from_grpc(response.r#type),
+            from_id: response.from_id,
+            mention_id_list: response.mention_ids,
+            room_id: response.room_id,
+            to_id: response.to_id,
+        })
+    }
+}
+
+impl From<MessagePayload> for grpc::MessagePayloadResponse {
+    fn from(payload: MessagePayload) -> Self {
+        Self {
+            id: payload.id,
+            filename: payload.filename,
+            text: payload.text,
+            timestamp: payload.timestamp,
+            r#type: message_type_to_grpc(payload.message_type) as i32,
+            from_id: payload.from_id,
+            room_id: payload.room_id,
+            to_id: payload.to_id,
+            mention_ids: payload.mention_id_list,
+        }
+    }
+}
+
+impl TryFrom<grpc::RoomPayloadResponse> for RoomPayload {
+    type Error = PuppetError;
+
+    fn try_from(response: grpc::RoomPayloadResponse) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: response.id,
+            topic: response.topic,
+            avatar: response.avatar,
+            member_id_list: response.member_ids,
+            owner_id: response.owner_id,
+            admin_id_list: response.admin_ids,
+        })
+    }
+}
+
+impl From<RoomPayload> for grpc::RoomPayloadResponse {
+    fn from(payload: RoomPayload) -> Self {
+        Self {
+            id: payload.id,
+            topic: payload.topic,
+            avatar: payload.avatar,
+            owner_id: payload.owner_id,
+            admin_ids: payload.admin_id_list,
+            member_ids: payload.member_id_list,
+        }
+    }
+}
+
+impl TryFrom<grpc::RoomMemberPayloadResponse> for RoomMemberPayload {
+    type Error = PuppetError;
+
+    fn try_from(response: grpc::RoomMemberPayloadResponse) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: response.id,
+            room_alias: response.room_alias,
+            inviter_id: response.inviter_id,
+            avatar: response.avatar,
+            name: response.name,
+        })
+    }
+}
+
+impl From<RoomMemberPayload> for grpc::RoomMemberPayloadResponse {
+    fn from(payload: RoomMemberPayload) -> Self {
+        Self {
+            id: payload.id,
+            room_alias: payload.room_alias,
+            inviter_id: payload.inviter_id,
+            avatar: payload.avatar,
+            name: payload.name,
+        }
+    }
+}
+
+impl TryFrom<grpc::RoomInvitationPayloadResponse> for RoomInvitationPayload {
+    type Error = PuppetError;
+
+    fn try_from(response: grpc::RoomInvitationPayloadResponse) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: response.id,
+            inviter_id: response.inviter_id,
+            topic: response.topic,
+            avatar: response.avatar,
+            invitation: response.invitation,
+            member_count: response.member_count,
+            member_id_list: response.member_ids,
+            timestamp: response.timestamp,
+            receiver_id: response.receiver_id,
+        })
+    }
+}
+
+impl From<RoomInvitationPayload> for grpc::RoomInvitationPayloadResponse {
+    fn from(payload: RoomInvitationPayload) -> Self {
+        Self {
+            id: payload.id,
+            inviter_id: payload.inviter_id,
+            topic: payload.topic,
+            member_count: payload.member_count,
+            member_ids: payload.member_id_list,
+            timestamp: payload.timestamp,
+            avatar: payload.avatar,
+            invitation: payload.invitation,
+            receiver_id: payload.receiver_id,
+        }
+    }
+}
+
+impl TryFrom<grpc::MessageUrlResponse> for UrlLinkPayload {
+    type Error = PuppetError;
+
+    fn try_from(response: grpc::MessageUrlResponse) -> Result<Self, Self::Error> {
+        serde_json::from_str(&response.url_link)
+            .map_err(|e| PuppetError::InvalidPayload(format!("Malformed url link, reason: {}", e)))
+    }
+}
+
+impl From<UrlLinkPayload> for grpc::MessageUrlResponse {
+    fn from(payload: UrlLinkPayload) -> Self {
+        Self {
+            url_link: serde_json::to_string(&payload).unwrap(),
+        }
+    }
+}
+
+impl TryFrom<grpc::MessageMiniProgramResponse> for MiniProgramPayload {
+    type Error = PuppetError;
+
+    fn try_from(response: grpc::MessageMiniProgramResponse) -> Result<Self, Self::Error> {
+        serde_json::from_str(&response.mini_program)
+            .map_err(|e| PuppetError::InvalidPayload(format!("Malformed mini program, reason: {}", e)))
+    }
+}
+
+impl From<MiniProgramPayload> for grpc::MessageMiniProgramResponse {
+    fn from(payload: MiniProgramPayload) -> Self {
+        Self {
+            mini_program: serde_json::to_string(&payload).unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use super::*;
+
+    /// A linear congruential generator, the same seed always gives the same payloads.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            self.0 >> 33
+        }
+
+        fn pick<T: Clone>(&mut self, values: &[T]) -> T {
+            values[self.next() as usize % values.len()].clone()
+        }
+
+        fn string(&mut self) -> String {
+            let len = self.next() % 8;
+            (0..len)
+                .map(|_| self.pick(&['a', 'Z', '0', ' ', '"', '\\', '群', '🤖']))
+                .collect()
+        }
+
+        fn strings(&mut self) -> Vec<String> {
+            (0..self.next() % 4).map(|_| self.string()).collect()
+        }
+
+        fn flip(&mut self) -> bool {
+            self.next() & 1 == 0
+        }
+
+        fn option(&mut self) -> Option<String> {
+            if self.flip() {
+                None
+            } else {
+                Some(self.string())
+            }
+        }
+
+        fn enum_value<T: FromPrimitive>(&mut self) -> T {
+            loop {
+                if let Some(value) = T::from_u64(self.next() % 32) {
+                    return value;
+                }
+            }
+        }
+    }
+
+    fn round_trip<P, M>(payload: P) -> P
+    where
+        P: Into<M> + TryFrom<M, Error = PuppetError>,
+    {
+        payload.into().try_into().unwrap()
+    }
+
+    #[test]
+    fn payloads_survive_round_trips() {
+        let mut rng = Lcg(0x5eed);
+        for _ in 0..500 {
+            let contact = ContactPayload {
+                id: rng.string(),
+                gender: rng.enum_value(),
+                contact_type: rng.enum_value(),
+                name: rng.string(),
+                avatar: rng.string(),
+                address: rng.string(),
+                alias: rng.string(),
+                city: rng.string(),
+                friend: rng.flip(),
+                province: rng.string(),
+                signature: rng.string(),
+                star: rng.flip(),
+                weixin: rng.string(),
+                corporation: rng.string(),
+                title: rng.string(),
+                description: rng.string(),
+                coworker: rng.flip(),
+                phone: rng.strings(),
+            };
+            let expected = format!("{:?}", contact);
+            assert_eq!(
+                format!("{:?}", round_trip::<_, grpc::ContactPayloadResponse>(contact)),
+                expected
+            );
+
+            let friendship = FriendshipPayload {
+                id: rng.string(),
+                contact_id: rng.string(),
+                hello: rng.string(),
+                timestamp: 0,
+                scene: rng.enum_value(),
+                stranger: rng.string(),
+                ticket: rng.string(),
+                friendship_type: rng.enum_value(),
+            };
+            let expected = format!("{:?}", friendship);
+            assert_eq!(
+                format!("{:?}", round_trip::<_, grpc::FriendshipPayloadResponse>(friendship)),
+                expected
+            );
+
+            let message = MessagePayload {
+                id: rng.string(),
+                filename: rng.string(),
+                text: rng.string(),
+                timestamp: rng.next(),
+                message_type: loop {
+                    match rng.enum_value() {
+                        MessageType::GroupNote => continue,
+                        message_type => break message_type,
+                    }
+                },
+                from_id: rng.string(),
+                mention_id_list: rng.strings(),
+                room_id: rng.string(),
+                to_id: rng.string(),
+            };
+            let expected = format!("{:?}", message);
+            assert_eq!(
+                format!("{:?}", round_trip::<_, grpc::MessagePayloadResponse>(message)),
+                expected
+            );
+
+            let room = RoomPayload {
+                id: rng.string(),
+                topic: rng.string(),
+                avatar: rng.string(),
+                member_id_list: rng.strings(),
+                owner_id: rng.string(),
+                admin_id_list: rng.strings(),
+            };
+            let expected = format!("{:?}", room);
+            assert_eq!(
+                format!("{:?}", round_trip::<_, grpc::RoomPayloadResponse>(room)),
+                expected
+            );
+
+            let room_member = RoomMemberPayload {
+                id: rng.string(),
+                room_alias: rng.string(),
+                inviter_id: rng.string(),
+                avatar: rng.string(),
+                name: rng.string(),
+            };
+            let expected = format!("{:?}", room_member);
+            assert_eq!(
+                format!("{:?}", round_trip::<_, grpc::RoomMemberPayloadResponse>(room_member)),
+                expected
+            );
+
+            let room_invitation = RoomInvitationPayload {
+                id: rng.string(),
+                inviter_id: rng.string(),
+                topic: rng.string(),
+                avatar: rng.string(),
+                invitation: rng.string(),
+                member_count: rng.next() as u32,
+                member_id_list: rng.strings(),
+                timestamp: rng.next(),
+                receiver_id: rng.string(),
+            };
+            let expected = format!("{:?}", room_invitation);
+            assert_eq!(
+                format!(
+                    "{:?}",
+                    round_trip::<_, grpc::RoomInvitationPayloadResponse>(room_invitation)
+                ),
+                expected
+            );
+
+            let url_link = UrlLinkPayload {
+                description: rng.option(),
+                thumbnail_url: rng.option(),
+                title: rng.string(),
+                url: rng.string(),
+            };
+            let expected = format!("{:?}", url_link);
+            assert_eq!(
+                format!("{:?}", round_trip::<_, grpc::MessageUrlResponse>(url_link)),
+                expected
+            );
+
+            let mini_program: MiniProgramPayload = serde_json::from_value(serde_json::json!({
+                "appid": rng.option(),
+                "description": rng.option(),
+                "pagePath": rng.option(),
+                "iconUrl": rng.option(),
+                "shareId": rng.option(),
+                "thumbUrl": rng.option(),
+                "title": rng.option(),
+                "username": rng.option(),
+                "thumbKey": rng.option(),
+            }))
+            .unwrap();
+            let expected = format!("{:?}", mini_program);
+            assert_eq!(
+                format!("{:?}", round_trip::<_, grpc::MessageMiniProgramResponse>(mini_program)),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn message_types_follow_the_protocol_numbering() {
+        let response = |r#type: grpc::MessageType| grpc::MessagePayloadResponse {
+            r#type: r#type as i32,
+            ..Default::default()
+        };
+        let payload: MessagePayload = response(grpc::MessageType::Text).try_into().unwrap();
+        assert_eq!(payload.message_type, MessageType::Text);
+        let payload: MessagePayload = response(grpc::MessageType::Video).try_into().unwrap();
+        assert_eq!(payload.message_type, MessageType::Video);
+        for r#type in 0..16 {
+            if let Some(r#type) = grpc::MessageType::from_i32(r#type) {
+                let payload: MessagePayload = response(r#type).try_into().unwrap();
+                assert_eq!(grpc::MessagePayloadResponse::from(payload).r#type, r#type as i32);
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_enum_values_decode_to_unknown() {
+        let contact = grpc::ContactPayloadResponse {
+            gender: 42,
+            r#type: 42,
+            ..Default::default()
+        };
+        let contact = ContactPayload::try_from(contact).unwrap();
+        assert_eq!(contact.gender, ContactGender::Unknown);
+        assert_eq!(contact.contact_type, ContactType::Unknown);
+        let friendship = grpc::FriendshipPayloadResponse {
+            scene: 42,
+            r#type: 42,
+            ..Default::default()
+        };
+        let friendship = FriendshipPayload::try_from(friendship).unwrap();
+        assert_eq!(friendship.scene, FriendshipSceneType::Unknown);
+        assert_eq!(friendship.friendship_type, FriendshipType::Unknown);
+        let message = grpc::MessagePayloadResponse {
+            r#type: -1,
+            ..Default::default()
+        };
+        assert_eq!(
+            MessagePayload::try_from(message).unwrap().message_type,
+            MessageType::Unknown
+        );
+    }
+
+    #[test]
+    fn server_timestamps_are_kept() {
+        let message = grpc::MessagePayloadResponse {
+            timestamp: 1_600_000_000,
+            ..Default::default()
+        };
+        assert_eq!(MessagePayload::try_from(message).unwrap().timestamp, 1_600_000_000);
+        let room_invitation = grpc::RoomInvitationPayloadResponse {
+            timestamp: 1_600_000_000,
+            ..Default::default()
+        };
+        assert_eq!(
+            RoomInvitationPayload::try_from(room_invitation).unwrap().timestamp,
+            1_600_000_000
+        );
+    }
+}
@@ -3,6 +3,8 @@ extern crate num_derive;
 
 pub mod error;
 pub mod events;
+#[cfg(feature = "grpc")]
+mod grpc;
 pub mod puppet;
 pub mod schemas;
 pub mod types;
@@ -18,7 +20,7 @@ pub use schemas::image::ImageType;
 pub use schemas::message::*;
 pub use schemas::mini_program::MiniProgramPayload;
 pub use schemas::payload::PayloadType;
-pub use schemas::puppet::PuppetOptions;
+pub use schemas::puppet::{PuppetOptions, PuppetState, PuppetTlsOptions};
 pub use schemas::room::*;
 pub use schemas::room_invitation::RoomInvitationPayload;
 pub use schemas::url_link::UrlLinkPayload;
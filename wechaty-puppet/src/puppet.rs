@@ -1,17 +1,21 @@
 use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
 
-use actix::{Actor, Addr, Context, Handler, Message, Recipient};
+use actix::{Actor, Addr, AsyncContext, Context, Handler, Message, Recipient};
 use async_trait::async_trait;
 use filebox::FileBox;
-use futures::StreamExt;
+use futures::{Stream, StreamExt};
 use log::{debug, error, info};
 use lru::LruCache;
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
 
 use crate::{
-    ContactPayload, ContactQueryFilter, FriendshipPayload, FriendshipSearchQueryFilter, ImageType, MessagePayload,
-    MessageQueryFilter, MessageType, MiniProgramPayload, PayloadType, PuppetError, PuppetEvent, RoomInvitationPayload,
-    RoomMemberPayload, RoomMemberQueryFilter, RoomPayload, RoomQueryFilter, UrlLinkPayload,
+    ContactPayload, ContactQueryFilter, EventStateChangePayload, FriendshipPayload, FriendshipSearchQueryFilter,
+    ImageType, MessagePayload, MessageQueryFilter, MessageType, MiniProgramPayload, PayloadType, PuppetError,
+    PuppetEvent, PuppetState, RoomInvitationPayload, RoomMemberPayload, RoomMemberQueryFilter, RoomPayload,
+    RoomQueryFilter, UrlLinkPayload,
 };
 
 const DEFAULT_CONTACT_CACHE_CAP: usize = 3000;
@@ -36,10 +40,48 @@ where
     cache_room_payload: LruCachePtr<RoomPayload>,
     cache_room_member_payload: LruCachePtr<RoomMemberPayload>,
     cache_room_invitation_payload: LruCachePtr<RoomInvitationPayload>,
-    id: Option<String>,
+    event_senders: EventSendersPtr,
+    state: StatePtr,
 }
 
 type SubscribersPtr = Arc<Mutex<HashMap<String, Recipient<PuppetEvent>>>>;
+type EventSendersPtr = Arc<Mutex<Vec<broadcast::Sender<PuppetEvent>>>>;
+type StatePtr = Arc<Mutex<StateCell>>;
+
+/// The lifecycle state, along with the session the host reported while the puppet was not running.
+///
+/// The puppet is started whenever `current` is not `Stopped`. A session reported before `start`, or while
+/// `start` or `stop` holds the state, is kept in `reported` until `start` or `stop` picks it up.
+#[derive(Debug)]
+struct StateCell {
+    current: PuppetState,
+    reported: Option<PuppetState>,
+}
+
+impl StateCell {
+    /// The state seen from outside, where a stopped puppet shows the session reported before `start`.
+    fn visible(&self) -> PuppetState {
+        match (&self.current, &self.reported) {
+            (PuppetState::Stopped, Some(reported @ PuppetState::LoggedIn(_))) => reported.clone(),
+            (current, _) => current.clone(),
+        }
+    }
+
+    /// Apply `update`, and queue the change of the visible state on the mailbox of the puppet.
+    ///
+    /// Both the puppet and its actor change the state under the state lock, so queueing the change there
+    /// delivers state changes in the order they happened.
+    fn update<F: FnOnce(&mut Self)>(&mut self, addr: &Addr<PuppetInner>, update: F) {
+        let previous = self.visible();
+        update(self);
+        let current = self.visible();
+        if current == previous {
+            return;
+        }
+        info!("Puppet state changed from {:?} to {:?}", previous, current);
+        addr.do_send(PuppetEvent::StateChange(EventStateChangePayload { previous, current }));
+    }
+}
 
 #[derive(Message)]
 #[rtype("()")]
@@ -72,10 +114,15 @@ struct PuppetInner {
     room_leave_subscribers: SubscribersPtr,
     room_topic_subscribers: SubscribersPtr,
     scan_subscribers: SubscribersPtr,
+    state_change_subscribers: SubscribersPtr,
+    dirty_subscribers: SubscribersPtr,
+    raw_subscribers: SubscribersPtr,
+    event_senders: EventSendersPtr,
+    state: StatePtr,
 }
 
 impl PuppetInner {
-    fn new() -> Self {
+    fn new(event_senders: EventSendersPtr, state: StatePtr) -> Self {
         Self {
             dong_subscribers: Arc::new(Mutex::new(HashMap::new())),
             error_subscribers: Arc::new(Mutex::new(HashMap::new())),
@@ -91,17 +138,77 @@ impl PuppetInner {
             room_leave_subscribers: Arc::new(Mutex::new(HashMap::new())),
             room_topic_subscribers: Arc::new(Mutex::new(HashMap::new())),
             scan_subscribers: Arc::new(Mutex::new(HashMap::new())),
+            state_change_subscribers: Arc::new(Mutex::new(HashMap::new())),
+            dirty_subscribers: Arc::new(Mutex::new(HashMap::new())),
+            raw_subscribers: Arc::new(Mutex::new(HashMap::new())),
+            event_senders,
+            state,
         }
     }
 
     fn notify(&self, msg: PuppetEvent, subscribers: SubscribersPtr) {
         for (name, subscriber) in subscribers.lock().unwrap().clone() {
-            match subscriber.do_send(msg.clone()) {
-                Err(e) => {
-                    error!("Failed to notify {} : {}", name, e);
-                }
-                Ok(_) => {}
+            if let Err(e) = subscriber.do_send(msg.clone()) {
+                error!("Failed to notify {} : {}", name, e);
+            }
+        }
+    }
+
+    /// Send the event to all stream consumers, dropping the channels whose consumers have gone away.
+    fn broadcast(&self, msg: PuppetEvent) {
+        self.event_senders
+            .lock()
+            .unwrap()
+            .retain(|sender| sender.send(msg.clone()).is_ok());
+    }
+
+    /// Deliver the event to stream consumers and to the subscribers of its kind.
+    fn dispatch(&self, msg: PuppetEvent) {
+        self.broadcast(msg.clone());
+        match msg {
+            PuppetEvent::Dong(_) => self.notify(msg, self.dong_subscribers.clone()),
+            PuppetEvent::Error(_) => self.notify(msg, self.error_subscribers.clone()),
+            PuppetEvent::Friendship(_) => self.notify(msg, self.friendship_subscribers.clone()),
+            PuppetEvent::Heartbeat(_) => self.notify(msg, self.heartbeat_subscribers.clone()),
+            PuppetEvent::Login(_) => self.notify(msg, self.login_subscribers.clone()),
+            PuppetEvent::Logout(_) => self.notify(msg, self.logout_subscribers.clone()),
+            PuppetEvent::Message(_) => self.notify(msg, self.message_subscribers.clone()),
+            PuppetEvent::Ready(_) => self.notify(msg, self.ready_subscribers.clone()),
+            PuppetEvent::Reset(_) => self.notify(msg, self.reset_subscribers.clone()),
+            PuppetEvent::RoomInvite(_) => self.notify(msg, self.room_invite_subscribers.clone()),
+            PuppetEvent::RoomJoin(_) => self.notify(msg, self.room_join_subscribers.clone()),
+            PuppetEvent::RoomLeave(_) => self.notify(msg, self.room_leave_subscribers.clone()),
+            PuppetEvent::RoomTopic(_) => self.notify(msg, self.room_topic_subscribers.clone()),
+            PuppetEvent::Scan(_) => self.notify(msg, self.scan_subscribers.clone()),
+            PuppetEvent::StateChange(_) => self.notify(msg, self.state_change_subscribers.clone()),
+            PuppetEvent::Dirty(_) => self.notify(msg, self.dirty_subscribers.clone()),
+            PuppetEvent::Raw(_) => self.notify(msg, self.raw_subscribers.clone()),
+        }
+    }
+
+    /// Move to the state implied by a login or logout event, and emit the state change.
+    ///
+    /// A running puppet switches between `LoggedOut` and `LoggedIn`, and so does a starting puppet on login.
+    /// A host may also report a session before `start` is called, or while `stop` holds the state, and a
+    /// logout while `start` or `stop` holds it. Those are recorded, for `start` and `stop` to pick them up.
+    fn transition(&self, msg: &PuppetEvent, addr: &Addr<Self>) {
+        let mut state = self.state.lock().unwrap();
+        match (msg, &state.current) {
+            (PuppetEvent::Login(payload), PuppetState::Stopped)
+            | (PuppetEvent::Login(payload), PuppetState::Stopping) => state.update(addr, |state| {
+                state.reported = Some(PuppetState::LoggedIn(payload.contact_id.clone()))
+            }),
+            (PuppetEvent::Login(payload), _) => state.update(addr, |state| {
+                state.current = PuppetState::LoggedIn(payload.contact_id.clone())
+            }),
+            (PuppetEvent::Logout(_), PuppetState::Stopped) => state.update(addr, |state| state.reported = None),
+            (PuppetEvent::Logout(_), PuppetState::Starting) | (PuppetEvent::Logout(_), PuppetState::Stopping) => {
+                state.reported = Some(PuppetState::LoggedOut)
+            }
+            (PuppetEvent::Logout(_), PuppetState::LoggedIn(_)) => {
+                state.update(addr, |state| state.current = PuppetState::LoggedOut)
             }
+            _ => {}
         }
     }
 }
@@ -166,6 +273,15 @@ impl Handler<Subscribe> for PuppetInner {
             "scan" => {
                 self.scan_subscribers.lock().unwrap().insert(msg.name, msg.addr);
             }
+            "state-change" => {
+                self.state_change_subscribers.lock().unwrap().insert(msg.name, msg.addr);
+            }
+            "dirty" => {
+                self.dirty_subscribers.lock().unwrap().insert(msg.name, msg.addr);
+            }
+            "raw" => {
+                self.raw_subscribers.lock().unwrap().insert(msg.name, msg.addr);
+            }
             _ => {
                 error!("Trying to subscribe to unknown event: {}", msg.name);
             }
@@ -221,6 +337,15 @@ impl Handler<UnSubscribe> for PuppetInner {
             "scan" => {
                 self.scan_subscribers.lock().unwrap().remove(&msg.name);
             }
+            "state-change" => {
+                self.state_change_subscribers.lock().unwrap().remove(&msg.name);
+            }
+            "dirty" => {
+                self.dirty_subscribers.lock().unwrap().remove(&msg.name);
+            }
+            "raw" => {
+                self.raw_subscribers.lock().unwrap().remove(&msg.name);
+            }
             _ => {
                 error!("Trying to unsubscribe from unknown event: {}", msg.name);
             }
@@ -228,27 +353,23 @@ impl Handler<UnSubscribe> for PuppetInner {
     }
 }
 
+/// A no-op message, the response to which arrives once the events received before it have been dispatched.
+#[derive(Message)]
+#[rtype("()")]
+struct Flush;
+
+impl Handler<Flush> for PuppetInner {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Flush, _ctx: &mut Self::Context) -> Self::Result {}
+}
+
 impl Handler<PuppetEvent> for PuppetInner {
     type Result = ();
 
-    fn handle(&mut self, msg: PuppetEvent, _ctx: &mut Self::Context) -> Self::Result {
-        match msg {
-            PuppetEvent::Dong(_) => self.notify(msg, self.dong_subscribers.clone()),
-            PuppetEvent::Error(_) => self.notify(msg, self.error_subscribers.clone()),
-            PuppetEvent::Friendship(_) => self.notify(msg, self.friendship_subscribers.clone()),
-            PuppetEvent::Heartbeat(_) => self.notify(msg, self.heartbeat_subscribers.clone()),
-            PuppetEvent::Login(_) => self.notify(msg, self.login_subscribers.clone()),
-            PuppetEvent::Logout(_) => self.notify(msg, self.logout_subscribers.clone()),
-            PuppetEvent::Message(_) => self.notify(msg, self.message_subscribers.clone()),
-            PuppetEvent::Ready(_) => self.notify(msg, self.ready_subscribers.clone()),
-            PuppetEvent::Reset(_) => self.notify(msg, self.reset_subscribers.clone()),
-            PuppetEvent::RoomInvite(_) => self.notify(msg, self.room_invite_subscribers.clone()),
-            PuppetEvent::RoomJoin(_) => self.notify(msg, self.room_join_subscribers.clone()),
-            PuppetEvent::RoomLeave(_) => self.notify(msg, self.room_leave_subscribers.clone()),
-            PuppetEvent::RoomTopic(_) => self.notify(msg, self.room_topic_subscribers.clone()),
-            PuppetEvent::Scan(_) => self.notify(msg, self.scan_subscribers.clone()),
-            _ => {}
-        }
+    fn handle(&mut self, msg: PuppetEvent, ctx: &mut Self::Context) -> Self::Result {
+        self.transition(&msg, &ctx.address());
+        self.dispatch(msg);
     }
 }
 
@@ -257,7 +378,12 @@ where
     T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
 {
     pub fn new(puppet_impl: T) -> Self {
-        let addr = PuppetInner::new().start();
+        let event_senders = Arc::new(Mutex::new(vec![]));
+        let state = Arc::new(Mutex::new(StateCell {
+            current: PuppetState::Stopped,
+            reported: None,
+        }));
+        let addr = PuppetInner::new(event_senders.clone(), state.clone()).start();
 
         Self {
             puppet_impl,
@@ -268,10 +394,16 @@ where
             cache_room_payload: Arc::new(Mutex::new(LruCache::new(DEFAULT_ROOM_CACHE_CAP))),
             cache_room_member_payload: Arc::new(Mutex::new(LruCache::new(DEFAULT_ROOM_MEMBER_CACHE_CAP))),
             cache_room_invitation_payload: Arc::new(Mutex::new(LruCache::new(DEFAULT_ROOM_INVITATION_CACHE_CAP))),
-            id: None,
+            event_senders,
+            state,
         }
     }
 
+    /// Get the underlying puppet implementation.
+    pub fn puppet_impl(&self) -> &T {
+        &self.puppet_impl
+    }
+
     pub fn self_addr(&self) -> Recipient<PuppetEvent> {
         debug!("self_addr()");
         self.addr.clone().recipient()
@@ -287,17 +419,88 @@ where
         self.addr.clone().recipient()
     }
 
-    pub fn self_id(self) -> Option<String> {
+    /// Wait until the events emitted so far have been delivered to subscribers and streams.
+    ///
+    /// Subscribers may still be handling them.
+    pub async fn flush(&self) {
+        debug!("flush()");
+        if let Err(e) = self.addr.send(Flush).await {
+            error!("Internal error: {}", e);
+        }
+    }
+
+    /// Get a stream of all events emitted by the puppet.
+    ///
+    /// Every stream owns a buffer of `buffer_size` events. When the consumer falls behind, the oldest events
+    /// are dropped and the stream yields `Err(PuppetError::Lagged(skipped))` before resuming with the
+    /// remaining ones.
+    ///
+    /// This works alongside `Subscribe`, and can be consumed outside of actix, e.g. in `tokio::select!`.
+    pub fn events(&self, buffer_size: usize) -> impl Stream<Item = Result<PuppetEvent, PuppetError>> {
+        debug!("events(buffer_size = {})", buffer_size);
+        let (sender, receiver) = broadcast::channel(buffer_size.max(1));
+        self.event_senders.lock().unwrap().push(sender);
+        BroadcastStream::new(receiver).map(|item| match item {
+            Ok(event) => Ok(event),
+            Err(BroadcastStreamRecvError::Lagged(skipped)) => Err(PuppetError::Lagged(skipped)),
+        })
+    }
+
+    /// Get the current lifecycle state.
+    pub fn state(&self) -> PuppetState {
+        debug!("state()");
+        self.state.lock().unwrap().visible()
+    }
+
+    /// Get the id of the logged in account.
+    pub fn self_id(&self) -> Option<String> {
         debug!("self_id()");
-        self.id.clone()
+        match self.state.lock().unwrap().visible() {
+            PuppetState::LoggedIn(id) => Some(id),
+            _ => None,
+        }
     }
 
-    pub fn log_on_off(self) -> bool {
+    pub fn log_on_off(&self) -> bool {
         debug!("log_on_off()");
-        match self.id {
-            Some(_) => true,
-            None => false,
+        self.self_id().is_some()
+    }
+
+    /// Move to `next` if `allowed` accepts the current state, return the state moved from.
+    fn enter<F>(&self, allowed: F, next: PuppetState) -> Result<PuppetState, PuppetError>
+    where
+        F: Fn(&StateCell) -> bool,
+    {
+        let mut state = self.state.lock().unwrap();
+        if !allowed(&state) {
+            return Err(PuppetError::InvalidState(format!(
+                "cannot move from {:?} to {:?}",
+                state.visible(),
+                next
+            )));
         }
+        let previous = state.current.clone();
+        state.update(&self.addr, |state| state.current = next);
+        Ok(previous)
+    }
+
+    /// Leave the transitional state `from`, given the session reported meanwhile.
+    ///
+    /// `next` is given that session, and returns the new state along with the session to keep. Nothing
+    /// changes if an event has already moved the puppet out of `from`.
+    fn leave<N>(&self, from: PuppetState, next: N)
+    where
+        N: FnOnce(Option<PuppetState>) -> (PuppetState, Option<PuppetState>),
+    {
+        let mut state = self.state.lock().unwrap();
+        if state.current != from {
+            return;
+        }
+        state.update(&self.addr, |state| {
+            let (current, reported) = next(state.reported.take());
+            state.current = current;
+            state.reported = reported;
+        });
     }
 
     /*
@@ -970,10 +1173,7 @@ where
 
     pub async fn room_search(&mut self, query: RoomQueryFilter) -> Result<Vec<String>, PuppetError> {
         debug!("room_search(query = {:?})", query);
-        let room_id_list = match self.puppet_impl.room_list().await {
-            Ok(room_id_list) => room_id_list,
-            _ => Vec::new(),
-        };
+        let room_id_list = self.puppet_impl.room_list().await.unwrap_or_default();
         debug!("room_search(room_id_list.len() = {})", room_id_list.len());
 
         let filter = Puppet::<T>::room_query_filter_factory(query);
@@ -1221,6 +1421,14 @@ where
         self.puppet_impl.message_raw_payload(message_id).await
     }
 
+    async fn message_recall(&self, message_id: String) -> Result<bool, PuppetError> {
+        let recalled = self.puppet_impl.message_recall(message_id.clone()).await?;
+        if recalled {
+            self.cache_message_payload.lock().unwrap().pop(&message_id);
+        }
+        Ok(recalled)
+    }
+
     async fn friendship_accept(&self, friendship_id: String) -> Result<(), PuppetError> {
         self.puppet_impl.friendship_accept(friendship_id).await
     }
@@ -1312,12 +1520,47 @@ where
         self.puppet_impl.room_member_raw_payload(room_id, contact_id).await
     }
 
+    /// Start the puppet.
+    ///
+    /// A session reported before `start` is kept, calling `start` twice returns `PuppetError::InvalidState`.
     async fn start(&self) -> Result<(), PuppetError> {
-        self.puppet_impl.start().await
-    }
-
+        debug!("start()");
+        self.enter(|state| state.current == PuppetState::Stopped, PuppetState::Starting)?;
+        let result = self.puppet_impl.start().await;
+        // A login event may have arrived while starting, in which case the state has already moved on.
+        self.leave(PuppetState::Starting, |reported| match &result {
+            Ok(_) => (reported.unwrap_or(PuppetState::LoggedOut), None),
+            Err(_) => (
+                PuppetState::Stopped,
+                reported.filter(|reported| *reported != PuppetState::LoggedOut),
+            ),
+        });
+        result
+    }
+
+    /// Stop the puppet, or close the session reported before `start`.
+    ///
+    /// If the underlying puppet fails to stop, the previous state is restored, or the session reported while
+    /// stopping.
     async fn stop(&self) -> Result<(), PuppetError> {
-        self.puppet_impl.stop().await
+        debug!("stop()");
+        let previous = self.enter(
+            |state| state.visible() != PuppetState::Stopped && state.current != PuppetState::Stopping,
+            PuppetState::Stopping,
+        )?;
+        let result = self.puppet_impl.stop().await;
+        self.leave(PuppetState::Stopping, |reported| match (&result, previous) {
+            (Ok(_), _) => (PuppetState::Stopped, None),
+            (Err(_), previous @ PuppetState::LoggedIn(_)) | (Err(_), previous @ PuppetState::LoggedOut) => {
+                (reported.unwrap_or(previous), None)
+            }
+            (Err(_), PuppetState::Stopped) => (
+                PuppetState::Stopped,
+                reported.filter(|reported| *reported != PuppetState::LoggedOut),
+            ),
+            (Err(_), previous) => (previous, None),
+        });
+        result
     }
 
     async fn ding(&self, data: String) -> Result<(), PuppetError> {
@@ -1388,6 +1631,12 @@ pub trait PuppetImpl {
         url_link_payload: UrlLinkPayload,
     ) -> Result<Option<String>, PuppetError>;
     async fn message_raw_payload(&self, message_id: String) -> Result<MessagePayload, PuppetError>;
+    /// Recall a message sent by the bot, return whether it was recalled.
+    ///
+    /// Puppets that cannot recall messages need not implement it.
+    async fn message_recall(&self, _message_id: String) -> Result<bool, PuppetError> {
+        Err(PuppetError::Unsupported("message_recall".into()))
+    }
 
     async fn friendship_accept(&self, friendship_id: String) -> Result<(), PuppetError>;
     async fn friendship_add(&self, contact_id: String, hello: Option<String>) -> Result<(), PuppetError>;
@@ -1,6 +1,7 @@
 use serde_repr::{Deserialize_repr, Serialize_repr};
 
 use crate::schemas::payload::PayloadType;
+use crate::schemas::puppet::PuppetState;
 
 #[derive(Debug, Clone, PartialEq, FromPrimitive, Deserialize_repr, Serialize_repr)]
 #[repr(i32)]
@@ -101,3 +102,16 @@ pub struct EventDirtyPayload {
     pub payload_type: PayloadType,
     pub payload_id: String,
 }
+
+#[derive(Debug, Clone)]
+pub struct EventStateChangePayload {
+    pub previous: PuppetState,
+    pub current: PuppetState,
+}
+
+/// An event that this library does not know yet, passed through with its type number and JSON payload.
+#[derive(Debug, Clone)]
+pub struct EventRawPayload {
+    pub event_type: i32,
+    pub payload: String,
+}
@@ -1,5 +1,51 @@
+use std::collections::HashMap;
+
+/// Options for creating a puppet.
+///
+/// When `endpoint` is not given, it is resolved from `token`, through the static mapping file at
+/// `endpoint_map_path` if given, otherwise through the discovery service at `discovery_url`.
+#[derive(Debug, Clone, Default)]
 pub struct PuppetOptions {
     pub endpoint: Option<String>,
+    /// Default deadline of a call to the puppet, in seconds.
     pub timeout: Option<u64>,
+    /// Deadlines overriding `timeout` for single methods, in seconds, keyed by the name of the `PuppetImpl`
+    /// method such as `message_send_file` or `room_topic_set`.
+    pub method_timeouts: HashMap<String, u64>,
+    /// Deadline for establishing the connection to the puppet, in seconds.
+    pub connect_timeout: Option<u64>,
     pub token: Option<String>,
+    pub tls: Option<PuppetTlsOptions>,
+    pub discovery_url: Option<String>,
+    pub endpoint_map_path: Option<String>,
+}
+
+/// TLS options for connecting to a puppet service.
+///
+/// Certificates and keys are PEM encoded files. When `server_name` is not set, the host of the endpoint is
+/// used for SNI and certificate verification, it must be set when that host is an IP address.
+#[derive(Debug, Clone, Default)]
+pub struct PuppetTlsOptions {
+    pub ca_cert_path: Option<String>,
+    pub client_cert_path: Option<String>,
+    pub client_key_path: Option<String>,
+    pub server_name: Option<String>,
+    /// Skip verification of the server certificate. Only use it for development.
+    pub insecure: bool,
+}
+
+/// The lifecycle state of a puppet.
+///
+/// `start` moves a stopped puppet through `Starting` to `LoggedOut`, login and logout events switch between
+/// `LoggedOut` and `LoggedIn`, and `stop` moves it through `Stopping` back to `Stopped`. A login reported
+/// before `start` shows a stopped puppet as `LoggedIn`, which `start` then keeps, even after a failed
+/// attempt. A logout reported while starting or stopping is kept when `start` or `stop` leaves its
+/// transitional state.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PuppetState {
+    Stopped,
+    Starting,
+    LoggedOut,
+    LoggedIn(String),
+    Stopping,
 }
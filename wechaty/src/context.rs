@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
 
 use futures::StreamExt;
 use log::{debug, error};
@@ -8,35 +9,50 @@ use wechaty_puppet::{
     MessageQueryFilter, Puppet, PuppetImpl, RoomInvitationPayload, RoomPayload, RoomQueryFilter,
 };
 
-use crate::{Contact, Friendship, IntoContact, Message, Room, WechatyError};
+use crate::dispatch::{Dispatch, DispatchOptions, Release};
+use crate::session::Sessions;
+use crate::{Contact, Friendship, IntoContact, Message, Room, Session, SessionKey, WechatyError};
 
 #[derive(Clone)]
 pub struct WechatyContext<T>
 where
     T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
 {
-    id_: Option<String>,
     puppet_: Puppet<T>,
     contacts_: Arc<Mutex<HashMap<String, ContactPayload>>>,
     friendships_: Arc<Mutex<HashMap<String, FriendshipPayload>>>,
     messages_: Arc<Mutex<HashMap<String, MessagePayload>>>,
     rooms_: Arc<Mutex<HashMap<String, RoomPayload>>>,
     room_invitations_: Arc<Mutex<HashMap<String, RoomInvitationPayload>>>,
+    sessions_: Sessions<T>,
+    dispatch_: Dispatch,
+    /// Lets the listener running the current handler go on, once the handler waits for a message.
+    release_: Option<Release>,
 }
 
 impl<T> WechatyContext<T>
 where
     T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
 {
-    pub(crate) fn new(puppet: Puppet<T>) -> Self {
+    pub(crate) fn new(puppet: Puppet<T>, dispatch: DispatchOptions) -> Self {
         Self {
-            id_: None,
             puppet_: puppet,
             contacts_: Arc::new(Mutex::new(Default::default())),
             friendships_: Arc::new(Mutex::new(Default::default())),
             messages_: Arc::new(Mutex::new(Default::default())),
             rooms_: Arc::new(Mutex::new(Default::default())),
             room_invitations_: Arc::new(Mutex::new(Default::default())),
+            sessions_: Sessions::new(),
+            dispatch_: Dispatch::new(dispatch),
+            release_: None,
+        }
+    }
+
+    /// Get the context handed to a handler, which releases its listener when the handler waits.
+    pub(crate) fn with_release(&self, release: Release) -> Self {
+        Self {
+            release_: Some(release),
+            ..self.clone()
         }
     }
 
@@ -64,20 +80,49 @@ where
         self.room_invitations_.lock().unwrap()
     }
 
-    pub(crate) fn id(&self) -> Option<String> {
-        self.id_.clone()
+    pub(crate) fn sessions(&self) -> &Sessions<T> {
+        &self.sessions_
     }
 
-    pub(crate) fn set_id(&mut self, id: String) {
-        self.id_ = Some(id);
+    pub(crate) fn dispatch(&self) -> &Dispatch {
+        &self.dispatch_
     }
 
-    pub(crate) fn clear_id(&mut self) {
-        self.id_ = None;
+    pub(crate) fn id(&self) -> Option<String> {
+        self.puppet_.self_id()
     }
 
     pub(crate) fn is_logged_in(&self) -> bool {
-        self.id_.is_some()
+        self.puppet_.log_on_off()
+    }
+
+    /// Wait for the next incoming message accepted by `filter`, for at most `timeout`.
+    ///
+    /// The message is handed to the first flow waiting for it, and not seen by the handlers. Called from a
+    /// handler, through the context it was given, it lets the listener go on with other events meanwhile.
+    pub async fn wait_for_message<F>(&self, filter: F, timeout: Duration) -> Result<Message<T>, WechatyError>
+    where
+        F: Fn(&Message<T>) -> bool + Send + 'static,
+    {
+        debug!("wait_for_message(timeout = {:?})", timeout);
+        let receiver = self.sessions_.wait(filter);
+        if let Some(release) = &self.release_ {
+            release.release();
+        }
+        match actix_rt::time::timeout(timeout, receiver).await {
+            Ok(Ok(message)) => Ok(message),
+            _ => Err(WechatyError::Timeout),
+        }
+    }
+
+    /// Get the session of the sender of a message in its conversation, resuming it if it has not expired.
+    ///
+    /// Return `None` if the sender of the message is unknown.
+    pub fn session<S>(&self, message: &Message<T>, timeout: Duration) -> Option<Session<T, S>>
+    where
+        S: Default + Send + 'static,
+    {
+        SessionKey::of(message).map(|key| Session::new(self.clone(), key, timeout))
     }
 
     /// Load a contact.
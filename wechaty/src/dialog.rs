@@ -0,0 +1,655 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{error, fmt, fs, io};
+
+use futures::future::BoxFuture;
+use log::{debug, error};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use wechaty_puppet::{MessageType, PuppetImpl};
+
+use crate::{EventListener, Message, MessagePayload, PluginListener, SessionKey, WechatyContext, WechatyPlugin};
+
+type CompleteHandler<T> = Arc<dyn Fn(DialogPayload<T>, WechatyContext<T>) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// A condition on an incoming message.
+#[derive(Clone)]
+pub enum MessageMatcher {
+    Any,
+    /// The trimmed text equals the given one.
+    Text(String),
+    Regex(Regex),
+    Type(MessageType),
+    Func(Arc<dyn Fn(&str) -> bool + Send + Sync>),
+}
+
+impl MessageMatcher {
+    pub fn text(text: &str) -> Self {
+        MessageMatcher::Text(text.to_owned())
+    }
+
+    pub fn regex(pattern: &str) -> Result<Self, regex::Error> {
+        Ok(MessageMatcher::Regex(Regex::new(pattern)?))
+    }
+
+    /// Match the texts accepted by `f`.
+    pub fn func<F>(f: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        MessageMatcher::Func(Arc::new(f))
+    }
+
+    fn matches(&self, text: &str, message_type: &MessageType) -> bool {
+        match self {
+            MessageMatcher::Any => true,
+            MessageMatcher::Text(expected) => text.trim() == expected,
+            MessageMatcher::Regex(regex) => regex.is_match(text),
+            MessageMatcher::Type(expected) => message_type == expected,
+            MessageMatcher::Func(f) => f(text),
+        }
+    }
+}
+
+/// A value asked to the user, kept in the slots of the dialog under its name.
+#[derive(Clone)]
+pub struct Slot {
+    name: String,
+    prompt: String,
+    matcher: MessageMatcher,
+    retry_prompt: Option<String>,
+    max_retries: usize,
+}
+
+impl Slot {
+    pub fn new(name: &str, prompt: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            prompt: prompt.to_owned(),
+            matcher: MessageMatcher::Any,
+            retry_prompt: None,
+            max_retries: 3,
+        }
+    }
+
+    /// Only accept the answers matching `matcher`.
+    pub fn validate(mut self, matcher: MessageMatcher) -> Self {
+        self.matcher = matcher;
+        self
+    }
+
+    /// Set the prompt sent after an invalid answer, the prompt itself by default.
+    pub fn retry(mut self, retry_prompt: &str) -> Self {
+        self.retry_prompt = Some(retry_prompt.to_owned());
+        self
+    }
+
+    /// Give up the dialog after this many invalid answers in a row, 3 by default.
+    pub fn max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+}
+
+/// A state of a dialog.
+///
+/// Entering a state sends its replies, in which `{name}` is replaced by the value of the slot `name`. A state
+/// then either fills a slot, or waits for a message matching one of its transitions. A state with neither
+/// ends the dialog.
+#[derive(Clone)]
+pub struct DialogState {
+    name: String,
+    replies: Vec<String>,
+    slot: Option<(Slot, String)>,
+    transitions: Vec<(MessageMatcher, String)>,
+}
+
+impl DialogState {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            replies: vec![],
+            slot: None,
+            transitions: vec![],
+        }
+    }
+
+    /// Send a text when entering the state.
+    pub fn reply(mut self, text: &str) -> Self {
+        self.replies.push(text.to_owned());
+        self
+    }
+
+    /// Ask for a slot, then go to `next` once it is filled.
+    pub fn slot(mut self, slot: Slot, next: &str) -> Self {
+        self.slot = Some((slot, next.to_owned()));
+        self
+    }
+
+    /// Go to `target` on a message matching `matcher`. Transitions are tried in order.
+    pub fn on(mut self, matcher: MessageMatcher, target: &str) -> Self {
+        self.transitions.push((matcher, target.to_owned()));
+        self
+    }
+
+    fn is_final(&self) -> bool {
+        self.slot.is_none() && self.transitions.is_empty()
+    }
+}
+
+/// Where a dialog is at with a sender in a conversation.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DialogProgress {
+    pub state: String,
+    pub slots: HashMap<String, String>,
+    /// Invalid answers given in a row for the current slot.
+    pub retries: usize,
+    /// When the progress was saved, in seconds since the Unix epoch.
+    pub updated_at: u64,
+}
+
+/// Storage of the progress of a dialog.
+pub trait DialogStore: Send {
+    fn load(&mut self, key: &SessionKey) -> Option<DialogProgress>;
+
+    fn save(&mut self, key: &SessionKey, progress: &DialogProgress);
+
+    fn remove(&mut self, key: &SessionKey);
+}
+
+/// A store keeping the progress in memory, lost on restart.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    progress: HashMap<SessionKey, DialogProgress>,
+}
+
+impl DialogStore for MemoryStore {
+    fn load(&mut self, key: &SessionKey) -> Option<DialogProgress> {
+        self.progress.get(key).cloned()
+    }
+
+    fn save(&mut self, key: &SessionKey, progress: &DialogProgress) {
+        self.progress.insert(key.clone(), progress.clone());
+    }
+
+    fn remove(&mut self, key: &SessionKey) {
+        self.progress.remove(key);
+    }
+}
+
+pub enum DialogStoreError {
+    Io(io::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Debug for DialogStoreError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "DialogStoreError({})", self)
+    }
+}
+
+impl fmt::Display for DialogStoreError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DialogStoreError::Io(e) => write!(fmt, "Failed to access dialog store: {}", e),
+            DialogStoreError::Json(e) => write!(fmt, "Invalid dialog store: {}", e),
+        }
+    }
+}
+
+impl error::Error for DialogStoreError {}
+
+/// A store keeping the progress in a JSON file, rewritten on every change.
+///
+/// The new content is written next to the file first, then moved over it, so a crash never leaves a
+/// truncated store behind.
+#[derive(Debug)]
+pub struct FileStore {
+    path: PathBuf,
+    progress: HashMap<SessionKey, DialogProgress>,
+}
+
+impl FileStore {
+    /// Open a store, the file is created on the first change if it does not exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, DialogStoreError> {
+        let path = path.as_ref().to_path_buf();
+        let progress = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str::<Vec<(SessionKey, DialogProgress)>>(&content)
+                .map_err(DialogStoreError::Json)?
+                .into_iter()
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(DialogStoreError::Io(e)),
+        };
+        Ok(Self { path, progress })
+    }
+
+    fn persist(&self) {
+        let entries: Vec<(&SessionKey, &DialogProgress)> = self.progress.iter().collect();
+        let result = serde_json::to_string(&entries)
+            .map_err(DialogStoreError::Json)
+            .and_then(|content| {
+                let mut temp_path = self.path.clone().into_os_string();
+                temp_path.push(".tmp");
+                fs::write(&temp_path, content)
+                    .and_then(|_| fs::rename(&temp_path, &self.path))
+                    .map_err(DialogStoreError::Io)
+            });
+        if let Err(e) = result {
+            error!("Failed to save dialogs to {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+impl DialogStore for FileStore {
+    fn load(&mut self, key: &SessionKey) -> Option<DialogProgress> {
+        self.progress.get(key).cloned()
+    }
+
+    fn save(&mut self, key: &SessionKey, progress: &DialogProgress) {
+        self.progress.insert(key.clone(), progress.clone());
+        self.persist();
+    }
+
+    fn remove(&mut self, key: &SessionKey) {
+        if self.progress.remove(key).is_some() {
+            self.persist();
+        }
+    }
+}
+
+/// What the completion handler of a dialog receives.
+pub struct DialogPayload<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    /// The message that ended the dialog.
+    pub message: Message<T>,
+    pub slots: HashMap<String, String>,
+}
+
+/// The outcome of a message for a dialog.
+#[derive(Default)]
+struct Step {
+    replies: Vec<String>,
+    completed: Option<HashMap<String, String>>,
+}
+
+/// A dialog with each sender in each conversation, driven by a finite-state machine.
+///
+/// ```ignore
+/// let dialog = Dialog::new("order", MessageMatcher::text("order"), "address")
+///     .state(DialogState::new("address").slot(
+///         Slot::new("address", "What's your address?")
+///             .validate(MessageMatcher::regex(r"^\d+ .+")?)
+///             .retry("Please give a street number and name"),
+///         "confirm",
+///     ))
+///     .state(
+///         DialogState::new("confirm")
+///             .reply("Ship to {address}? (yes/no)")
+///             .on(MessageMatcher::text("yes"), "done")
+///             .on(MessageMatcher::text("no"), "address"),
+///     )
+///     .state(DialogState::new("done").reply("Order placed"))
+///     .store(FileStore::open("dialogs.json")?);
+/// bot.use_plugin(dialog)?;
+/// ```
+pub struct Dialog<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    name: String,
+    trigger: MessageMatcher,
+    initial: String,
+    states: HashMap<String, DialogState>,
+    timeout: Duration,
+    store: Arc<Mutex<Box<dyn DialogStore>>>,
+    on_complete: Option<CompleteHandler<T>>,
+}
+
+impl<T> Dialog<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    /// Create a dialog starting in state `initial` on a message matching `trigger`.
+    pub fn new(name: &str, trigger: MessageMatcher, initial: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            trigger,
+            initial: initial.to_owned(),
+            states: HashMap::new(),
+            timeout: Duration::from_secs(600),
+            store: Arc::new(Mutex::new(Box::new(MemoryStore::default()))),
+            on_complete: None,
+        }
+    }
+
+    pub fn state(mut self, state: DialogState) -> Self {
+        self.states.insert(state.name.clone(), state);
+        self
+    }
+
+    /// Forget a dialog left alone for longer than `timeout`, 10 minutes by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Keep the progress in `store`, in memory by default.
+    pub fn store<S: DialogStore + 'static>(mut self, store: S) -> Self {
+        self.store = Arc::new(Mutex::new(Box::new(store)));
+        self
+    }
+
+    /// Run `handler` with the filled slots when the dialog ends.
+    pub fn on_complete<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(DialogPayload<T>, WechatyContext<T>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.on_complete = Some(Arc::new(move |payload, ctx| Box::pin(handler(payload, ctx))));
+        self
+    }
+
+    /// Get the progress of the dialog with a sender in a conversation, if it is ongoing.
+    pub fn progress(&self, key: &SessionKey) -> Option<DialogProgress> {
+        self.store.lock().unwrap().load(key)
+    }
+
+    /// Advance the dialog with a message, return whether the message was part of it.
+    ///
+    /// Messages handed to a flow waiting for them, e.g. with `Session::next_message`, are not part of dialogs.
+    pub async fn handle(&self, mut message: Message<T>, ctx: WechatyContext<T>) -> bool {
+        debug!("Dialog.handle(name = {}, message = {})", self.name, message);
+        if message.is_self() || ctx.sessions().route(ctx.clone(), message.id()).await {
+            return false;
+        }
+        let (key, message_type) = match (SessionKey::of(&message), message.message_type()) {
+            (Some(key), Some(message_type)) => (key, message_type),
+            _ => return false,
+        };
+        let text = message.text().unwrap_or_default();
+        let step = match self.step(&key, &text, &message_type) {
+            Some(step) => step,
+            None => return false,
+        };
+        for reply in step.replies {
+            if let Err(e) = message.reply_text(reply).await {
+                error!("Failed to reply to {}: {}", message, e);
+            }
+        }
+        if let (Some(slots), Some(on_complete)) = (step.completed, &self.on_complete) {
+            on_complete(DialogPayload { message, slots }, ctx).await;
+        }
+        true
+    }
+
+    /// Update the progress with a message, return what to do or `None` if the dialog ignores the message.
+    fn step(&self, key: &SessionKey, text: &str, message_type: &MessageType) -> Option<Step> {
+        let mut store = self.store.lock().unwrap();
+        let progress = store.load(key).filter(|progress| {
+            now().saturating_sub(progress.updated_at) <= self.timeout.as_secs()
+                && self.states.contains_key(&progress.state)
+        });
+        let mut progress = match progress {
+            Some(progress) => progress,
+            None if self.trigger.matches(text, message_type) => {
+                let initial = self.initial.clone();
+                return Some(self.enter(&mut **store, key, &initial, DialogProgress::default()));
+            }
+            None => {
+                store.remove(key);
+                return None;
+            }
+        };
+        let state = &self.states[&progress.state];
+        match &state.slot {
+            Some((slot, next)) if slot.matcher.matches(text, message_type) => {
+                progress.slots.insert(slot.name.clone(), text.trim().to_owned());
+                progress.retries = 0;
+                Some(self.enter(&mut **store, key, next, progress))
+            }
+            Some((slot, _)) => {
+                progress.retries += 1;
+                if progress.retries > slot.max_retries {
+                    store.remove(key);
+                    return Some(Step::default());
+                }
+                progress.updated_at = now();
+                store.save(key, &progress);
+                Some(Step {
+                    replies: vec![slot.retry_prompt.clone().unwrap_or_else(|| slot.prompt.clone())],
+                    completed: None,
+                })
+            }
+            None => {
+                let (_, target) = state
+                    .transitions
+                    .iter()
+                    .find(|(matcher, _)| matcher.matches(text, message_type))?;
+                Some(self.enter(&mut **store, key, target, progress))
+            }
+        }
+    }
+
+    fn enter(&self, store: &mut dyn DialogStore, key: &SessionKey, name: &str, mut progress: DialogProgress) -> Step {
+        debug!("Dialog.enter(name = {}, state = {})", self.name, name);
+        let state = match self.states.get(name) {
+            Some(state) => state,
+            None => {
+                error!("Dialog {} has no state {}", self.name, name);
+                store.remove(key);
+                return Step::default();
+            }
+        };
+        let mut replies: Vec<String> = state.replies.iter().map(|reply| fill(reply, &progress.slots)).collect();
+        if let Some((slot, _)) = &state.slot {
+            replies.push(fill(&slot.prompt, &progress.slots));
+        }
+        if state.is_final() {
+            store.remove(key);
+            return Step {
+                replies,
+                completed: Some(progress.slots),
+            };
+        }
+        progress.state = name.to_owned();
+        progress.updated_at = now();
+        store.save(key, &progress);
+        Step {
+            replies,
+            completed: None,
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// Replace `{name}` by the value of the slot `name`.
+///
+/// The template is read once, so braces in the values are kept as they are.
+fn fill(template: &str, slots: &HashMap<String, String>) -> String {
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after
+            .find('}')
+            .and_then(|end| slots.get(&after[..end]).map(|value| (end, value)))
+        {
+            Some((end, value)) => {
+                text.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                text.push('{');
+                rest = after;
+            }
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+impl<T> Clone for Dialog<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            trigger: self.trigger.clone(),
+            initial: self.initial.clone(),
+            states: self.states.clone(),
+            timeout: self.timeout,
+            store: self.store.clone(),
+            on_complete: self.on_complete.clone(),
+        }
+    }
+}
+
+impl<T> WechatyPlugin<T> for Dialog<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    fn name(&self) -> String {
+        format!("Dialog({})", self.name)
+    }
+
+    fn install(&self, listener: &PluginListener<T>) {
+        let dialog = Arc::new(self.clone());
+        listener.on_message(move |payload: MessagePayload<T>, ctx| {
+            let dialog = dialog.clone();
+            async move {
+                dialog.handle(payload.message, ctx).await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::testing::{MessageContent, PuppetMock, TestBot};
+
+    fn order_dialog(path: &Path, orders: Arc<Mutex<Vec<String>>>) -> Dialog<PuppetMock> {
+        Dialog::new("order", MessageMatcher::text("order"), "address")
+            .state(
+                DialogState::new("address").slot(
+                    Slot::new("address", "What's your address?")
+                        .validate(MessageMatcher::regex(r"^\d+ .+").unwrap())
+                        .retry("Please give a street number and name"),
+                    "confirm",
+                ),
+            )
+            .state(
+                DialogState::new("confirm")
+                    .reply("Ship to {address}? (yes/no)")
+                    .on(MessageMatcher::text("yes"), "done")
+                    .on(MessageMatcher::text("no"), "address"),
+            )
+            .state(DialogState::new("done").reply("Order placed"))
+            .store(FileStore::open(path).unwrap())
+            .on_complete(move |payload: DialogPayload<PuppetMock>, _ctx| {
+                let orders = orders.clone();
+                async move { orders.lock().unwrap().push(payload.slots["address"].clone()) }
+            })
+    }
+
+    async fn started_bot(dialog: Dialog<PuppetMock>) -> TestBot {
+        let mut bot = TestBot::new();
+        bot.use_plugin(dialog).unwrap();
+        bot.login("bot").await;
+        bot.mocker().create_contact("alice", "Alice");
+        bot
+    }
+
+    async fn say(bot: &TestBot, text: &str) -> Vec<String> {
+        bot.receive_text("alice", text).await;
+        bot.take_sent_messages()
+            .into_iter()
+            .map(|sent| match sent.content {
+                MessageContent::Text { text, .. } => text,
+                content => panic!("Unexpected reply {:?}", content),
+            })
+            .collect()
+    }
+
+    #[actix_rt::test]
+    async fn dialogs_survive_restarts() {
+        let path = env::temp_dir().join(format!("wechaty-dialog-{}.json", process::id()));
+        let orders = Arc::new(Mutex::new(vec![]));
+
+        let bot = started_bot(order_dialog(&path, orders.clone())).await;
+        assert!(say(&bot, "hello").await.is_empty());
+        assert_eq!(say(&bot, "order").await, vec!["What's your address?"]);
+        assert_eq!(say(&bot, "home").await, vec!["Please give a street number and name"]);
+        assert_eq!(
+            say(&bot, "12 Main Street").await,
+            vec!["Ship to 12 Main Street? (yes/no)"]
+        );
+        bot.stop().await.unwrap();
+
+        let bot = started_bot(order_dialog(&path, orders.clone())).await;
+        assert!(say(&bot, "maybe").await.is_empty());
+        assert_eq!(say(&bot, "yes").await, vec!["Order placed"]);
+        assert_eq!(*orders.lock().unwrap(), vec!["12 Main Street"]);
+        assert!(say(&bot, "yes").await.is_empty());
+        assert!(FileStore::open(&path).unwrap().progress.is_empty());
+        let mut temp_path = path.clone().into_os_string();
+        temp_path.push(".tmp");
+        assert!(!Path::new(&temp_path).exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn slots_are_filled_in_a_single_pass() {
+        let slots: HashMap<String, String> = vec![
+            ("name".to_owned(), "{address}".to_owned()),
+            ("address".to_owned(), "12 Main Street".to_owned()),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            fill("{name} lives at {address}, {unknown} {", &slots),
+            "{address} lives at 12 Main Street, {unknown} {"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn messages_taken_by_waiting_flows_are_not_part_of_dialogs() {
+        let dialog =
+            Dialog::new("greet", MessageMatcher::text("hi"), "done").state(DialogState::new("done").reply("Hello"));
+        // The dialog is driven by a stream consumer, which also sees the messages taken by waiting flows.
+        let bot = TestBot::new();
+        bot.login("bot").await;
+        bot.mocker().create_contact("alice", "Alice");
+        let ctx = bot.get_listener().ctx();
+        let mut messages = Box::pin(bot.messages(16));
+
+        let waiting = ctx.sessions().wait(|_| true);
+        bot.receive_text("alice", "hi").await;
+        let message = messages.next().await.unwrap().unwrap();
+        assert!(!dialog.handle(message, ctx.clone()).await);
+        assert_eq!(waiting.await.unwrap().text().unwrap(), "hi");
+        assert!(bot.take_sent_messages().is_empty());
+
+        bot.mocker().receive_text("alice", "hi");
+        let message = messages.next().await.unwrap().unwrap();
+        assert!(dialog.handle(message, ctx).await);
+        bot.settle().await;
+        assert_eq!(bot.take_sent_messages().len(), 1);
+    }
+}
@@ -0,0 +1,238 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::channel::oneshot;
+use futures::future::{join_all, select, BoxFuture, Either, FutureExt, Shared};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::HandlerError;
+
+/// How a bot runs its handlers.
+///
+/// By default, a listener runs the handlers of one event at a time, in the order the events arrive. A handler
+/// waiting for a message, through `WechatyContext::wait_for_message` or a session, lets the listener go on
+/// with the next events, and the later events of its conversation are queued until it is done. In concurrent
+/// mode, events of different conversations are handled concurrently, while the events of one conversation,
+/// and the events of one kind that belong to no conversation, keep their order.
+#[derive(Clone, Debug, Default)]
+pub struct DispatchOptions {
+    pub concurrent: bool,
+    /// The most handlers running at once, across all the listeners of the bot.
+    pub max_concurrency: Option<usize>,
+    /// Give up on a handler that runs longer than this.
+    pub handler_timeout: Option<Duration>,
+}
+
+/// The dispatch options of a bot, with the permits of its concurrency cap.
+#[derive(Clone)]
+pub(crate) struct Dispatch {
+    options: DispatchOptions,
+    permits: Option<Arc<Semaphore>>,
+}
+
+impl Dispatch {
+    pub(crate) fn new(options: DispatchOptions) -> Self {
+        Self {
+            permits: options.max_concurrency.map(|max| Arc::new(Semaphore::new(max))),
+            options,
+        }
+    }
+
+    pub(crate) fn is_concurrent(&self) -> bool {
+        self.options.concurrent
+    }
+
+    /// Run a handler within the concurrency cap and the timeout, catching its panics.
+    pub(crate) async fn run<F>(&self, handler: F) -> Result<(), HandlerError>
+    where
+        F: Future<Output = Result<(), HandlerError>>,
+    {
+        let _permit: Option<OwnedSemaphorePermit> = match &self.permits {
+            Some(permits) => permits.clone().acquire_owned().await.ok(),
+            None => None,
+        };
+        let handler = AssertUnwindSafe(handler)
+            .catch_unwind()
+            .map(|result| result.unwrap_or_else(|panic| Err(HandlerError::Panicked(panic_message(panic)))));
+        match self.options.handler_timeout {
+            Some(timeout) => actix_rt::time::timeout(timeout, handler)
+                .await
+                .unwrap_or(Err(HandlerError::TimedOut(timeout))),
+            None => handler.await,
+        }
+    }
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => match panic.downcast::<&'static str>() {
+            Ok(message) => (*message).to_owned(),
+            Err(_) => "unknown cause".to_owned(),
+        },
+    }
+}
+
+/// Lets go of whoever awaits `Lanes::run_until_released`, while the job goes on.
+#[derive(Clone)]
+pub(crate) struct Release(Arc<Mutex<Option<oneshot::Sender<()>>>>);
+
+impl Release {
+    pub(crate) fn release(&self) {
+        if let Some(sender) = self.0.lock().unwrap().take() {
+            sender.send(()).ok();
+        }
+    }
+}
+
+type Tail = Shared<BoxFuture<'static, ()>>;
+
+/// Queues of handler runs, one per conversation, run concurrently with each other.
+#[derive(Clone, Default)]
+pub(crate) struct Lanes {
+    tails: Arc<Mutex<HashMap<String, Tail>>>,
+}
+
+impl Lanes {
+    /// Run `job` once the jobs pushed before to the same lane have finished.
+    pub(crate) fn push(&self, lane: String, job: BoxFuture<'static, ()>) {
+        // Read the previous tail and insert the new one under the same lock, so that concurrent pushes to a
+        // lane chain one after the other.
+        let tail = {
+            let mut tails = self.tails.lock().unwrap();
+            let previous = tails.get(&lane).cloned();
+            let tail = async move {
+                if let Some(previous) = previous {
+                    previous.await;
+                }
+                job.await;
+            }
+            .boxed()
+            .shared();
+            tails.insert(lane.clone(), tail.clone());
+            tail
+        };
+        let tails = self.tails.clone();
+        actix::spawn(async move {
+            tail.clone().await;
+            let mut tails = tails.lock().unwrap();
+            if tails.get(&lane).map_or(false, |last| last.ptr_eq(&tail)) {
+                tails.remove(&lane);
+            }
+        });
+    }
+
+    /// Run the job made by `job` until it finishes or releases its caller, then let it go on on the lane.
+    pub(crate) async fn run_until_released<F>(&self, lane: String, job: F)
+    where
+        F: FnOnce(Release) -> BoxFuture<'static, ()>,
+    {
+        let (sender, released) = oneshot::channel();
+        let mut job = job(Release(Arc::new(Mutex::new(Some(sender)))));
+        if let Either::Right(_) = select(&mut job, released).await {
+            self.push(lane, job);
+        }
+    }
+
+    /// Check if a job pushed to the lane is still running or queued.
+    pub(crate) fn is_busy(&self, lane: &str) -> bool {
+        self.tails
+            .lock()
+            .unwrap()
+            .get(lane)
+            .map_or(false, |tail| tail.peek().is_none())
+    }
+
+    /// Wait until the jobs pushed so far have finished.
+    pub(crate) async fn drain(&self) {
+        let tails: Vec<Tail> = self.tails.lock().unwrap().values().cloned().collect();
+        join_all(tails).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::testing::{PuppetMock, TestBot};
+    use crate::{EventListener, IntoContact, MessagePayload};
+
+    /// A bot logging `<sender> <text>`, after sleeping as many milliseconds as the text says, if any.
+    fn sleepy_bot(dispatch: DispatchOptions, log: &Arc<Mutex<Vec<String>>>) -> TestBot {
+        let bot = TestBot::with_dispatch(dispatch);
+        let log = log.clone();
+        bot.on_message(move |payload: MessagePayload<PuppetMock>, _ctx| {
+            let log = log.clone();
+            async move {
+                let text = payload.message.text().unwrap_or_default();
+                if let Some(ms) = text.split_whitespace().last().and_then(|ms| ms.parse().ok()) {
+                    actix_rt::time::sleep(Duration::from_millis(ms)).await;
+                }
+                let from = payload.message.from().unwrap().name().unwrap_or_default();
+                log.lock().unwrap().push(format!("{} {}", from, text));
+            }
+        });
+        bot
+    }
+
+    async fn receive_all(bot: &TestBot, messages: &[(&str, &str)]) {
+        bot.login("bot").await;
+        for (from, _) in messages {
+            bot.mocker().create_contact(from, from);
+        }
+        for (from, text) in messages {
+            bot.mocker().receive_text(from, text);
+        }
+        bot.settle().await;
+    }
+
+    #[actix_rt::test]
+    async fn conversations_run_concurrently_in_order() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let bot = sleepy_bot(
+            DispatchOptions {
+                concurrent: true,
+                handler_timeout: Some(Duration::from_millis(300)),
+                ..DispatchOptions::default()
+            },
+            &log,
+        );
+        let started = Instant::now();
+        receive_all(
+            &bot,
+            &[
+                ("alice", "slow 100"),
+                ("alice", "slower 150"),
+                ("bob", "fast"),
+                ("carol", "hang 5000"),
+            ],
+        )
+        .await;
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["bob fast", "alice slow 100", "alice slower 150"]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn concurrency_is_capped() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let bot = sleepy_bot(
+            DispatchOptions {
+                concurrent: true,
+                max_concurrency: Some(1),
+                ..DispatchOptions::default()
+            },
+            &log,
+        );
+        receive_all(&bot, &[("alice", "slow 100"), ("bob", "fast")]).await;
+        assert_eq!(*log.lock().unwrap(), vec!["alice slow 100", "bob fast"]);
+    }
+}
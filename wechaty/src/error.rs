@@ -8,6 +8,7 @@ pub enum WechatyError {
     Maybe(String),
     NotLoggedIn,
     NoPayload,
+    Timeout,
 }
 
 impl fmt::Debug for WechatyError {
@@ -24,6 +25,7 @@ impl fmt::Display for WechatyError {
             WechatyError::Maybe(maybe) => write!(fmt, "An error may have occurred: {}", maybe),
             WechatyError::NotLoggedIn => write!(fmt, "User is not logged in"),
             WechatyError::NoPayload => write!(fmt, "Operation cannot be done because the current entity does not have payload due to an unknown previous issue"),
+            WechatyError::Timeout => write!(fmt, "Operation timed out"),
         }
     }
 }
@@ -0,0 +1,232 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{error, fmt};
+
+use async_trait::async_trait;
+use futures::future::{BoxFuture, FutureExt};
+use wechaty_puppet::{PuppetEvent, PuppetImpl};
+
+use crate::WechatyContext;
+
+/// A registered handler, adapted to report its failures.
+pub type HandlerFn<T, Payload> =
+    Arc<dyn Fn(Payload, WechatyContext<T>) -> BoxFuture<'static, Result<(), HandlerError>> + Send + Sync>;
+
+/// A handler of the events with the given payload, registered with the `on_*` methods of a listener.
+///
+/// Closures and `async fn` items taking the payload and the context are handlers, as long as the future
+/// they return is `Send`. Handlers that carry configuration may be written as structs instead.
+#[async_trait]
+pub trait Handler<T, Payload>: Send + Sync + 'static
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+    Payload: Send + 'static,
+{
+    type Output: IntoHandlerResult;
+
+    async fn handle(&self, payload: Payload, ctx: WechatyContext<T>) -> Self::Output;
+}
+
+#[async_trait]
+impl<T, Payload, F, Fut> Handler<T, Payload> for F
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+    Payload: Send + 'static,
+    F: Fn(Payload, WechatyContext<T>) -> Fut + Send + Sync + 'static,
+    Fut: Future + Send + 'static,
+    Fut::Output: IntoHandlerResult,
+{
+    type Output = Fut::Output;
+
+    async fn handle(&self, payload: Payload, ctx: WechatyContext<T>) -> Self::Output {
+        self(payload, ctx).await
+    }
+}
+
+/// What a handler may return: `()`, or a `Result` whose error is reported to the `on_error` handlers.
+pub trait IntoHandlerResult {
+    fn into_handler_result(self) -> Result<(), HandlerError>;
+}
+
+impl IntoHandlerResult for () {
+    fn into_handler_result(self) -> Result<(), HandlerError> {
+        Ok(())
+    }
+}
+
+impl<E: fmt::Display> IntoHandlerResult for Result<(), E> {
+    fn into_handler_result(self) -> Result<(), HandlerError> {
+        self.map_err(|e| HandlerError::Failed(e.to_string()))
+    }
+}
+
+pub(crate) fn handler_fn<T, Payload, H>(handler: H) -> HandlerFn<T, Payload>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+    Payload: Send + 'static,
+    H: Handler<T, Payload>,
+{
+    let handler = Arc::new(handler);
+    Arc::new(move |payload, ctx| {
+        let handler = handler.clone();
+        async move { handler.handle(payload, ctx).await.into_handler_result() }.boxed()
+    })
+}
+
+/// Why a handler failed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HandlerError {
+    /// The handler returned an error.
+    Failed(String),
+    Panicked(String),
+    TimedOut(Duration),
+}
+
+impl fmt::Display for HandlerError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandlerError::Failed(e) => write!(fmt, "{}", e),
+            HandlerError::Panicked(message) => write!(fmt, "Panicked: {}", message),
+            HandlerError::TimedOut(timeout) => write!(fmt, "Timed out after {:?}", timeout),
+        }
+    }
+}
+
+impl error::Error for HandlerError {}
+
+/// A handler that failed, and the event it was run for.
+#[derive(Clone, Debug)]
+pub struct HandlerFailure {
+    pub listener: String,
+    pub event_name: &'static str,
+    /// The puppet event, `None` for lifecycle events.
+    pub event: Option<PuppetEvent>,
+    pub error: HandlerError,
+}
+
+impl fmt::Display for HandlerFailure {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "{} handler of event {} failed: {}",
+            self.listener, self.event_name, self.error
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use futures::future::{BoxFuture, FutureExt};
+    use wechaty_puppet::PuppetEvent;
+
+    use crate::testing::{PuppetMock, TestBot};
+    use crate::{
+        ErrorPayload, EventListener, Handler, HandlerError, IntoContact, MessagePayload, WechatyContext, WechatyError,
+    };
+
+    struct Echo {
+        prefix: String,
+    }
+
+    #[async_trait]
+    impl Handler<PuppetMock, MessagePayload<PuppetMock>> for Echo {
+        type Output = Result<(), WechatyError>;
+
+        async fn handle(&self, payload: MessagePayload<PuppetMock>, _ctx: WechatyContext<PuppetMock>) -> Self::Output {
+            let text = payload.message.text().unwrap_or_default();
+            let mut from = payload.message.from().unwrap();
+            from.send_text(format!("{}{}", self.prefix, text)).await?;
+            Ok(())
+        }
+    }
+
+    async fn greet(payload: MessagePayload<PuppetMock>, _ctx: WechatyContext<PuppetMock>) {
+        let mut from = payload.message.from().unwrap();
+        let name = from.name().unwrap_or_default();
+        from.send_text(format!("Hello {}", name)).await.unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn handlers_can_be_functions_closures_and_structs() {
+        let bot = TestBot::new();
+        bot.on_message(Echo {
+            prefix: "echo: ".to_owned(),
+        })
+        .on_message(greet)
+        .on_message(|payload: MessagePayload<PuppetMock>, _ctx| -> BoxFuture<'static, ()> {
+            async move {
+                let mut from = payload.message.from().unwrap();
+                from.send_text("Bye".to_owned()).await.unwrap();
+            }
+            .boxed()
+        });
+        bot.login("bot").await;
+        bot.mocker().create_contact("alice", "Alice");
+        bot.receive_text("alice", "hi").await;
+        let sent: Vec<String> = bot
+            .take_sent_messages()
+            .into_iter()
+            .map(|sent| format!("{:?}", sent.content))
+            .collect();
+        assert_eq!(sent.len(), 3);
+        assert!(sent[0].contains("echo: hi"));
+        assert!(sent[1].contains("Hello Alice"));
+        assert!(sent[2].contains("Bye"));
+    }
+
+    #[actix_rt::test]
+    async fn failures_are_routed_to_error_handlers() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let bot = TestBot::new();
+        bot.on_message(
+            |payload: MessagePayload<PuppetMock>, _ctx: WechatyContext<PuppetMock>| async move {
+                match payload.message.text().unwrap_or_default().as_str() {
+                    "fail" => Err("cannot handle this"),
+                    "panic" => panic!("handler bug"),
+                    _ => Ok(()),
+                }
+            },
+        );
+        let seen = log.clone();
+        bot.on_message(move |payload: MessagePayload<PuppetMock>, _ctx| {
+            let seen = seen.clone();
+            async move {
+                seen.lock().unwrap().push(payload.message.text().unwrap_or_default());
+            }
+        });
+        let failures = log.clone();
+        bot.on_error(move |payload: ErrorPayload, _ctx| {
+            let failures = failures.clone();
+            async move {
+                let failure = payload.failure.unwrap();
+                assert!(matches!(failure.event, Some(PuppetEvent::Message(_))));
+                let error = match failure.error {
+                    HandlerError::Failed(e) => format!("failed: {}", e),
+                    HandlerError::Panicked(message) => format!("panicked: {}", message),
+                    HandlerError::TimedOut(_) => "timed out".to_owned(),
+                };
+                failures.lock().unwrap().push(error);
+            }
+        });
+        bot.login("bot").await;
+        bot.mocker().create_contact("alice", "Alice");
+
+        bot.receive_text("alice", "fail").await;
+        bot.receive_text("alice", "panic").await;
+        bot.receive_text("alice", "hello").await;
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                "failed: cannot handle this",
+                "fail",
+                "panicked: handler bug",
+                "panic",
+                "hello"
+            ]
+        );
+    }
+}
@@ -1,6 +1,15 @@
 mod context;
+mod dialog;
+mod dispatch;
 mod error;
+mod handler;
 mod payload;
+mod plugin;
+pub mod plugins;
+mod router;
+mod session;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 mod traits;
 mod user;
 mod wechaty;
@@ -9,11 +18,20 @@ pub use actix_rt as wechaty_rt;
 pub use wechaty_puppet::{MessageType, PuppetOptions};
 
 pub use crate::context::WechatyContext;
+pub use crate::dialog::{
+    Dialog, DialogPayload, DialogProgress, DialogState, DialogStore, DialogStoreError, FileStore, MemoryStore,
+    MessageMatcher, Slot,
+};
+pub use crate::dispatch::DispatchOptions;
 pub use crate::error::WechatyError;
+pub use crate::handler::{Handler, HandlerError, HandlerFailure, IntoHandlerResult};
 pub use crate::payload::*;
+pub use crate::plugin::{PluginListener, WechatyPlugin};
+pub use crate::router::{Command, CommandArgs, CommandPayload, CommandRouter};
+pub use crate::session::{Session, SessionKey};
 pub use crate::traits::contact::IntoContact;
-pub use crate::traits::event_listener::EventListener;
 pub(crate) use crate::traits::event_listener::EventListenerInner;
+pub use crate::traits::event_listener::{EventListener, HandlerHandle};
 pub use crate::user::contact::Contact;
 pub use crate::user::contact_self::ContactSelf;
 pub(crate) use crate::user::entity::Entity;
@@ -21,7 +39,7 @@ pub use crate::user::favorite::Favorite;
 pub use crate::user::friendship::Friendship;
 pub use crate::user::image::Image;
 pub use crate::user::location::Location;
-pub use crate::user::message::Message;
+pub use crate::user::message::{Message, ReplyOptions};
 pub use crate::user::mini_program::MiniProgram;
 pub use crate::user::moment::Moment;
 pub use crate::user::money::Money;
@@ -36,17 +54,26 @@ pub mod prelude {
     pub use wechaty_puppet::{MessageType, PuppetOptions};
 
     pub use crate::context::WechatyContext;
+    pub use crate::dialog::{
+        Dialog, DialogPayload, DialogProgress, DialogState, DialogStore, DialogStoreError, FileStore, MemoryStore,
+        MessageMatcher, Slot,
+    };
+    pub use crate::dispatch::DispatchOptions;
     pub use crate::error::WechatyError;
+    pub use crate::handler::{Handler, HandlerError, HandlerFailure, IntoHandlerResult};
     pub use crate::payload::*;
+    pub use crate::plugin::{PluginListener, WechatyPlugin};
+    pub use crate::router::{Command, CommandArgs, CommandPayload, CommandRouter};
+    pub use crate::session::{Session, SessionKey};
     pub use crate::traits::contact::IntoContact;
-    pub use crate::traits::event_listener::EventListener;
+    pub use crate::traits::event_listener::{EventListener, HandlerHandle};
     pub use crate::user::contact::Contact;
     pub use crate::user::contact_self::ContactSelf;
     pub use crate::user::favorite::Favorite;
     pub use crate::user::friendship::Friendship;
     pub use crate::user::image::Image;
     pub use crate::user::location::Location;
-    pub use crate::user::message::Message;
+    pub use crate::user::message::{Message, ReplyOptions};
     pub use crate::user::mini_program::MiniProgram;
     pub use crate::user::moment::Moment;
     pub use crate::user::money::Money;
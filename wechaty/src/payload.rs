@@ -1,14 +1,24 @@
 use wechaty_puppet::{
-    EventDongPayload, EventErrorPayload, EventHeartbeatPayload, EventReadyPayload, EventResetPayload, EventScanPayload,
-    PuppetImpl,
+    EventDirtyPayload, EventDongPayload, EventHeartbeatPayload, EventRawPayload, EventReadyPayload, EventResetPayload,
+    EventScanPayload, PuppetImpl,
 };
 
+use crate::handler::HandlerFailure;
 use crate::user::contact_self::ContactSelf;
 use crate::{Contact, Friendship, Message, Room, RoomInvitation};
 
+/// A payload cached by the puppet that has gone stale.
+pub type DirtyPayload = EventDirtyPayload;
+
 pub type DongPayload = EventDongPayload;
 
-pub type ErrorPayload = EventErrorPayload;
+/// An error of the puppet, or a handler of the listener that failed.
+#[derive(Clone, Debug)]
+pub struct ErrorPayload {
+    pub data: String,
+    /// The failed handler, `None` for the errors of the puppet.
+    pub failure: Option<HandlerFailure>,
+}
 
 #[derive(Clone, Debug)]
 pub struct FriendshipPayload<T>
@@ -45,6 +55,9 @@ where
     pub message: Message<T>,
 }
 
+/// An event the puppet does not know, passed through as it came.
+pub type RawPayload = EventRawPayload;
+
 pub type ScanPayload = EventScanPayload;
 
 pub type ReadyPayload = EventReadyPayload;
@@ -92,3 +105,11 @@ where
     pub changer: Contact<T>,
     pub timestamp: u64,
 }
+
+/// Emitted once the bot has started.
+#[derive(Clone, Debug)]
+pub struct StartPayload;
+
+/// Emitted when the bot is stopping, after in-flight handlers have finished and before the puppet stops.
+#[derive(Clone, Debug)]
+pub struct StopPayload;
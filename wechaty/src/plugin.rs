@@ -0,0 +1,157 @@
+use actix::{Actor, Addr, Recipient};
+use log::error;
+use wechaty_puppet::{Puppet, PuppetEvent, PuppetImpl, UnSubscribe};
+
+use crate::traits::event_listener::{Flush, LifecycleEvent, Shutdown, EVENT_NAMES};
+use crate::{EventListener, EventListenerInner, WechatyContext};
+
+/// A reusable piece of bot behavior, installed with `Wechaty::use_plugin`.
+///
+/// The configuration of a plugin lives in the implementing type, and is usually cloned into the handlers
+/// it registers.
+pub trait WechatyPlugin<T>: Send + Sync
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    /// The name of the plugin, unique among the plugins of a bot.
+    fn name(&self) -> String;
+
+    /// Register the handlers of the plugin.
+    fn install(&self, listener: &PluginListener<T>);
+
+    /// Clean up after the handlers of the plugin have been removed.
+    fn uninstall(&self, _ctx: WechatyContext<T>) {}
+}
+
+/// The listener of a plugin, on which it registers its handlers.
+///
+/// Every plugin subscribes to the puppet on its own, under the name `Plugin(<name>)`.
+pub struct PluginListener<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    puppet: Puppet<T>,
+    listener: EventListenerInner<T>,
+    addr: Addr<EventListenerInner<T>>,
+}
+
+impl<T> PluginListener<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    pub(crate) fn new(name: &str, puppet: Puppet<T>, ctx: WechatyContext<T>) -> Self {
+        let listener = EventListenerInner::new(format!("Plugin({})", name), ctx);
+        let addr = listener.clone().start();
+        Self { puppet, listener, addr }
+    }
+
+    pub fn ctx(&self) -> WechatyContext<T> {
+        self.listener.ctx()
+    }
+
+    pub(crate) async fn flush(&self) {
+        if let Err(e) = self.addr.send(Flush).await {
+            error!("Internal error: {}", e);
+        }
+    }
+
+    pub(crate) async fn lifecycle(&self, event: LifecycleEvent) {
+        if let Err(e) = self.addr.send(event).await {
+            error!("Internal error: {}", e);
+        }
+    }
+
+    /// Unsubscribe from the puppet and stop the listener.
+    pub(crate) async fn shutdown(&self) {
+        for event_name in EVENT_NAMES.iter() {
+            if let Err(e) = self.puppet.get_unsubscribe_addr().do_send(UnSubscribe {
+                name: self.get_name(),
+                event_name,
+            }) {
+                error!(
+                    "{} failed to unsubscribe from event {}: {}",
+                    self.get_name(),
+                    event_name,
+                    e
+                );
+            }
+        }
+        self.puppet.flush().await;
+        if let Err(e) = self.addr.send(Shutdown).await {
+            error!("Internal error: {}", e);
+        }
+    }
+}
+
+impl<T> EventListener<T> for PluginListener<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    fn get_listener(&self) -> &EventListenerInner<T> {
+        &self.listener
+    }
+
+    fn get_puppet(&self) -> Puppet<T> {
+        self.puppet.clone()
+    }
+
+    fn get_addr(&self) -> Recipient<PuppetEvent> {
+        self.addr.clone().recipient()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::plugins::{AutoAcceptFriendship, DingDong};
+    use crate::testing::{MessageContent, TestBot};
+
+    #[actix_rt::test]
+    async fn plugins_handle_events_until_uninstalled() {
+        let mut bot = TestBot::new();
+        bot.use_plugin(DingDong::default())
+            .unwrap()
+            .use_plugin(AutoAcceptFriendship {
+                keyword: Some("wechaty".to_owned()),
+                greeting: Some("Welcome".to_owned()),
+            })
+            .unwrap();
+        assert!(bot.use_plugin(DingDong::default()).is_err());
+        assert_eq!(bot.plugin_names(), vec!["DingDong", "AutoAcceptFriendship"]);
+        bot.login("bot").await;
+        bot.mocker().create_contact("alice", "Alice");
+        bot.mocker().create_contact("bob", "Bob");
+
+        let message_id = bot.receive_text("alice", "ding").await;
+        bot.expect_reply_text(&message_id, "dong");
+        bot.take_sent_messages();
+
+        bot.mocker().request_friendship("alice", "Hello");
+        bot.mocker().request_friendship("bob", "I like wechaty");
+        bot.settle().await;
+        let sent = bot.take_sent_messages();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].conversation_id, "bob");
+        assert_eq!(
+            sent[0].content,
+            MessageContent::Text {
+                text: "Welcome".to_owned(),
+                mention_id_list: vec![],
+            }
+        );
+
+        bot.uninstall_plugin("DingDong").await.unwrap();
+        assert!(bot.uninstall_plugin("DingDong").await.is_err());
+        bot.receive_text("alice", "ding").await;
+        bot.expect_silence();
+    }
+
+    #[actix_rt::test]
+    async fn ding_dong_ignores_messages_without_a_sender() {
+        let mut bot = TestBot::new();
+        bot.use_plugin(DingDong::default()).unwrap();
+        bot.login("bot").await;
+
+        bot.receive_text("", "ding").await;
+        bot.expect_silence();
+    }
+}
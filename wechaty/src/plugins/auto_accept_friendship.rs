@@ -0,0 +1,49 @@
+use log::{error, info};
+use wechaty_puppet::{FriendshipType, PuppetImpl};
+
+use crate::{EventListener, FriendshipPayload, IntoContact, PluginListener, WechatyPlugin};
+
+/// Accept friendship requests, optionally only those whose greeting contains a keyword.
+#[derive(Clone, Debug, Default)]
+pub struct AutoAcceptFriendship {
+    pub keyword: Option<String>,
+    /// Sent to new friends once the request is accepted.
+    pub greeting: Option<String>,
+}
+
+impl<T> WechatyPlugin<T> for AutoAcceptFriendship
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    fn name(&self) -> String {
+        "AutoAcceptFriendship".to_owned()
+    }
+
+    fn install(&self, listener: &PluginListener<T>) {
+        let config = self.clone();
+        listener.on_friendship(move |payload: FriendshipPayload<T>, _ctx| {
+            let config = config.clone();
+            async move {
+                let mut friendship = payload.friendship;
+                if friendship.friendship_type() != Some(FriendshipType::Receive) {
+                    return;
+                }
+                if let Some(keyword) = &config.keyword {
+                    if !friendship.hello().unwrap_or_default().contains(keyword.as_str()) {
+                        info!("Friendship {} ignored, its greeting lacks the keyword", friendship);
+                        return;
+                    }
+                }
+                if let Err(e) = friendship.accept().await {
+                    error!("Failed to accept friendship {}: {}", friendship, e);
+                    return;
+                }
+                if let (Some(greeting), Some(mut contact)) = (config.greeting, friendship.contact()) {
+                    if let Err(e) = contact.send_text(greeting).await {
+                        error!("Failed to greet {}: {}", contact, e);
+                    }
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,52 @@
+use log::error;
+use wechaty_puppet::{MessageType, PuppetImpl};
+
+use crate::{EventListener, MessagePayload, PluginListener, WechatyPlugin};
+
+/// Reply `dong` to every `ding` sent directly to the bot, to check that it is alive.
+#[derive(Clone, Debug)]
+pub struct DingDong {
+    pub ding: String,
+    pub dong: String,
+}
+
+impl Default for DingDong {
+    fn default() -> Self {
+        Self {
+            ding: "ding".to_owned(),
+            dong: "dong".to_owned(),
+        }
+    }
+}
+
+impl<T> WechatyPlugin<T> for DingDong
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    fn name(&self) -> String {
+        "DingDong".to_owned()
+    }
+
+    fn install(&self, listener: &PluginListener<T>) {
+        let config = self.clone();
+        listener.on_message(move |payload: MessagePayload<T>, _ctx| {
+            let config = config.clone();
+            async move {
+                let mut message = payload.message;
+                // Messages without a sender have no one to reply to.
+                if message.from().is_none()
+                    || message.is_self()
+                    || message.is_in_room()
+                    || message.message_type() != Some(MessageType::Text)
+                {
+                    return;
+                }
+                if message.text().unwrap_or_default() == config.ding {
+                    if let Err(e) = message.reply_text(config.dong).await {
+                        error!("Failed to reply to {}: {}", message, e);
+                    }
+                }
+            }
+        });
+    }
+}
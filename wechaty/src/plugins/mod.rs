@@ -0,0 +1,9 @@
+//! Reference plugins, see `WechatyPlugin`.
+
+mod auto_accept_friendship;
+mod ding_dong;
+mod qr_terminal;
+
+pub use auto_accept_friendship::AutoAcceptFriendship;
+pub use ding_dong::DingDong;
+pub use qr_terminal::QrTerminal;
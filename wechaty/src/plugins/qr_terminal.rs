@@ -0,0 +1,37 @@
+use log::error;
+use qrcode::render::unicode::Dense1x2;
+use qrcode::QrCode;
+use wechaty_puppet::{PuppetImpl, ScanStatus};
+
+use crate::{EventListener, PluginListener, ScanPayload, WechatyPlugin};
+
+/// Print the login QR code in the terminal.
+#[derive(Clone, Debug, Default)]
+pub struct QrTerminal;
+
+impl<T> WechatyPlugin<T> for QrTerminal
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    fn name(&self) -> String {
+        "QrTerminal".to_owned()
+    }
+
+    fn install(&self, listener: &PluginListener<T>) {
+        listener.on_scan(|payload: ScanPayload, _ctx| async move {
+            if !matches!(payload.status, ScanStatus::Waiting | ScanStatus::Timeout) {
+                return;
+            }
+            if let Some(qrcode) = payload.qrcode {
+                match QrCode::new(qrcode.as_bytes()) {
+                    Ok(code) => println!("{}", code.render::<Dense1x2>().build()),
+                    Err(e) => error!("Failed to render QR code {}: {}", qrcode, e),
+                }
+                println!(
+                    "Scan the QR code above, or visit https://wechaty.js.org/qrcode/{}",
+                    qrcode
+                );
+            }
+        });
+    }
+}
@@ -0,0 +1,509 @@
+use std::any;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use log::{debug, error};
+use regex::Regex;
+use wechaty_puppet::{MessageType, PuppetImpl};
+
+use crate::user::message::MENTION_SEPARATOR;
+use crate::{EventListener, Message, MessagePayload, PluginListener, ReplyOptions, WechatyContext, WechatyPlugin};
+
+type CommandHandler<T> = Box<dyn Fn(CommandPayload<T>, WechatyContext<T>) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// What a command handler receives.
+pub struct CommandPayload<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    pub message: Message<T>,
+    pub args: CommandArgs,
+}
+
+/// The arguments of a command, already checked against its declaration.
+#[derive(Clone, Debug, Default)]
+pub struct CommandArgs {
+    values: HashMap<String, String>,
+}
+
+impl CommandArgs {
+    /// Get an argument parsed as `V`, or `None` if it was not given.
+    ///
+    /// Declared arguments have been checked to parse as their declared type.
+    pub fn get<V: FromStr>(&self, name: &str) -> Option<V> {
+        self.values.get(name).and_then(|value| value.parse().ok())
+    }
+
+    /// Get an argument as written in the message.
+    pub fn raw(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+}
+
+#[derive(Clone)]
+struct ArgSpec {
+    name: String,
+    type_name: &'static str,
+    optional: bool,
+    check: fn(&str) -> bool,
+}
+
+impl fmt::Display for ArgSpec {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.optional {
+            write!(fmt, "[{}]", self.name)
+        } else {
+            write!(fmt, "<{}>", self.name)
+        }
+    }
+}
+
+fn parses_as<V: FromStr>(value: &str) -> bool {
+    value.parse::<V>().is_ok()
+}
+
+#[derive(Clone)]
+enum Pattern {
+    Prefix,
+    Regex(Regex),
+}
+
+/// The declaration of a command: how it is recognized, its arguments and its help line.
+#[derive(Clone)]
+pub struct Command {
+    name: String,
+    description: String,
+    pattern: Pattern,
+    args: Vec<ArgSpec>,
+    usage: Option<String>,
+    require_mention: bool,
+}
+
+impl Command {
+    /// A command written as the prefix of the router followed by `name`, with arguments separated by
+    /// whitespace.
+    pub fn new(name: &str, description: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            description: description.to_owned(),
+            pattern: Pattern::Prefix,
+            args: vec![],
+            usage: None,
+            require_mention: true,
+        }
+    }
+
+    /// A command matching the whole text of a message, whose arguments are the named groups of `pattern`.
+    pub fn regex(name: &str, pattern: &str, description: &str) -> Result<Self, regex::Error> {
+        Ok(Self {
+            pattern: Pattern::Regex(Regex::new(pattern)?),
+            ..Self::new(name, description)
+        })
+    }
+
+    /// Declare a required argument parsed as `V`. Arguments of prefix commands are taken in declaration order.
+    pub fn arg<V: FromStr>(mut self, name: &str) -> Self {
+        self.args.push(ArgSpec {
+            name: name.to_owned(),
+            type_name: short_type_name::<V>(),
+            optional: false,
+            check: parses_as::<V>,
+        });
+        self
+    }
+
+    /// Declare an optional argument parsed as `V`, after the required ones.
+    pub fn optional_arg<V: FromStr>(mut self, name: &str) -> Self {
+        self.args.push(ArgSpec {
+            name: name.to_owned(),
+            type_name: short_type_name::<V>(),
+            optional: true,
+            check: parses_as::<V>,
+        });
+        self
+    }
+
+    /// Set the usage shown in the help, by default generated from the arguments.
+    pub fn usage(mut self, usage: &str) -> Self {
+        self.usage = Some(usage.to_owned());
+        self
+    }
+
+    /// Whether the bot must be mentioned for the command to run in rooms, `true` by default.
+    pub fn require_mention(mut self, require_mention: bool) -> Self {
+        self.require_mention = require_mention;
+        self
+    }
+
+    fn usage_line(&self, prefix: &str) -> String {
+        if let Some(usage) = &self.usage {
+            return usage.clone();
+        }
+        let mut usage = match &self.pattern {
+            Pattern::Prefix => format!("{}{}", prefix, self.name),
+            Pattern::Regex(_) => self.name.clone(),
+        };
+        for arg in &self.args {
+            usage.push_str(&format!(" {}", arg));
+        }
+        usage
+    }
+
+    /// Get the raw arguments if `text` invokes this command.
+    fn matches(&self, prefix: &str, text: &str) -> Option<Result<HashMap<String, String>, String>> {
+        match &self.pattern {
+            Pattern::Prefix => {
+                let mut words = text.split_whitespace();
+                if words.next()? != format!("{}{}", prefix, self.name) {
+                    return None;
+                }
+                let words: Vec<&str> = words.collect();
+                if words.len() > self.args.len() {
+                    return Some(Err("Too many arguments".to_owned()));
+                }
+                Some(Ok(self
+                    .args
+                    .iter()
+                    .zip(words)
+                    .map(|(arg, word)| (arg.name.clone(), word.to_owned()))
+                    .collect()))
+            }
+            Pattern::Regex(regex) => {
+                let captures = regex.captures(text)?;
+                Some(Ok(regex
+                    .capture_names()
+                    .flatten()
+                    .filter_map(|name| {
+                        captures
+                            .name(name)
+                            .map(|value| (name.to_owned(), value.as_str().to_owned()))
+                    })
+                    .collect()))
+            }
+        }
+    }
+
+    fn check(&self, values: HashMap<String, String>) -> Result<CommandArgs, String> {
+        for arg in &self.args {
+            match values.get(&arg.name) {
+                Some(value) if !(arg.check)(value) => {
+                    return Err(format!("Invalid {}: expected {}, got {}", arg, arg.type_name, value));
+                }
+                None if !arg.optional => return Err(format!("Missing {}", arg)),
+                _ => {}
+            }
+        }
+        Ok(CommandArgs { values })
+    }
+}
+
+fn short_type_name<V>() -> &'static str {
+    let name = any::type_name::<V>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// Drop the `@name` mentions leading a room message.
+///
+/// WeChat ends every mention with `MENTION_SEPARATOR`, as names may contain spaces. Mentions typed without
+/// it end at the first whitespace.
+fn strip_mentions(text: &str) -> &str {
+    let mut text = text.trim();
+    while text.starts_with('@') {
+        let end = text.find(MENTION_SEPARATOR).or_else(|| text.find(char::is_whitespace));
+        text = match end {
+            Some(end) => text[end..].trim_start(),
+            None => "",
+        };
+    }
+    text
+}
+
+/// Dispatch chat commands to async handlers.
+///
+/// ```ignore
+/// let mut router = CommandRouter::new();
+/// router.command(
+///     Command::new("add", "Add two numbers").arg::<i64>("a").arg::<i64>("b"),
+///     |payload: CommandPayload<PuppetService>, _ctx| async move {
+///         let sum = payload.args.get::<i64>("a").unwrap() + payload.args.get::<i64>("b").unwrap();
+///         let mut message = payload.message;
+///         message.reply_text(sum.to_string()).await.unwrap();
+///     },
+/// );
+/// bot.use_plugin(router).unwrap();
+/// ```
+///
+/// Invalid arguments are answered with the usage of the command, and `/help` lists the commands. The
+/// router is a plugin, or can be called from an `on_message` handler with `dispatch`.
+pub struct CommandRouter<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    prefix: String,
+    commands: Vec<Arc<(Command, CommandHandler<T>)>>,
+}
+
+impl<T> CommandRouter<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    /// Create a router for commands starting with `/`.
+    pub fn new() -> Self {
+        Self::with_prefix("/")
+    }
+
+    pub fn with_prefix(prefix: &str) -> Self {
+        Self {
+            prefix: prefix.to_owned(),
+            commands: vec![],
+        }
+    }
+
+    /// Register a command. Commands are tried in registration order.
+    pub fn command<F, Fut>(&mut self, command: Command, handler: F) -> &mut Self
+    where
+        F: Fn(CommandPayload<T>, WechatyContext<T>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        debug!("CommandRouter.command(name = {})", command.name);
+        let handler: CommandHandler<T> = Box::new(move |payload, ctx| Box::pin(handler(payload, ctx)));
+        self.commands.push(Arc::new((command, handler)));
+        self
+    }
+
+    /// Whether the built-in help command is served, i.e. no `help` command was registered.
+    fn builtin_help(&self) -> bool {
+        !self
+            .commands
+            .iter()
+            .any(|command| matches!(command.0.pattern, Pattern::Prefix) && command.0.name == "help")
+    }
+
+    /// Get the help text, listing the commands with their usage and description.
+    pub fn help(&self) -> String {
+        let mut lines = vec!["Commands:".to_owned()];
+        for command in &self.commands {
+            let (command, _) = &**command;
+            lines.push(format!(
+                "{} - {}",
+                command.usage_line(&self.prefix),
+                command.description
+            ));
+        }
+        if self.builtin_help() {
+            lines.push(format!("{}help - Show this help", self.prefix));
+        }
+        lines.join("\n")
+    }
+
+    /// Run the handler of the command in a message, return whether the message was a command.
+    ///
+    /// In rooms, commands are only run when the bot is mentioned, unless declared otherwise.
+    pub async fn dispatch(&self, mut message: Message<T>, ctx: WechatyContext<T>) -> bool {
+        debug!("CommandRouter.dispatch(message = {})", message);
+        if message.is_self() || message.message_type() != Some(MessageType::Text) {
+            return false;
+        }
+        let in_room = message.is_in_room();
+        let mentioned = message.mentioned_self();
+        let text = message.text().unwrap_or_default();
+        let text = strip_mentions(&text);
+
+        if self.builtin_help() && text == format!("{}help", self.prefix) {
+            if in_room && !mentioned {
+                return false;
+            }
+            reply(&mut message, self.help()).await;
+            return true;
+        }
+        for command in &self.commands {
+            let (command, handler) = &**command;
+            let values = match command.matches(&self.prefix, text) {
+                Some(values) => values,
+                None => continue,
+            };
+            // A later command may match without a mention.
+            if in_room && command.require_mention && !mentioned {
+                continue;
+            }
+            match values.and_then(|values| command.check(values)) {
+                Ok(args) => handler(CommandPayload { message, args }, ctx).await,
+                Err(e) => {
+                    let usage = format!("{}\nUsage: {}", e, command.usage_line(&self.prefix));
+                    reply(&mut message, usage).await;
+                }
+            }
+            return true;
+        }
+        false
+    }
+}
+
+/// Answer in the conversation of a message, mentioning the sender in rooms.
+async fn reply<T>(message: &mut Message<T>, text: String)
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    let options = ReplyOptions {
+        mention: true,
+        ..ReplyOptions::default()
+    };
+    if let Err(e) = message.reply_text_with(text, options).await {
+        error!("Failed to reply to {}: {}", message, e);
+    }
+}
+
+impl<T> Clone for CommandRouter<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    fn clone(&self) -> Self {
+        Self {
+            prefix: self.prefix.clone(),
+            commands: self.commands.clone(),
+        }
+    }
+}
+
+impl<T> Default for CommandRouter<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> WechatyPlugin<T> for CommandRouter<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    fn name(&self) -> String {
+        "CommandRouter".to_owned()
+    }
+
+    fn install(&self, listener: &PluginListener<T>) {
+        let router = self.clone();
+        listener.on_message(move |payload: MessagePayload<T>, ctx| {
+            let router = router.clone();
+            async move {
+                router.dispatch(payload.message, ctx).await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{PuppetMock, TestBot};
+
+    fn router() -> CommandRouter<PuppetMock> {
+        let mut router = CommandRouter::new();
+        router
+            .command(
+                Command::new("add", "Add two numbers").arg::<i64>("a").arg::<i64>("b"),
+                |payload: CommandPayload<PuppetMock>, _ctx| async move {
+                    let sum = payload.args.get::<i64>("a").unwrap() + payload.args.get::<i64>("b").unwrap();
+                    let mut message = payload.message;
+                    message.reply_text(sum.to_string()).await.unwrap();
+                },
+            )
+            .command(
+                Command::regex("weather", r"^weather in (?P<city>\w+)$", "Show the weather")
+                    .unwrap()
+                    .arg::<String>("city"),
+                |payload: CommandPayload<PuppetMock>, _ctx| async move {
+                    let city = payload.args.raw("city").unwrap().to_owned();
+                    let mut message = payload.message;
+                    message.reply_text(format!("Sunny in {}", city)).await.unwrap();
+                },
+            );
+        router
+    }
+
+    #[test]
+    fn mentions_are_stripped() {
+        assert_eq!(strip_mentions("@bot\u{2005}/help"), "/help");
+        assert_eq!(strip_mentions(" @bot @alice /add 1 2 "), "/add 1 2");
+        assert_eq!(strip_mentions("@bot"), "");
+        assert_eq!(strip_mentions("@Alice Smith\u{2005}/help"), "/help");
+        assert_eq!(strip_mentions("@Alice Smith\u{2005}@bot\u{2005}/add 1 2"), "/add 1 2");
+    }
+
+    #[actix_rt::test]
+    async fn commands_are_parsed_and_dispatched() {
+        let mut bot = TestBot::new();
+        bot.use_plugin(router()).unwrap();
+        bot.login("bot").await;
+        bot.mocker().create_contact("alice", "Alice");
+        bot.mocker().create_room("room", "Friends", &["bot", "alice"]);
+
+        let message_id = bot.receive_text("alice", "/add 1 2").await;
+        bot.expect_reply_text(&message_id, "3");
+        bot.take_sent_messages();
+
+        let message_id = bot.receive_text("alice", "/add 1 two").await;
+        bot.expect_reply_text(&message_id, "Invalid <b>: expected i64, got two\nUsage: /add <a> <b>");
+        bot.take_sent_messages();
+
+        let message_id = bot.receive_text("alice", "weather in Paris").await;
+        bot.expect_reply_text(&message_id, "Sunny in Paris");
+        bot.take_sent_messages();
+
+        bot.receive_text("alice", "hello").await;
+        bot.receive_room_text("room", "alice", "/help", &[]).await;
+        bot.expect_silence();
+
+        let message_id = bot.receive_room_text("room", "alice", "@bot /help", &["bot"]).await;
+        let reply = bot.expect_reply_text(
+            &message_id,
+            "@Alice\u{2005}Commands:\n/add <a> <b> - Add two numbers\nweather <city> - Show the weather\n/help - Show this help",
+        );
+        assert_eq!(reply.conversation_id, "room");
+    }
+
+    #[actix_rt::test]
+    async fn commands_without_a_sender_are_answered_in_the_room() {
+        let mut bot = TestBot::new();
+        bot.use_plugin(router()).unwrap();
+        bot.login("bot").await;
+        bot.mocker().create_room("room", "Friends", &["bot"]);
+
+        let message_id = bot.receive_room_text("room", "", "@bot /add 1 2", &["bot"]).await;
+        let reply = bot.expect_reply_text(&message_id, "3");
+        assert_eq!(reply.conversation_id, "room");
+    }
+
+    #[actix_rt::test]
+    async fn unmentioned_room_messages_fall_through_to_commands_without_mention() {
+        let mut router = router();
+        router.command(
+            Command::regex("guess", r"^weather in (?P<city>\w+)$", "Guess the weather")
+                .unwrap()
+                .require_mention(false),
+            |payload: CommandPayload<PuppetMock>, _ctx| async move {
+                let mut message = payload.message;
+                message.reply_text("Rainy".to_owned()).await.unwrap();
+            },
+        );
+        let mut bot = TestBot::new();
+        bot.use_plugin(router).unwrap();
+        bot.login("bot").await;
+        bot.mocker().create_contact("alice", "Alice");
+        bot.mocker().create_room("room", "Friends", &["bot", "alice"]);
+
+        let message_id = bot.receive_room_text("room", "alice", "weather in Paris", &[]).await;
+        bot.expect_reply_text(&message_id, "Rainy");
+        bot.take_sent_messages();
+
+        let message_id = bot
+            .receive_room_text("room", "alice", "@bot weather in Paris", &["bot"])
+            .await;
+        bot.expect_reply_text(&message_id, "Sunny in Paris");
+    }
+}
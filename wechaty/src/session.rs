@@ -0,0 +1,413 @@
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix::{Actor, ActorContext, Context, Handler};
+use futures::channel::oneshot;
+use futures::future::{BoxFuture, FutureExt, Shared};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use wechaty_puppet::{PuppetEvent, PuppetImpl};
+
+use crate::traits::event_listener::Shutdown;
+use crate::{Message, WechatyContext, WechatyError};
+
+/// How many routing decisions are remembered, for the listeners that have not handled a message yet.
+const ROUTE_CAPACITY: usize = 1024;
+
+type Route = Shared<BoxFuture<'static, bool>>;
+type MessageFilter<T> = Box<dyn Fn(&Message<T>) -> bool + Send>;
+type StoredState = (Instant, Box<dyn Any + Send>);
+
+struct Waiter<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    filter: MessageFilter<T>,
+    sender: oneshot::Sender<Message<T>>,
+}
+
+/// The waiting flows and session states of a bot, shared by its listeners.
+pub(crate) struct Sessions<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    waiters: Arc<Mutex<Vec<Waiter<T>>>>,
+    routes: Arc<Mutex<VecDeque<(String, Route)>>>,
+    states: Arc<Mutex<HashMap<SessionKey, StoredState>>>,
+}
+
+impl<T> Sessions<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    pub(crate) fn new() -> Self {
+        Self {
+            waiters: Arc::new(Mutex::new(vec![])),
+            routes: Arc::new(Mutex::new(VecDeque::new())),
+            states: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub(crate) fn wait<F>(&self, filter: F) -> oneshot::Receiver<Message<T>>
+    where
+        F: Fn(&Message<T>) -> bool + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        self.waiters.lock().unwrap().push(Waiter {
+            filter: Box::new(filter),
+            sender,
+        });
+        receiver
+    }
+
+    /// Hand a message to the first flow waiting for it, resolve to whether one took it.
+    ///
+    /// The decision is made once per message, every listener awaits the same one.
+    pub(crate) fn route(&self, ctx: WechatyContext<T>, message_id: String) -> Route {
+        let mut routes = self.routes.lock().unwrap();
+        if let Some((_, route)) = routes.iter().find(|(id, _)| *id == message_id) {
+            return route.clone();
+        }
+        let waiters = self.waiters.clone();
+        let id = message_id.clone();
+        let route = async move {
+            if waiters.lock().unwrap().is_empty() {
+                return false;
+            }
+            let message = match ctx.message_load(id).await {
+                Ok(message) => message,
+                Err(_) => return false,
+            };
+            let mut waiters = waiters.lock().unwrap();
+            waiters.retain(|waiter| !waiter.sender.is_canceled());
+            match waiters.iter().position(|waiter| (waiter.filter)(&message)) {
+                Some(index) => waiters.remove(index).sender.send(message).is_ok(),
+                None => false,
+            }
+        }
+        .boxed()
+        .shared();
+        routes.push_back((message_id, route.clone()));
+        if routes.len() > ROUTE_CAPACITY {
+            routes.pop_front();
+        }
+        route
+    }
+
+    fn load_state<S: Send + 'static>(&self, key: &SessionKey) -> Option<S> {
+        match self.states.lock().unwrap().remove(key) {
+            Some((expires_at, state)) if expires_at > Instant::now() => state.downcast().ok().map(|state| *state),
+            _ => None,
+        }
+    }
+
+    fn save_state<S: Send + 'static>(&self, key: SessionKey, state: S, timeout: Duration) {
+        let mut states = self.states.lock().unwrap();
+        let now = Instant::now();
+        states.retain(|_, (expires_at, _)| *expires_at > now);
+        states.insert(key, (now + timeout, Box::new(state)));
+    }
+}
+
+impl<T> Clone for Sessions<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    fn clone(&self) -> Self {
+        Self {
+            waiters: self.waiters.clone(),
+            routes: self.routes.clone(),
+            states: self.states.clone(),
+        }
+    }
+}
+
+/// The conversation and the sender a session is kept for.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SessionKey {
+    /// The room of the conversation, or the contact for direct messages.
+    pub conversation_id: String,
+    pub talker_id: String,
+}
+
+impl SessionKey {
+    /// Get the key of the session a message belongs to, `None` if its sender is unknown.
+    pub fn of<T>(message: &Message<T>) -> Option<Self>
+    where
+        T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+    {
+        let talker_id = message.from()?.id();
+        let conversation_id = match message.room() {
+            Some(room) => room.id(),
+            None => talker_id.clone(),
+        };
+        Some(Self {
+            conversation_id,
+            talker_id,
+        })
+    }
+
+    fn matches<T>(&self, message: &Message<T>) -> bool
+    where
+        T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+    {
+        SessionKey::of(message).as_ref() == Some(self)
+    }
+}
+
+/// A dialog with one sender in one conversation, holding a typed state.
+///
+/// The state is kept when the session is dropped, and expires `timeout` after that. Getting the session
+/// of the same conversation and sender again before then resumes it.
+pub struct Session<T, S>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+    S: Send + 'static,
+{
+    ctx: WechatyContext<T>,
+    key: SessionKey,
+    state: Option<S>,
+    timeout: Duration,
+}
+
+impl<T, S> Session<T, S>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+    S: Default + Send + 'static,
+{
+    pub(crate) fn new(ctx: WechatyContext<T>, key: SessionKey, timeout: Duration) -> Self {
+        debug!("Session.new(key = {:?})", key);
+        let state = ctx.sessions().load_state(&key).unwrap_or_default();
+        Self {
+            ctx,
+            key,
+            state: Some(state),
+            timeout,
+        }
+    }
+}
+
+impl<T, S> Session<T, S>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+    S: Send + 'static,
+{
+    pub fn key(&self) -> &SessionKey {
+        &self.key
+    }
+
+    pub fn state(&self) -> &S {
+        self.state.as_ref().unwrap()
+    }
+
+    pub fn state_mut(&mut self) -> &mut S {
+        self.state.as_mut().unwrap()
+    }
+
+    /// Wait for the next message of the sender in the conversation, for at most the timeout of the session.
+    ///
+    /// The message is not seen by the other handlers.
+    pub async fn next_message(&self) -> Result<Message<T>, WechatyError> {
+        debug!("Session.next_message(key = {:?})", self.key);
+        let key = self.key.clone();
+        self.ctx
+            .wait_for_message(move |message| key.matches(message), self.timeout)
+            .await
+    }
+
+    /// End the session, forgetting its state.
+    pub fn end(mut self) {
+        debug!("Session.end(key = {:?})", self.key);
+        self.state = None;
+    }
+}
+
+impl<T, S> Drop for Session<T, S>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+    S: Send + 'static,
+{
+    fn drop(&mut self) {
+        if let Some(state) = self.state.take() {
+            self.ctx.sessions().save_state(self.key.clone(), state, self.timeout);
+        }
+    }
+}
+
+/// Route incoming messages to the waiting flows.
+///
+/// Routing is done by this actor, which never waits, so that flows get their messages even while the listeners
+/// are busy.
+pub(crate) struct SessionRouter<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    ctx: WechatyContext<T>,
+}
+
+impl<T> SessionRouter<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    pub(crate) fn new(ctx: WechatyContext<T>) -> Self {
+        Self { ctx }
+    }
+}
+
+impl<T> Actor for SessionRouter<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    type Context = Context<Self>;
+}
+
+impl<T> Handler<PuppetEvent> for SessionRouter<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    type Result = ();
+
+    fn handle(&mut self, msg: PuppetEvent, _ctx: &mut Context<Self>) -> Self::Result {
+        if let PuppetEvent::Message(payload) = msg {
+            let route = self.ctx.sessions().route(self.ctx.clone(), payload.message_id);
+            actix::spawn(async move {
+                route.await;
+            });
+        }
+    }
+}
+
+impl<T> Handler<Shutdown> for SessionRouter<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    type Result = ();
+
+    fn handle(&mut self, _msg: Shutdown, ctx: &mut Context<Self>) -> Self::Result {
+        ctx.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use crate::testing::{PuppetMock, TestBot};
+    use crate::{EventListener, MessagePayload, WechatyContext};
+
+    #[actix_rt::test]
+    async fn follow_ups_go_to_the_waiting_flow() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let bot = TestBot::new();
+        let log = seen.clone();
+        bot.on_message(
+            move |payload: MessagePayload<PuppetMock>, ctx: WechatyContext<PuppetMock>| {
+                let log = log.clone();
+                async move {
+                    let mut message = payload.message;
+                    let text = message.text().unwrap_or_default();
+                    log.lock().unwrap().push(text.clone());
+                    if text != "order" {
+                        return;
+                    }
+                    let mut session = ctx.session::<usize>(&message, Duration::from_millis(200)).unwrap();
+                    *session.state_mut() += 1;
+                    let reply = format!("Order #{}, what's your address?", session.state());
+                    message.reply_text(reply).await.unwrap();
+                    match session.next_message().await {
+                        Ok(mut answer) => {
+                            let reply = format!("Shipping to {}", answer.text().unwrap_or_default());
+                            answer.reply_text(reply).await.unwrap();
+                        }
+                        Err(_) => {
+                            message.reply_text("Too late".to_owned()).await.unwrap();
+                        }
+                    }
+                }
+            },
+        );
+        bot.login("bot").await;
+        bot.mocker().create_contact("alice", "Alice");
+        bot.mocker().create_contact("bob", "Bob");
+        let texts = |bot: &TestBot| -> Vec<String> {
+            bot.take_sent_messages()
+                .into_iter()
+                .map(|sent| format!("{:?}", sent.content))
+                .collect()
+        };
+
+        bot.mocker().receive_text("alice", "order");
+        bot.mocker().receive_text("bob", "hi");
+        bot.receive_text("alice", "Main Street").await;
+        assert_eq!(*seen.lock().unwrap(), vec!["order", "hi"]);
+        let sent = texts(&bot);
+        assert_eq!(sent.len(), 2);
+        assert!(sent[0].contains("Order #1, what's your address?"));
+        assert!(sent[1].contains("Shipping to Main Street"));
+
+        bot.mocker().receive_text("alice", "order");
+        bot.receive_text("alice", "Elm Street").await;
+        assert!(texts(&bot)[0].contains("Order #2"));
+
+        actix_rt::time::sleep(Duration::from_millis(250)).await;
+        bot.receive_text("alice", "order").await;
+        let sent = texts(&bot);
+        assert!(sent[0].contains("Order #1"));
+        assert!(sent[1].contains("Too late"));
+        assert_eq!(*seen.lock().unwrap(), vec!["order", "hi", "order", "order"]);
+    }
+
+    #[actix_rt::test]
+    async fn waiting_flows_do_not_hold_up_other_conversations() {
+        let bot = TestBot::new();
+        bot.on_message(
+            |payload: MessagePayload<PuppetMock>, ctx: WechatyContext<PuppetMock>| async move {
+                let mut message = payload.message;
+                match message.text().unwrap_or_default().as_str() {
+                    "ding" => {
+                        message.reply_text("dong".to_owned()).await.unwrap();
+                    }
+                    "order" => {
+                        let session = ctx.session::<()>(&message, Duration::from_secs(5)).unwrap();
+                        message.reply_text("What's your address?".to_owned()).await.unwrap();
+                        let mut answer = session.next_message().await.unwrap();
+                        let reply = format!("Shipping to {}", answer.text().unwrap_or_default());
+                        answer.reply_text(reply).await.unwrap();
+                    }
+                    _ => {}
+                }
+            },
+        );
+        bot.login("bot").await;
+        bot.mocker().create_contact("alice", "Alice");
+        bot.mocker().create_contact("bob", "Bob");
+        let texts = |bot: &TestBot| -> Vec<String> {
+            bot.sent_messages()
+                .into_iter()
+                .map(|sent| format!("{} {:?}", sent.conversation_id, sent.content))
+                .collect()
+        };
+
+        bot.mocker().receive_text("alice", "order");
+        bot.mocker().receive_text("bob", "ding");
+        // Alice is still in the middle of her order, Bob is answered meanwhile.
+        for _ in 0..100 {
+            if texts(&bot).len() == 2 {
+                break;
+            }
+            actix_rt::time::sleep(Duration::from_millis(10)).await;
+        }
+        let sent = texts(&bot);
+        assert_eq!(sent.len(), 2);
+        assert!(sent[0].starts_with("alice") && sent[0].contains("What's your address?"));
+        assert!(sent[1].starts_with("bob") && sent[1].contains("dong"));
+
+        bot.receive_text("alice", "Main Street").await;
+        let sent = texts(&bot);
+        assert_eq!(sent.len(), 3);
+        assert!(sent[2].starts_with("alice") && sent[2].contains("Shipping to Main Street"));
+    }
+}
@@ -0,0 +1,300 @@
+//! Utilities for testing bots without WeChat.
+//!
+//! ```ignore
+//! #[actix_rt::test]
+//! async fn replies_dong() {
+//!     let bot = TestBot::new();
+//!     bot.on_message(|payload: MessagePayload<PuppetMock>, _ctx| async move {
+//!         let mut message = payload.message;
+//!         message.reply_text("dong".to_owned()).await.unwrap();
+//!     });
+//!     bot.login("bot").await;
+//!     let message_id = bot.receive_text("alice", "ding").await;
+//!     bot.expect_reply_text(&message_id, "dong");
+//! }
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{debug, error};
+use wechaty_puppet::{EventLoginPayload, EventMessagePayload, MessagePayload, MessageType, Puppet, PuppetEvent};
+pub use wechaty_puppet_mock::{MessageContent, Mocker, PuppetMock, SentMessage};
+
+use crate::{DispatchOptions, Wechaty};
+
+/// A bot running on a mock puppet.
+///
+/// It dereferences to the `Wechaty` instance to register handlers. Events fed through it are awaited until
+/// every handler has finished, so what the bot sent can be checked right away.
+pub struct TestBot {
+    bot: Wechaty<PuppetMock>,
+    puppet: Puppet<PuppetMock>,
+    mocker: Mocker,
+    /// The messages already sent when each message was received, which cannot be replies to it.
+    sent_before: Mutex<HashMap<String, HashSet<String>>>,
+}
+
+impl TestBot {
+    pub fn new() -> Self {
+        Self::with_dispatch(DispatchOptions::default())
+    }
+
+    pub fn with_dispatch(dispatch: DispatchOptions) -> Self {
+        let puppet = Puppet::new(PuppetMock::new());
+        let mocker = Mocker::new(&puppet);
+        Self {
+            bot: Wechaty::with_dispatch(puppet.clone(), dispatch),
+            puppet,
+            mocker,
+            sent_before: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Get the mocker, to populate the world of the puppet or inspect it.
+    pub fn mocker(&self) -> &Mocker {
+        &self.mocker
+    }
+
+    pub fn puppet(&self) -> Puppet<PuppetMock> {
+        self.puppet.clone()
+    }
+
+    /// Feed an event to the bot, and wait until its handlers have finished.
+    pub async fn emit(&self, event: PuppetEvent) {
+        debug!("TestBot.emit(event = {:?})", event);
+        if let Err(e) = self.puppet.self_addr().send(event).await {
+            error!("Internal error: {}", e);
+        }
+        self.settle().await;
+    }
+
+    /// Wait until the events delivered to the bot so far have been handled.
+    pub async fn settle(&self) {
+        self.bot.flush().await;
+    }
+
+    /// Log in as `contact_id`, who is added to the contacts if unknown.
+    pub async fn login(&self, contact_id: &str) {
+        if self.mocker.contact(contact_id).is_none() {
+            self.mocker.create_contact(contact_id, contact_id);
+        }
+        self.mocker.set_self_id(Some(contact_id));
+        self.emit(PuppetEvent::Login(EventLoginPayload {
+            contact_id: contact_id.to_owned(),
+        }))
+        .await;
+    }
+
+    /// Feed an incoming message to the bot, return its id.
+    pub async fn receive_message(&self, payload: MessagePayload, content: MessageContent) -> String {
+        let message_id = self.mocker.add_message(payload, content);
+        let sent_before = self.sent_messages().into_iter().map(|sent| sent.id).collect();
+        self.sent_before.lock().unwrap().insert(message_id.clone(), sent_before);
+        self.emit(PuppetEvent::Message(EventMessagePayload {
+            message_id: message_id.clone(),
+        }))
+        .await;
+        message_id
+    }
+
+    /// Feed a text from a contact to the bot, return the message id.
+    pub async fn receive_text(&self, from_id: &str, text: &str) -> String {
+        let to_id = self.puppet.self_id().unwrap_or_default();
+        self.receive_message(
+            text_payload(from_id, "", &to_id, text, &[]),
+            MessageContent::Text {
+                text: text.to_owned(),
+                mention_id_list: vec![],
+            },
+        )
+        .await
+    }
+
+    /// Feed a text from a member of a room, return the message id.
+    pub async fn receive_room_text(
+        &self,
+        room_id: &str,
+        from_id: &str,
+        text: &str,
+        mention_id_list: &[&str],
+    ) -> String {
+        self.receive_message(
+            text_payload(from_id, room_id, "", text, mention_id_list),
+            MessageContent::Text {
+                text: text.to_owned(),
+                mention_id_list: mention_id_list.iter().map(|id| id.to_string()).collect(),
+            },
+        )
+        .await
+    }
+
+    /// Get the messages sent by the bot, oldest first.
+    pub fn sent_messages(&self) -> Vec<SentMessage> {
+        self.mocker.sent_messages()
+    }
+
+    /// Get and forget the messages sent by the bot, oldest first.
+    pub fn take_sent_messages(&self) -> Vec<SentMessage> {
+        self.mocker.take_sent_messages()
+    }
+
+    /// Get the first message sent to the conversation of a message after it was received, panic if there is
+    /// none.
+    pub fn expect_reply(&self, message_id: &str) -> SentMessage {
+        let message = match self.mocker.message(message_id) {
+            Some(message) => message,
+            None => panic!("Unknown message {}", message_id),
+        };
+        let conversation_id = if message.room_id.is_empty() {
+            message.from_id
+        } else {
+            message.room_id
+        };
+        let sent_before = self
+            .sent_before
+            .lock()
+            .unwrap()
+            .get(message_id)
+            .cloned()
+            .unwrap_or_default();
+        let sent = self.sent_messages();
+        match sent
+            .iter()
+            .find(|sent| sent.conversation_id == conversation_id && !sent_before.contains(&sent.id))
+        {
+            Some(reply) => reply.clone(),
+            None => panic!(
+                "Expected a reply to message {} in {}, sent messages: {:#?}",
+                message_id, conversation_id, sent
+            ),
+        }
+    }
+
+    /// Check that the first reply to a message is the given text, and return it.
+    pub fn expect_reply_text(&self, message_id: &str, text: &str) -> SentMessage {
+        let reply = self.expect_reply(message_id);
+        match &reply.content {
+            MessageContent::Text { text: reply_text, .. } if reply_text == text => reply,
+            _ => panic!(
+                "Expected {:?} in reply to message {}, got {:?}",
+                text, message_id, reply
+            ),
+        }
+    }
+
+    /// Check that the bot has sent nothing.
+    pub fn expect_silence(&self) {
+        let sent = self.sent_messages();
+        assert!(sent.is_empty(), "Expected no message to be sent, got {:#?}", sent);
+    }
+}
+
+impl Default for TestBot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for TestBot {
+    type Target = Wechaty<PuppetMock>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.bot
+    }
+}
+
+impl DerefMut for TestBot {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.bot
+    }
+}
+
+fn text_payload(from_id: &str, room_id: &str, to_id: &str, text: &str, mention_id_list: &[&str]) -> MessagePayload {
+    MessagePayload {
+        id: String::new(),
+        filename: String::new(),
+        text: text.to_owned(),
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        message_type: MessageType::Text,
+        from_id: from_id.to_owned(),
+        mention_id_list: mention_id_list.iter().map(|id| id.to_string()).collect(),
+        room_id: room_id.to_owned(),
+        to_id: to_id.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EventListener, IntoContact, MessagePayload};
+
+    fn ding_dong_bot() -> TestBot {
+        let bot = TestBot::new();
+        bot.on_message(|payload: MessagePayload<PuppetMock>, _ctx| async move {
+            let mut message = payload.message;
+            if message.is_self() || message.text().unwrap_or_default() != "ding" {
+                return;
+            }
+            if !message.is_in_room() {
+                message.reply_text("dong".to_owned()).await.unwrap();
+            } else if message.mentioned_self() {
+                message.from().unwrap().send_text("dong".to_owned()).await.unwrap();
+            }
+        });
+        bot
+    }
+
+    #[actix_rt::test]
+    async fn bots_reply_to_contacts() {
+        let bot = ding_dong_bot();
+        bot.login("bot").await;
+        bot.mocker().create_contact("alice", "Alice");
+
+        let message_id = bot.receive_text("alice", "ding").await;
+        assert_eq!(bot.expect_reply_text(&message_id, "dong").conversation_id, "alice");
+        bot.take_sent_messages();
+
+        bot.receive_text("alice", "hello").await;
+        bot.expect_silence();
+    }
+
+    #[actix_rt::test]
+    async fn replies_are_looked_up_after_their_message() {
+        let bot = ding_dong_bot();
+        bot.login("bot").await;
+        bot.mocker().create_contact("alice", "Alice");
+
+        let first_id = bot.receive_text("alice", "ding").await;
+        let second_id = bot.receive_text("alice", "ding").await;
+        let first_reply = bot.expect_reply_text(&first_id, "dong");
+        let second_reply = bot.expect_reply_text(&second_id, "dong");
+        assert_ne!(first_reply.id, second_reply.id);
+    }
+
+    #[actix_rt::test]
+    async fn bots_see_mentions_in_rooms() {
+        let bot = ding_dong_bot();
+        bot.login("bot").await;
+        bot.mocker().create_contact("alice", "Alice");
+        bot.mocker().create_room("room", "Friends", &["bot", "alice"]);
+
+        bot.receive_room_text("room", "alice", "ding", &[]).await;
+        bot.expect_silence();
+
+        bot.receive_room_text("room", "alice", "ding", &["bot"]).await;
+        assert_eq!(
+            bot.take_sent_messages(),
+            vec![SentMessage {
+                id: "message-3".to_owned(),
+                conversation_id: "alice".to_owned(),
+                content: MessageContent::Text {
+                    text: "dong".to_owned(),
+                    mention_id_list: vec![],
+                },
+            }]
+        );
+    }
+}
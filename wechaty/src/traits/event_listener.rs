@@ -1,22 +1,48 @@
-use std::cell::RefCell;
+use std::fmt;
 use std::future::Future;
-use std::rc::Rc;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
-use actix::{Actor, ActorFuture, AtomicResponse, Context, Handler, Recipient, WrapFuture};
+use actix::{Actor, ActorContext, ActorFutureExt, AtomicResponse, Context, Handler, Recipient, WrapFuture};
+use futures::future::{BoxFuture, FutureExt};
 use log::{error, info};
 use wechaty_puppet::{
-    AsyncFnPtr, EventDongPayload, EventErrorPayload, EventFriendshipPayload, EventHeartbeatPayload, EventLoginPayload,
-    EventLogoutPayload, EventMessagePayload, EventReadyPayload, EventResetPayload, EventRoomInvitePayload,
-    EventRoomJoinPayload, EventRoomLeavePayload, EventRoomTopicPayload, EventScanPayload, IntoAsyncFnPtr, PayloadType,
-    Puppet, PuppetEvent, PuppetImpl, Subscribe,
+    EventDirtyPayload, EventDongPayload, EventErrorPayload, EventFriendshipPayload, EventHeartbeatPayload,
+    EventLoginPayload, EventLogoutPayload, EventMessagePayload, EventRawPayload, EventReadyPayload, EventResetPayload,
+    EventRoomInvitePayload, EventRoomJoinPayload, EventRoomLeavePayload, EventRoomTopicPayload, EventScanPayload,
+    PayloadType, Puppet, PuppetEvent, PuppetImpl, Subscribe, UnSubscribe,
 };
 
+use crate::dispatch::Lanes;
+use crate::handler::{handler_fn, Handler as EventHandler, HandlerFn};
 use crate::{
-    Contact, ContactSelf, DongPayload, ErrorPayload, Friendship, FriendshipPayload, HeartbeatPayload, IntoContact,
-    LoginPayload, LogoutPayload, Message, MessagePayload, ReadyPayload, ResetPayload, Room, RoomInvitation,
-    RoomInvitePayload, RoomJoinPayload, RoomLeavePayload, RoomTopicPayload, ScanPayload, WechatyContext,
+    Contact, ContactSelf, DirtyPayload, DongPayload, ErrorPayload, Friendship, FriendshipPayload, HandlerFailure,
+    HeartbeatPayload, IntoContact, LoginPayload, LogoutPayload, Message, MessagePayload, RawPayload, ReadyPayload,
+    ResetPayload, Room, RoomInvitation, RoomInvitePayload, RoomJoinPayload, RoomLeavePayload, RoomTopicPayload,
+    ScanPayload, StartPayload, StopPayload, WechatyContext,
 };
 
+/// The puppet events a listener may be subscribed to.
+pub(crate) const EVENT_NAMES: [&str; 16] = [
+    "dirty",
+    "dong",
+    "error",
+    "friendship",
+    "heartbeat",
+    "login",
+    "logout",
+    "message",
+    "raw",
+    "ready",
+    "reset",
+    "room-invite",
+    "room-join",
+    "room-leave",
+    "room-topic",
+    "scan",
+];
+
 pub trait EventListener<T>
 where
     T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
@@ -28,272 +54,606 @@ where
         self.get_listener().name.clone()
     }
 
+    /// Register a handler, subscribing the listener to its event.
     fn on_event_with_handle<Payload>(
-        &mut self,
-        handler: AsyncFnPtr<Payload, WechatyContext<T>, ()>,
+        &self,
+        handler: HandlerFn<T, Payload>,
         limit: Option<usize>,
-        handlers: Rc<RefCell<Vec<(AsyncFnPtr<Payload, WechatyContext<T>, ()>, usize)>>>,
-        event_name: &'static str,
-    ) -> (&mut Self, usize) {
-        match self.get_puppet().get_subscribe_addr().do_send(Subscribe {
-            addr: self.get_addr(),
-            name: self.get_name(),
-            event_name,
-        }) {
-            Err(e) => {
-                error!("{} failed to subscribe to event {}: {}", self.get_name(), event_name, e);
-            }
-            Ok(_) => {}
-        }
-        let counter = handlers.borrow().len();
-        let limit = match limit {
-            Some(limit) => limit,
-            None => usize::MAX,
-        };
-        handlers.borrow_mut().push((handler, limit));
-        (self, counter)
+        handlers: HandlersPtr<T, Payload>,
+    ) -> HandlerHandle<Payload> {
+        handlers.add(&self.get_puppet(), self.get_addr(), handler, limit)
+    }
+
+    /// Remove a handler, return whether it was still registered.
+    ///
+    /// The listener unsubscribes from the event once its last handler is removed.
+    fn remove_handler<Payload>(&self, handle: HandlerHandle<Payload>) -> bool {
+        self.get_listener().remove_handler(handle.event_name, handle.id)
+    }
+
+    /// Called when a payload cached by the puppet goes stale.
+    fn on_dirty<H>(&self, handler: H) -> &Self
+    where
+        H: EventHandler<T, DirtyPayload>,
+    {
+        self.on_dirty_with_handle(handler, None);
+        self
+    }
+
+    fn on_dirty_with_handle<H>(&self, handler: H, limit: Option<usize>) -> HandlerHandle<DirtyPayload>
+    where
+        H: EventHandler<T, DirtyPayload>,
+    {
+        let dirty_handlers = self.get_listener().dirty_handlers.clone();
+        self.on_event_with_handle(handler_fn(handler), limit, dirty_handlers)
+    }
+
+    fn once_dirty<H>(&self, handler: H) -> HandlerHandle<DirtyPayload>
+    where
+        H: EventHandler<T, DirtyPayload>,
+    {
+        self.on_dirty_with_handle(handler, Some(1))
     }
 
-    fn on_dong<F>(&mut self, handler: F) -> &mut Self
+    fn on_dong<H>(&self, handler: H) -> &Self
     where
-        F: IntoAsyncFnPtr<DongPayload, WechatyContext<T>, ()>,
+        H: EventHandler<T, DongPayload>,
     {
         self.on_dong_with_handle(handler, None);
         self
     }
 
-    fn on_dong_with_handle<F>(&mut self, handler: F, limit: Option<usize>) -> usize
+    fn on_dong_with_handle<H>(&self, handler: H, limit: Option<usize>) -> HandlerHandle<DongPayload>
     where
-        F: IntoAsyncFnPtr<DongPayload, WechatyContext<T>, ()>,
+        H: EventHandler<T, DongPayload>,
     {
         let dong_handlers = self.get_listener().dong_handlers.clone();
-        self.on_event_with_handle(handler.into(), limit, dong_handlers, "dong")
-            .1
+        self.on_event_with_handle(handler_fn(handler), limit, dong_handlers)
     }
 
-    fn on_error<F>(&mut self, handler: F) -> &mut Self
+    fn once_dong<H>(&self, handler: H) -> HandlerHandle<DongPayload>
     where
-        F: IntoAsyncFnPtr<ErrorPayload, WechatyContext<T>, ()>,
+        H: EventHandler<T, DongPayload>,
+    {
+        self.on_dong_with_handle(handler, Some(1))
+    }
+
+    fn on_error<H>(&self, handler: H) -> &Self
+    where
+        H: EventHandler<T, ErrorPayload>,
     {
         self.on_error_with_handle(handler, None);
         self
     }
 
-    fn on_error_with_handle<F>(&mut self, handler: F, limit: Option<usize>) -> usize
+    fn on_error_with_handle<H>(&self, handler: H, limit: Option<usize>) -> HandlerHandle<ErrorPayload>
     where
-        F: IntoAsyncFnPtr<ErrorPayload, WechatyContext<T>, ()>,
+        H: EventHandler<T, ErrorPayload>,
     {
         let error_handlers = self.get_listener().error_handlers.clone();
-        self.on_event_with_handle(handler.into(), limit, error_handlers, "error")
-            .1
+        self.on_event_with_handle(handler_fn(handler), limit, error_handlers)
     }
 
-    fn on_friendship<F>(&mut self, handler: F) -> &mut Self
+    fn once_error<H>(&self, handler: H) -> HandlerHandle<ErrorPayload>
     where
-        F: IntoAsyncFnPtr<FriendshipPayload<T>, WechatyContext<T>, ()>,
+        H: EventHandler<T, ErrorPayload>,
+    {
+        self.on_error_with_handle(handler, Some(1))
+    }
+
+    fn on_friendship<H>(&self, handler: H) -> &Self
+    where
+        H: EventHandler<T, FriendshipPayload<T>>,
     {
         self.on_friendship_with_handle(handler, None);
         self
     }
 
-    fn on_friendship_with_handle<F>(&mut self, handler: F, limit: Option<usize>) -> usize
+    fn on_friendship_with_handle<H>(&self, handler: H, limit: Option<usize>) -> HandlerHandle<FriendshipPayload<T>>
     where
-        F: IntoAsyncFnPtr<FriendshipPayload<T>, WechatyContext<T>, ()>,
+        H: EventHandler<T, FriendshipPayload<T>>,
     {
         let friendship_handlers = self.get_listener().friendship_handlers.clone();
-        self.on_event_with_handle(handler.into(), limit, friendship_handlers, "friendship")
-            .1
+        self.on_event_with_handle(handler_fn(handler), limit, friendship_handlers)
+    }
+
+    fn once_friendship<H>(&self, handler: H) -> HandlerHandle<FriendshipPayload<T>>
+    where
+        H: EventHandler<T, FriendshipPayload<T>>,
+    {
+        self.on_friendship_with_handle(handler, Some(1))
     }
 
-    fn on_heartbeat<F>(&mut self, handler: F) -> &mut Self
+    fn on_heartbeat<H>(&self, handler: H) -> &Self
     where
-        F: IntoAsyncFnPtr<HeartbeatPayload, WechatyContext<T>, ()>,
+        H: EventHandler<T, HeartbeatPayload>,
     {
         self.on_heartbeat_with_handle(handler, None);
         self
     }
 
-    fn on_heartbeat_with_handle<F>(&mut self, handler: F, limit: Option<usize>) -> usize
+    fn on_heartbeat_with_handle<H>(&self, handler: H, limit: Option<usize>) -> HandlerHandle<HeartbeatPayload>
     where
-        F: IntoAsyncFnPtr<HeartbeatPayload, WechatyContext<T>, ()>,
+        H: EventHandler<T, HeartbeatPayload>,
     {
         let heartbeat_handlers = self.get_listener().heartbeat_handlers.clone();
-        self.on_event_with_handle(handler.into(), limit, heartbeat_handlers, "heartbeat")
-            .1
+        self.on_event_with_handle(handler_fn(handler), limit, heartbeat_handlers)
+    }
+
+    fn once_heartbeat<H>(&self, handler: H) -> HandlerHandle<HeartbeatPayload>
+    where
+        H: EventHandler<T, HeartbeatPayload>,
+    {
+        self.on_heartbeat_with_handle(handler, Some(1))
     }
 
-    fn on_login<F>(&mut self, handler: F) -> &mut Self
+    fn on_login<H>(&self, handler: H) -> &Self
     where
-        F: IntoAsyncFnPtr<LoginPayload<T>, WechatyContext<T>, ()>,
+        H: EventHandler<T, LoginPayload<T>>,
     {
         self.on_login_with_handle(handler, None);
         self
     }
 
-    fn on_login_with_handle<F>(&mut self, handler: F, limit: Option<usize>) -> usize
+    fn on_login_with_handle<H>(&self, handler: H, limit: Option<usize>) -> HandlerHandle<LoginPayload<T>>
     where
-        F: IntoAsyncFnPtr<LoginPayload<T>, WechatyContext<T>, ()>,
+        H: EventHandler<T, LoginPayload<T>>,
     {
         let login_handlers = self.get_listener().login_handlers.clone();
-        self.on_event_with_handle(handler.into(), limit, login_handlers, "login")
-            .1
+        self.on_event_with_handle(handler_fn(handler), limit, login_handlers)
     }
 
-    fn on_logout<F>(&mut self, handler: F) -> &mut Self
+    fn once_login<H>(&self, handler: H) -> HandlerHandle<LoginPayload<T>>
     where
-        F: IntoAsyncFnPtr<LogoutPayload<T>, WechatyContext<T>, ()>,
+        H: EventHandler<T, LoginPayload<T>>,
+    {
+        self.on_login_with_handle(handler, Some(1))
+    }
+
+    fn on_logout<H>(&self, handler: H) -> &Self
+    where
+        H: EventHandler<T, LogoutPayload<T>>,
     {
         self.on_logout_with_handle(handler, None);
         self
     }
 
-    fn on_logout_with_handle<F>(&mut self, handler: F, limit: Option<usize>) -> usize
+    fn on_logout_with_handle<H>(&self, handler: H, limit: Option<usize>) -> HandlerHandle<LogoutPayload<T>>
     where
-        F: IntoAsyncFnPtr<LogoutPayload<T>, WechatyContext<T>, ()>,
+        H: EventHandler<T, LogoutPayload<T>>,
     {
         let logout_handlers = self.get_listener().logout_handlers.clone();
-        self.on_event_with_handle(handler.into(), limit, logout_handlers, "logout")
-            .1
+        self.on_event_with_handle(handler_fn(handler), limit, logout_handlers)
+    }
+
+    fn once_logout<H>(&self, handler: H) -> HandlerHandle<LogoutPayload<T>>
+    where
+        H: EventHandler<T, LogoutPayload<T>>,
+    {
+        self.on_logout_with_handle(handler, Some(1))
     }
 
-    fn on_message<F>(&mut self, handler: F) -> &mut Self
+    fn on_message<H>(&self, handler: H) -> &Self
     where
-        F: IntoAsyncFnPtr<MessagePayload<T>, WechatyContext<T>, ()>,
+        H: EventHandler<T, MessagePayload<T>>,
     {
         self.on_message_with_handle(handler, None);
         self
     }
 
-    fn on_message_with_handle<F>(&mut self, handler: F, limit: Option<usize>) -> usize
+    fn on_message_with_handle<H>(&self, handler: H, limit: Option<usize>) -> HandlerHandle<MessagePayload<T>>
     where
-        F: IntoAsyncFnPtr<MessagePayload<T>, WechatyContext<T>, ()>,
+        H: EventHandler<T, MessagePayload<T>>,
     {
         let message_handlers = self.get_listener().message_handlers.clone();
-        self.on_event_with_handle(handler.into(), limit, message_handlers, "message")
-            .1
+        self.on_event_with_handle(handler_fn(handler), limit, message_handlers)
+    }
+
+    fn once_message<H>(&self, handler: H) -> HandlerHandle<MessagePayload<T>>
+    where
+        H: EventHandler<T, MessagePayload<T>>,
+    {
+        self.on_message_with_handle(handler, Some(1))
     }
 
-    fn on_ready<F>(&mut self, handler: F) -> &mut Self
+    /// Called with the events the puppet passes through without knowing them.
+    fn on_raw<H>(&self, handler: H) -> &Self
     where
-        F: IntoAsyncFnPtr<ReadyPayload, WechatyContext<T>, ()>,
+        H: EventHandler<T, RawPayload>,
+    {
+        self.on_raw_with_handle(handler, None);
+        self
+    }
+
+    fn on_raw_with_handle<H>(&self, handler: H, limit: Option<usize>) -> HandlerHandle<RawPayload>
+    where
+        H: EventHandler<T, RawPayload>,
+    {
+        let raw_handlers = self.get_listener().raw_handlers.clone();
+        self.on_event_with_handle(handler_fn(handler), limit, raw_handlers)
+    }
+
+    fn once_raw<H>(&self, handler: H) -> HandlerHandle<RawPayload>
+    where
+        H: EventHandler<T, RawPayload>,
+    {
+        self.on_raw_with_handle(handler, Some(1))
+    }
+
+    fn on_ready<H>(&self, handler: H) -> &Self
+    where
+        H: EventHandler<T, ReadyPayload>,
     {
         self.on_ready_with_handle(handler, None);
         self
     }
 
-    fn on_ready_with_handle<F>(&mut self, handler: F, limit: Option<usize>) -> usize
+    fn on_ready_with_handle<H>(&self, handler: H, limit: Option<usize>) -> HandlerHandle<ReadyPayload>
     where
-        F: IntoAsyncFnPtr<ReadyPayload, WechatyContext<T>, ()>,
+        H: EventHandler<T, ReadyPayload>,
     {
         let ready_handlers = self.get_listener().ready_handlers.clone();
-        self.on_event_with_handle(handler.into(), limit, ready_handlers, "ready")
-            .1
+        self.on_event_with_handle(handler_fn(handler), limit, ready_handlers)
+    }
+
+    fn once_ready<H>(&self, handler: H) -> HandlerHandle<ReadyPayload>
+    where
+        H: EventHandler<T, ReadyPayload>,
+    {
+        self.on_ready_with_handle(handler, Some(1))
     }
 
-    fn on_reset<F>(&mut self, handler: F) -> &mut Self
+    fn on_reset<H>(&self, handler: H) -> &Self
     where
-        F: IntoAsyncFnPtr<ResetPayload, WechatyContext<T>, ()>,
+        H: EventHandler<T, ResetPayload>,
     {
         self.on_reset_with_handle(handler, None);
         self
     }
 
-    fn on_reset_with_handle<F>(&mut self, handler: F, limit: Option<usize>) -> usize
+    fn on_reset_with_handle<H>(&self, handler: H, limit: Option<usize>) -> HandlerHandle<ResetPayload>
     where
-        F: IntoAsyncFnPtr<ResetPayload, WechatyContext<T>, ()>,
+        H: EventHandler<T, ResetPayload>,
     {
         let reset_handlers = self.get_listener().reset_handlers.clone();
-        self.on_event_with_handle(handler.into(), limit, reset_handlers, "reset")
-            .1
+        self.on_event_with_handle(handler_fn(handler), limit, reset_handlers)
     }
 
-    fn on_room_invite<F>(&mut self, handler: F) -> &mut Self
+    fn once_reset<H>(&self, handler: H) -> HandlerHandle<ResetPayload>
     where
-        F: IntoAsyncFnPtr<RoomInvitePayload<T>, WechatyContext<T>, ()>,
+        H: EventHandler<T, ResetPayload>,
+    {
+        self.on_reset_with_handle(handler, Some(1))
+    }
+
+    fn on_room_invite<H>(&self, handler: H) -> &Self
+    where
+        H: EventHandler<T, RoomInvitePayload<T>>,
     {
         self.on_room_invite_with_handle(handler, None);
         self
     }
 
-    fn on_room_invite_with_handle<F>(&mut self, handler: F, limit: Option<usize>) -> usize
+    fn on_room_invite_with_handle<H>(&self, handler: H, limit: Option<usize>) -> HandlerHandle<RoomInvitePayload<T>>
     where
-        F: IntoAsyncFnPtr<RoomInvitePayload<T>, WechatyContext<T>, ()>,
+        H: EventHandler<T, RoomInvitePayload<T>>,
     {
         let room_invite_handlers = self.get_listener().room_invite_handlers.clone();
-        self.on_event_with_handle(handler.into(), limit, room_invite_handlers, "room-invite")
-            .1
+        self.on_event_with_handle(handler_fn(handler), limit, room_invite_handlers)
     }
 
-    fn on_room_join<F>(&mut self, handler: F) -> &mut Self
+    fn once_room_invite<H>(&self, handler: H) -> HandlerHandle<RoomInvitePayload<T>>
     where
-        F: IntoAsyncFnPtr<RoomJoinPayload<T>, WechatyContext<T>, ()>,
+        H: EventHandler<T, RoomInvitePayload<T>>,
+    {
+        self.on_room_invite_with_handle(handler, Some(1))
+    }
+
+    fn on_room_join<H>(&self, handler: H) -> &Self
+    where
+        H: EventHandler<T, RoomJoinPayload<T>>,
     {
         self.on_room_join_with_handle(handler, None);
         self
     }
 
-    fn on_room_join_with_handle<F>(&mut self, handler: F, limit: Option<usize>) -> usize
+    fn on_room_join_with_handle<H>(&self, handler: H, limit: Option<usize>) -> HandlerHandle<RoomJoinPayload<T>>
     where
-        F: IntoAsyncFnPtr<RoomJoinPayload<T>, WechatyContext<T>, ()>,
+        H: EventHandler<T, RoomJoinPayload<T>>,
     {
         let room_join_handlers = self.get_listener().room_join_handlers.clone();
-        self.on_event_with_handle(handler.into(), limit, room_join_handlers, "room-join")
-            .1
+        self.on_event_with_handle(handler_fn(handler), limit, room_join_handlers)
+    }
+
+    fn once_room_join<H>(&self, handler: H) -> HandlerHandle<RoomJoinPayload<T>>
+    where
+        H: EventHandler<T, RoomJoinPayload<T>>,
+    {
+        self.on_room_join_with_handle(handler, Some(1))
     }
 
-    fn on_room_leave<F>(&mut self, handler: F) -> &mut Self
+    fn on_room_leave<H>(&self, handler: H) -> &Self
     where
-        F: IntoAsyncFnPtr<RoomLeavePayload<T>, WechatyContext<T>, ()>,
+        H: EventHandler<T, RoomLeavePayload<T>>,
     {
         self.on_room_leave_with_handle(handler, None);
         self
     }
 
-    fn on_room_leave_with_handle<F>(&mut self, handler: F, limit: Option<usize>) -> usize
+    fn on_room_leave_with_handle<H>(&self, handler: H, limit: Option<usize>) -> HandlerHandle<RoomLeavePayload<T>>
     where
-        F: IntoAsyncFnPtr<RoomLeavePayload<T>, WechatyContext<T>, ()>,
+        H: EventHandler<T, RoomLeavePayload<T>>,
     {
         let room_leave_handlers = self.get_listener().room_leave_handlers.clone();
-        self.on_event_with_handle(handler.into(), limit, room_leave_handlers, "room-leave")
-            .1
+        self.on_event_with_handle(handler_fn(handler), limit, room_leave_handlers)
+    }
+
+    fn once_room_leave<H>(&self, handler: H) -> HandlerHandle<RoomLeavePayload<T>>
+    where
+        H: EventHandler<T, RoomLeavePayload<T>>,
+    {
+        self.on_room_leave_with_handle(handler, Some(1))
     }
 
-    fn on_room_topic<F>(&mut self, handler: F) -> &mut Self
+    fn on_room_topic<H>(&self, handler: H) -> &Self
     where
-        F: IntoAsyncFnPtr<RoomTopicPayload<T>, WechatyContext<T>, ()>,
+        H: EventHandler<T, RoomTopicPayload<T>>,
     {
         self.on_room_topic_with_handle(handler, None);
         self
     }
 
-    fn on_room_topic_with_handle<F>(&mut self, handler: F, limit: Option<usize>) -> usize
+    fn on_room_topic_with_handle<H>(&self, handler: H, limit: Option<usize>) -> HandlerHandle<RoomTopicPayload<T>>
     where
-        F: IntoAsyncFnPtr<RoomTopicPayload<T>, WechatyContext<T>, ()>,
+        H: EventHandler<T, RoomTopicPayload<T>>,
     {
         let room_topic_handlers = self.get_listener().room_topic_handlers.clone();
-        self.on_event_with_handle(handler.into(), limit, room_topic_handlers, "room-topic")
-            .1
+        self.on_event_with_handle(handler_fn(handler), limit, room_topic_handlers)
     }
 
-    fn on_scan<F>(&mut self, handler: F) -> &mut Self
+    fn once_room_topic<H>(&self, handler: H) -> HandlerHandle<RoomTopicPayload<T>>
     where
-        F: IntoAsyncFnPtr<ScanPayload, WechatyContext<T>, ()>,
+        H: EventHandler<T, RoomTopicPayload<T>>,
+    {
+        self.on_room_topic_with_handle(handler, Some(1))
+    }
+
+    fn on_scan<H>(&self, handler: H) -> &Self
+    where
+        H: EventHandler<T, ScanPayload>,
     {
         self.on_scan_with_handle(handler, None);
         self
     }
 
-    fn on_scan_with_handle<F>(&mut self, handler: F, limit: Option<usize>) -> usize
+    fn on_scan_with_handle<H>(&self, handler: H, limit: Option<usize>) -> HandlerHandle<ScanPayload>
     where
-        F: IntoAsyncFnPtr<ScanPayload, WechatyContext<T>, ()>,
+        H: EventHandler<T, ScanPayload>,
     {
         let scan_handlers = self.get_listener().scan_handlers.clone();
-        self.on_event_with_handle(handler.into(), limit, scan_handlers, "scan")
-            .1
+        self.on_event_with_handle(handler_fn(handler), limit, scan_handlers)
+    }
+
+    fn once_scan<H>(&self, handler: H) -> HandlerHandle<ScanPayload>
+    where
+        H: EventHandler<T, ScanPayload>,
+    {
+        self.on_scan_with_handle(handler, Some(1))
+    }
+
+    fn on_start<H>(&self, handler: H) -> &Self
+    where
+        H: EventHandler<T, StartPayload>,
+    {
+        self.on_start_with_handle(handler, None);
+        self
+    }
+
+    /// Lifecycle events come from Wechaty itself, so there is no puppet event to subscribe to.
+    fn on_start_with_handle<H>(&self, handler: H, limit: Option<usize>) -> HandlerHandle<StartPayload>
+    where
+        H: EventHandler<T, StartPayload>,
+    {
+        let start_handlers = self.get_listener().start_handlers.clone();
+        self.on_event_with_handle(handler_fn(handler), limit, start_handlers)
+    }
+
+    fn once_start<H>(&self, handler: H) -> HandlerHandle<StartPayload>
+    where
+        H: EventHandler<T, StartPayload>,
+    {
+        self.on_start_with_handle(handler, Some(1))
+    }
+
+    fn on_stop<H>(&self, handler: H) -> &Self
+    where
+        H: EventHandler<T, StopPayload>,
+    {
+        self.on_stop_with_handle(handler, None);
+        self
+    }
+
+    fn on_stop_with_handle<H>(&self, handler: H, limit: Option<usize>) -> HandlerHandle<StopPayload>
+    where
+        H: EventHandler<T, StopPayload>,
+    {
+        let stop_handlers = self.get_listener().stop_handlers.clone();
+        self.on_event_with_handle(handler_fn(handler), limit, stop_handlers)
+    }
+
+    fn once_stop<H>(&self, handler: H) -> HandlerHandle<StopPayload>
+    where
+        H: EventHandler<T, StopPayload>,
+    {
+        self.on_stop_with_handle(handler, Some(1))
+    }
+}
+
+/// The ids of handlers are unique across listeners, so that a handle only ever matches its own handler.
+static NEXT_HANDLER_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A handler registered on a listener, to remove it with `remove_handler`.
+pub struct HandlerHandle<Payload> {
+    id: usize,
+    event_name: &'static str,
+    payload: PhantomData<fn(Payload)>,
+}
+
+impl<Payload> HandlerHandle<Payload> {
+    pub fn event_name(&self) -> &'static str {
+        self.event_name
     }
 }
 
-type HandlersPtr<T, Payload> = Rc<RefCell<Vec<(AsyncFnPtr<Payload, WechatyContext<T>, ()>, usize)>>>;
+impl<Payload> Clone for HandlerHandle<Payload> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<Payload> Copy for HandlerHandle<Payload> {}
+
+impl<Payload> PartialEq for HandlerHandle<Payload> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && self.event_name == other.event_name
+    }
+}
+
+impl<Payload> fmt::Debug for HandlerHandle<Payload> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "HandlerHandle({}, {})", self.event_name, self.id)
+    }
+}
+
+pub struct RegisteredHandler<T, Payload>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    id: usize,
+    handler: HandlerFn<T, Payload>,
+    remaining: usize,
+}
+
+/// The handlers of a listener for one event.
+pub struct Handlers<T, Payload>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    listener_name: String,
+    event_name: &'static str,
+    entries: Mutex<Vec<RegisteredHandler<T, Payload>>>,
+    lanes: Lanes,
+    /// The error handlers of the listener, which failures are reported to.
+    errors: Option<HandlersPtr<T, ErrorPayload>>,
+}
+
+pub type HandlersPtr<T, Payload> = Arc<Handlers<T, Payload>>;
+
+impl<T, Payload> Handlers<T, Payload>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    fn new(
+        listener_name: &str,
+        event_name: &'static str,
+        lanes: &Lanes,
+        errors: Option<&HandlersPtr<T, ErrorPayload>>,
+    ) -> HandlersPtr<T, Payload> {
+        Arc::new(Self {
+            listener_name: listener_name.to_owned(),
+            event_name,
+            entries: Mutex::new(vec![]),
+            lanes: lanes.clone(),
+            errors: errors.cloned(),
+        })
+    }
+
+    /// Subscribe to the event and register the handler under the same lock as removals, so that the
+    /// subscription is sent after any unsubscription of a handler removed before.
+    fn add(
+        &self,
+        puppet: &Puppet<T>,
+        addr: Recipient<PuppetEvent>,
+        handler: HandlerFn<T, Payload>,
+        limit: Option<usize>,
+    ) -> HandlerHandle<Payload> {
+        let id = NEXT_HANDLER_ID.fetch_add(1, Ordering::Relaxed);
+        let mut entries = self.entries.lock().unwrap();
+        self.subscribe(puppet, addr);
+        entries.push(RegisteredHandler {
+            id,
+            handler,
+            remaining: limit.unwrap_or(usize::MAX),
+        });
+        HandlerHandle {
+            id,
+            event_name: self.event_name,
+            payload: PhantomData,
+        }
+    }
+
+    fn remove(&self, ctx: &WechatyContext<T>, id: usize) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        let len = entries.len();
+        entries.retain(|entry| entry.id != id);
+        let removed = entries.len() < len;
+        if removed && entries.is_empty() {
+            self.unsubscribe(ctx);
+        }
+        removed
+    }
+
+    /// Count a run for every handler and drop the exhausted ones, return the handlers to run.
+    fn take_due(&self, ctx: &WechatyContext<T>) -> Vec<HandlerFn<T, Payload>> {
+        let mut entries = self.entries.lock().unwrap();
+        let due: Vec<_> = entries
+            .iter_mut()
+            .filter(|entry| entry.remaining > 0)
+            .map(|entry| {
+                entry.remaining -= 1;
+                entry.handler.clone()
+            })
+            .collect();
+        let len = entries.len();
+        entries.retain(|entry| entry.remaining > 0);
+        if entries.len() < len && entries.is_empty() {
+            self.unsubscribe(ctx);
+        }
+        due
+    }
+
+    /// Lifecycle events do not come from the puppet, so there is nothing to subscribe to.
+    fn subscribe(&self, puppet: &Puppet<T>, addr: Recipient<PuppetEvent>) {
+        if !EVENT_NAMES.contains(&self.event_name) {
+            return;
+        }
+        if let Err(e) = puppet.get_subscribe_addr().do_send(Subscribe {
+            addr,
+            name: self.listener_name.clone(),
+            event_name: self.event_name,
+        }) {
+            error!(
+                "{} failed to subscribe to event {}: {}",
+                self.listener_name, self.event_name, e
+            );
+        }
+    }
+
+    fn unsubscribe(&self, ctx: &WechatyContext<T>) {
+        if !EVENT_NAMES.contains(&self.event_name) {
+            return;
+        }
+        if let Err(e) = ctx.puppet().get_unsubscribe_addr().do_send(UnSubscribe {
+            name: self.listener_name.clone(),
+            event_name: self.event_name,
+        }) {
+            error!(
+                "{} failed to unsubscribe from event {}: {}",
+                self.listener_name, self.event_name, e
+            );
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct EventListenerInner<T>
@@ -302,6 +662,7 @@ where
 {
     name: String,
     ctx: WechatyContext<T>,
+    dirty_handlers: HandlersPtr<T, DirtyPayload>,
     dong_handlers: HandlersPtr<T, DongPayload>,
     error_handlers: HandlersPtr<T, ErrorPayload>,
     friendship_handlers: HandlersPtr<T, FriendshipPayload<T>>,
@@ -309,6 +670,7 @@ where
     login_handlers: HandlersPtr<T, LoginPayload<T>>,
     logout_handlers: HandlersPtr<T, LogoutPayload<T>>,
     message_handlers: HandlersPtr<T, MessagePayload<T>>,
+    raw_handlers: HandlersPtr<T, RawPayload>,
     ready_handlers: HandlersPtr<T, ReadyPayload>,
     reset_handlers: HandlersPtr<T, ResetPayload>,
     room_invite_handlers: HandlersPtr<T, RoomInvitePayload<T>>,
@@ -316,6 +678,9 @@ where
     room_leave_handlers: HandlersPtr<T, RoomLeavePayload<T>>,
     room_topic_handlers: HandlersPtr<T, RoomTopicPayload<T>>,
     scan_handlers: HandlersPtr<T, ScanPayload>,
+    start_handlers: HandlersPtr<T, StartPayload>,
+    stop_handlers: HandlersPtr<T, StopPayload>,
+    lanes: Lanes,
 }
 
 impl<T> Actor for EventListenerInner<T>
@@ -342,6 +707,11 @@ where
     fn handle(&mut self, msg: PuppetEvent, _ctx: &mut Context<Self>) -> Self::Result {
         info!("{} receives puppet event: {:?}", self.name.clone(), msg);
         match msg {
+            PuppetEvent::Dirty(payload) => AtomicResponse::new(Box::pin(
+                async {}
+                    .into_actor(self)
+                    .then(move |_, this, _| this.trigger_dirty_handlers(payload).into_actor(this)),
+            )),
             PuppetEvent::Dong(payload) => AtomicResponse::new(Box::pin(
                 async {}
                     .into_actor(self)
@@ -362,27 +732,26 @@ where
                     .into_actor(self)
                     .then(move |_, this, _| this.trigger_heartbeat_handlers(payload).into_actor(this)),
             )),
-            PuppetEvent::Login(payload) => {
-                self.ctx.set_id(payload.contact_id.clone());
-                AtomicResponse::new(Box::pin(
-                    async {}
-                        .into_actor(self)
-                        .then(move |_, this, _| this.trigger_login_handlers(payload).into_actor(this)),
-                ))
-            }
-            PuppetEvent::Logout(payload) => {
-                self.ctx.clear_id();
-                AtomicResponse::new(Box::pin(
-                    async {}
-                        .into_actor(self)
-                        .then(move |_, this, _| this.trigger_logout_handlers(payload).into_actor(this)),
-                ))
-            }
+            PuppetEvent::Login(payload) => AtomicResponse::new(Box::pin(
+                async {}
+                    .into_actor(self)
+                    .then(move |_, this, _| this.trigger_login_handlers(payload).into_actor(this)),
+            )),
+            PuppetEvent::Logout(payload) => AtomicResponse::new(Box::pin(
+                async {}
+                    .into_actor(self)
+                    .then(move |_, this, _| this.trigger_logout_handlers(payload).into_actor(this)),
+            )),
             PuppetEvent::Message(payload) => AtomicResponse::new(Box::pin(
                 async {}
                     .into_actor(self)
                     .then(move |_, this, _| this.trigger_message_handlers(payload).into_actor(this)),
             )),
+            PuppetEvent::Raw(payload) => AtomicResponse::new(Box::pin(
+                async {}
+                    .into_actor(self)
+                    .then(move |_, this, _| this.trigger_raw_handlers(payload).into_actor(this)),
+            )),
             PuppetEvent::Ready(payload) => AtomicResponse::new(Box::pin(
                 async {}
                     .into_actor(self)
@@ -423,87 +792,253 @@ where
     }
 }
 
+/// A message whose response arrives once the events received before it have been handled, including the
+/// handlers queued in concurrent mode.
+#[derive(actix::Message)]
+#[rtype("()")]
+pub(crate) struct Flush;
+
+impl<T> Handler<Flush> for EventListenerInner<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    type Result = AtomicResponse<Self, ()>;
+
+    fn handle(&mut self, _msg: Flush, _ctx: &mut Context<Self>) -> Self::Result {
+        let lanes = self.lanes.clone();
+        AtomicResponse::new(Box::pin(async move { lanes.drain().await }.into_actor(self)))
+    }
+}
+
+/// Events of the lifecycle of a bot, which do not come from the puppet.
+#[derive(actix::Message, Debug)]
+#[rtype("()")]
+pub(crate) enum LifecycleEvent {
+    Start,
+    Stop,
+}
+
+impl<T> Handler<LifecycleEvent> for EventListenerInner<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    type Result = AtomicResponse<Self, ()>;
+
+    fn handle(&mut self, msg: LifecycleEvent, _ctx: &mut Context<Self>) -> Self::Result {
+        info!("{} receives lifecycle event: {:?}", self.name, msg);
+        let ctx = self.ctx.clone();
+        match msg {
+            LifecycleEvent::Start => {
+                let handlers = self.start_handlers.clone();
+                AtomicResponse::new(Box::pin(
+                    EventListenerInner::<T>::trigger_handlers(ctx, StartPayload, handlers, None).into_actor(self),
+                ))
+            }
+            LifecycleEvent::Stop => {
+                let handlers = self.stop_handlers.clone();
+                AtomicResponse::new(Box::pin(
+                    EventListenerInner::<T>::trigger_handlers(ctx, StopPayload, handlers, None).into_actor(self),
+                ))
+            }
+        }
+    }
+}
+
+/// Stop the listener, once the events received before have been handled.
+#[derive(actix::Message)]
+#[rtype("()")]
+pub(crate) struct Shutdown;
+
+impl<T> Handler<Shutdown> for EventListenerInner<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    type Result = ();
+
+    fn handle(&mut self, _msg: Shutdown, ctx: &mut Context<Self>) -> Self::Result {
+        ctx.stop();
+    }
+}
+
 impl<T> EventListenerInner<T>
 where
     T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
 {
     pub(crate) fn new(name: String, ctx: WechatyContext<T>) -> Self {
+        let lanes = Lanes::default();
+        let errors = Handlers::new(&name, "error", &lanes, None);
         Self {
+            dirty_handlers: Handlers::new(&name, "dirty", &lanes, Some(&errors)),
+            dong_handlers: Handlers::new(&name, "dong", &lanes, Some(&errors)),
+            error_handlers: errors.clone(),
+            friendship_handlers: Handlers::new(&name, "friendship", &lanes, Some(&errors)),
+            heartbeat_handlers: Handlers::new(&name, "heartbeat", &lanes, Some(&errors)),
+            login_handlers: Handlers::new(&name, "login", &lanes, Some(&errors)),
+            logout_handlers: Handlers::new(&name, "logout", &lanes, Some(&errors)),
+            message_handlers: Handlers::new(&name, "message", &lanes, Some(&errors)),
+            raw_handlers: Handlers::new(&name, "raw", &lanes, Some(&errors)),
+            ready_handlers: Handlers::new(&name, "ready", &lanes, Some(&errors)),
+            reset_handlers: Handlers::new(&name, "reset", &lanes, Some(&errors)),
+            room_invite_handlers: Handlers::new(&name, "room-invite", &lanes, Some(&errors)),
+            room_join_handlers: Handlers::new(&name, "room-join", &lanes, Some(&errors)),
+            room_leave_handlers: Handlers::new(&name, "room-leave", &lanes, Some(&errors)),
+            room_topic_handlers: Handlers::new(&name, "room-topic", &lanes, Some(&errors)),
+            scan_handlers: Handlers::new(&name, "scan", &lanes, Some(&errors)),
+            start_handlers: Handlers::new(&name, "start", &lanes, Some(&errors)),
+            stop_handlers: Handlers::new(&name, "stop", &lanes, Some(&errors)),
             name,
             ctx,
-            dong_handlers: Rc::new(RefCell::new(vec![])),
-            error_handlers: Rc::new(RefCell::new(vec![])),
-            friendship_handlers: Rc::new(RefCell::new(vec![])),
-            heartbeat_handlers: Rc::new(RefCell::new(vec![])),
-            login_handlers: Rc::new(RefCell::new(vec![])),
-            logout_handlers: Rc::new(RefCell::new(vec![])),
-            message_handlers: Rc::new(RefCell::new(vec![])),
-            ready_handlers: Rc::new(RefCell::new(vec![])),
-            reset_handlers: Rc::new(RefCell::new(vec![])),
-            room_invite_handlers: Rc::new(RefCell::new(vec![])),
-            room_join_handlers: Rc::new(RefCell::new(vec![])),
-            room_leave_handlers: Rc::new(RefCell::new(vec![])),
-            room_topic_handlers: Rc::new(RefCell::new(vec![])),
-            scan_handlers: Rc::new(RefCell::new(vec![])),
+            lanes,
+        }
+    }
+
+    fn remove_handler(&self, event_name: &'static str, id: usize) -> bool {
+        match event_name {
+            "dirty" => self.dirty_handlers.remove(&self.ctx, id),
+            "dong" => self.dong_handlers.remove(&self.ctx, id),
+            "error" => self.error_handlers.remove(&self.ctx, id),
+            "friendship" => self.friendship_handlers.remove(&self.ctx, id),
+            "heartbeat" => self.heartbeat_handlers.remove(&self.ctx, id),
+            "login" => self.login_handlers.remove(&self.ctx, id),
+            "logout" => self.logout_handlers.remove(&self.ctx, id),
+            "message" => self.message_handlers.remove(&self.ctx, id),
+            "raw" => self.raw_handlers.remove(&self.ctx, id),
+            "ready" => self.ready_handlers.remove(&self.ctx, id),
+            "reset" => self.reset_handlers.remove(&self.ctx, id),
+            "room-invite" => self.room_invite_handlers.remove(&self.ctx, id),
+            "room-join" => self.room_join_handlers.remove(&self.ctx, id),
+            "room-leave" => self.room_leave_handlers.remove(&self.ctx, id),
+            "room-topic" => self.room_topic_handlers.remove(&self.ctx, id),
+            "scan" => self.scan_handlers.remove(&self.ctx, id),
+            "start" => self.start_handlers.remove(&self.ctx, id),
+            "stop" => self.stop_handlers.remove(&self.ctx, id),
+            _ => false,
         }
     }
 
-    async fn trigger_handlers<Payload: Clone + 'static>(
+    pub(crate) fn ctx(&self) -> WechatyContext<T> {
+        self.ctx.clone()
+    }
+
+    /// Run the handlers of an event, or queue them on the lane of the event in concurrent mode, or while the lane
+    /// is held by a handler waiting for a message.
+    ///
+    /// A handler that fails does not keep the others from running, its failure is reported along with
+    /// `event` to the error handlers of the listener.
+    async fn trigger_handlers<Payload: Clone + Lane + Send + Sync + 'static>(
         ctx: WechatyContext<T>,
         payload: Payload,
         handlers: HandlersPtr<T, Payload>,
+        event: Option<PuppetEvent>,
     ) where
         T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
     {
-        let len = handlers.borrow_mut().len();
-        for i in 0..len {
-            let mut handler = &mut handlers.borrow_mut()[i];
-            if handler.1 > 0 {
-                handler.0.run(payload.clone(), ctx.clone()).await;
-                handler.1 -= 1;
+        let due = handlers.take_due(&ctx);
+        if due.is_empty() {
+            return;
+        }
+        let concurrent = ctx.dispatch().is_concurrent();
+        let lanes = handlers.lanes.clone();
+        let lane = payload.lane();
+        let run = move |ctx: WechatyContext<T>| async move {
+            for handler in due {
+                let dispatch = ctx.dispatch().clone();
+                let (payload, handler_ctx) = (payload.clone(), ctx.clone());
+                if let Err(error) = dispatch.run(async move { handler(payload, handler_ctx).await }).await {
+                    let failure = HandlerFailure {
+                        listener: handlers.listener_name.clone(),
+                        event_name: handlers.event_name,
+                        event: event.clone(),
+                        error,
+                    };
+                    EventListenerInner::<T>::report_failure(ctx.clone(), handlers.errors.clone(), failure).await;
+                }
+            }
+        };
+        match lane {
+            Some(lane) if concurrent || lanes.is_busy(&lane) => lanes.push(lane, run(ctx).boxed()),
+            // Handlers waiting for a message let the listener go on, and hold their lane until done.
+            Some(lane) => {
+                lanes
+                    .run_until_released(lane, |release| run(ctx.with_release(release)).boxed())
+                    .await
             }
+            None => run(ctx).await,
         }
     }
 
+    fn report_failure(
+        ctx: WechatyContext<T>,
+        errors: Option<HandlersPtr<T, ErrorPayload>>,
+        failure: HandlerFailure,
+    ) -> BoxFuture<'static, ()> {
+        error!("{}", failure);
+        let payload = ErrorPayload {
+            data: failure.error.to_string(),
+            failure: Some(failure),
+        };
+        match errors {
+            Some(errors) => EventListenerInner::<T>::trigger_handlers(ctx, payload, errors, None).boxed(),
+            None => async {}.boxed(),
+        }
+    }
+
+    fn trigger_dirty_handlers(&mut self, payload: EventDirtyPayload) -> impl Future<Output = ()> + 'static {
+        let ctx = self.ctx.clone();
+        let handlers = self.dirty_handlers.clone();
+        let event = Some(PuppetEvent::Dirty(payload.clone()));
+        async move { EventListenerInner::<T>::trigger_handlers(ctx, payload, handlers, event).await }
+    }
+
     fn trigger_dong_handlers(&mut self, payload: EventDongPayload) -> impl Future<Output = ()> + 'static {
         let ctx = self.ctx.clone();
         let handlers = self.dong_handlers.clone();
-        async move { EventListenerInner::<T>::trigger_handlers(ctx, payload, handlers).await }
+        let event = Some(PuppetEvent::Dong(payload.clone()));
+        async move { EventListenerInner::<T>::trigger_handlers(ctx, payload, handlers, event).await }
     }
 
     fn trigger_error_handlers(&mut self, payload: EventErrorPayload) -> impl Future<Output = ()> + 'static {
         let ctx = self.ctx.clone();
         let handlers = self.error_handlers.clone();
-        async move { EventListenerInner::<T>::trigger_handlers(ctx, payload, handlers).await }
+        let event = Some(PuppetEvent::Error(payload.clone()));
+        let payload = ErrorPayload {
+            data: payload.data,
+            failure: None,
+        };
+        async move { EventListenerInner::<T>::trigger_handlers(ctx, payload, handlers, event).await }
     }
 
     fn trigger_friendship_handlers(&mut self, payload: EventFriendshipPayload) -> impl Future<Output = ()> + 'static {
+        let event = Some(PuppetEvent::Friendship(payload.clone()));
         let ctx = self.ctx.clone();
         let mut friendship = Friendship::new(payload.friendship_id, ctx.clone(), None);
         let handlers = self.friendship_handlers.clone();
         async move {
             friendship.ready().await.unwrap_or_default();
-            EventListenerInner::<T>::trigger_handlers(ctx, FriendshipPayload { friendship }, handlers).await
+            EventListenerInner::<T>::trigger_handlers(ctx, FriendshipPayload { friendship }, handlers, event).await
         }
     }
 
     fn trigger_heartbeat_handlers(&mut self, payload: EventHeartbeatPayload) -> impl Future<Output = ()> + 'static {
         let ctx = self.ctx.clone();
         let handlers = self.heartbeat_handlers.clone();
-        async move { EventListenerInner::<T>::trigger_handlers(ctx, payload, handlers).await }
+        let event = Some(PuppetEvent::Heartbeat(payload.clone()));
+        async move { EventListenerInner::<T>::trigger_handlers(ctx, payload, handlers, event).await }
     }
 
     fn trigger_login_handlers(&mut self, payload: EventLoginPayload) -> impl Future<Output = ()> + 'static {
+        let event = Some(PuppetEvent::Login(payload.clone()));
         let mut contact = ContactSelf::new(payload.contact_id, self.ctx.clone(), None);
         let ctx = self.ctx.clone();
         let handlers = self.login_handlers.clone();
         async move {
             contact.sync().await.unwrap_or_default();
-            EventListenerInner::<T>::trigger_handlers(ctx, LoginPayload { contact }, handlers).await
+            EventListenerInner::<T>::trigger_handlers(ctx, LoginPayload { contact }, handlers, event).await
         }
     }
 
     fn trigger_logout_handlers(&mut self, payload: EventLogoutPayload) -> impl Future<Output = ()> + 'static {
+        let event = Some(PuppetEvent::Logout(payload.clone()));
         let mut contact = ContactSelf::new(payload.contact_id.clone(), self.ctx.clone(), None);
         let ctx = self.ctx.clone();
         let handlers = self.logout_handlers.clone();
@@ -516,44 +1051,61 @@ where
                     data: payload.data,
                 },
                 handlers,
+                event,
             )
             .await
         }
     }
 
     fn trigger_message_handlers(&mut self, payload: EventMessagePayload) -> impl Future<Output = ()> + 'static {
+        let event = Some(PuppetEvent::Message(payload.clone()));
         let ctx = self.ctx.clone();
         let mut message = Message::new(payload.message_id, ctx.clone(), None);
         let handlers = self.message_handlers.clone();
+        let route = ctx.sessions().route(ctx.clone(), message.id());
         async move {
+            if route.await {
+                return;
+            }
             message.ready().await.unwrap_or_default();
-            EventListenerInner::<T>::trigger_handlers(ctx, MessagePayload { message }, handlers).await
+            EventListenerInner::<T>::trigger_handlers(ctx, MessagePayload { message }, handlers, event).await
         }
     }
 
+    fn trigger_raw_handlers(&mut self, payload: EventRawPayload) -> impl Future<Output = ()> + 'static {
+        let ctx = self.ctx.clone();
+        let handlers = self.raw_handlers.clone();
+        let event = Some(PuppetEvent::Raw(payload.clone()));
+        async move { EventListenerInner::<T>::trigger_handlers(ctx, payload, handlers, event).await }
+    }
+
     fn trigger_ready_handlers(&mut self, payload: EventReadyPayload) -> impl Future<Output = ()> + 'static {
         let ctx = self.ctx.clone();
         let handlers = self.ready_handlers.clone();
-        async move { EventListenerInner::<T>::trigger_handlers(ctx, payload, handlers).await }
+        let event = Some(PuppetEvent::Ready(payload.clone()));
+        async move { EventListenerInner::<T>::trigger_handlers(ctx, payload, handlers, event).await }
     }
 
     fn trigger_reset_handlers(&mut self, payload: EventResetPayload) -> impl Future<Output = ()> + 'static {
         let ctx = self.ctx.clone();
         let handlers = self.reset_handlers.clone();
-        async move { EventListenerInner::<T>::trigger_handlers(ctx, payload, handlers).await }
+        let event = Some(PuppetEvent::Reset(payload.clone()));
+        async move { EventListenerInner::<T>::trigger_handlers(ctx, payload, handlers, event).await }
     }
 
     fn trigger_room_invite_handlers(&mut self, payload: EventRoomInvitePayload) -> impl Future<Output = ()> + 'static {
+        let event = Some(PuppetEvent::RoomInvite(payload.clone()));
         let mut room_invitation = RoomInvitation::new(payload.room_invitation_id, self.ctx.clone(), None);
         let ctx = self.ctx.clone();
         let handlers = self.room_invite_handlers.clone();
         async move {
             room_invitation.ready().await.unwrap_or_default();
-            EventListenerInner::<T>::trigger_handlers(ctx, RoomInvitePayload { room_invitation }, handlers).await
+            EventListenerInner::<T>::trigger_handlers(ctx, RoomInvitePayload { room_invitation }, handlers, event).await
         }
     }
 
     fn trigger_room_join_handlers(&mut self, payload: EventRoomJoinPayload) -> impl Future<Output = ()> + 'static {
+        let event = Some(PuppetEvent::RoomJoin(payload.clone()));
         let ctx = self.ctx.clone();
         let handlers = self.room_join_handlers.clone();
         let mut room = Room::new(payload.room_id.clone(), ctx.clone(), None);
@@ -571,12 +1123,14 @@ where
                     timestamp: payload.timestamp,
                 },
                 handlers,
+                event,
             )
             .await
         }
     }
 
     fn trigger_room_leave_handlers(&mut self, payload: EventRoomLeavePayload) -> impl Future<Output = ()> + 'static {
+        let event = Some(PuppetEvent::RoomLeave(payload.clone()));
         let ctx = self.ctx.clone();
         let handlers = self.room_leave_handlers.clone();
         let mut room = Room::new(payload.room_id.clone(), ctx.clone(), None);
@@ -594,23 +1148,27 @@ where
                     remover,
                 },
                 handlers,
+                event,
             )
             .await;
-            let self_id = ctx.id().unwrap();
-            if payload.removee_id_list.contains(&self_id) {
-                ctx.puppet()
-                    .dirty_payload(PayloadType::Room, payload.room_id.clone())
-                    .await
-                    .unwrap_or_default();
-                ctx.puppet()
-                    .dirty_payload(PayloadType::RoomMember, payload.room_id)
-                    .await
-                    .unwrap_or_default();
+            // The bot may have logged out or be stopping by now, then there is no one to check.
+            if let Some(self_id) = ctx.id() {
+                if payload.removee_id_list.contains(&self_id) {
+                    ctx.puppet()
+                        .dirty_payload(PayloadType::Room, payload.room_id.clone())
+                        .await
+                        .unwrap_or_default();
+                    ctx.puppet()
+                        .dirty_payload(PayloadType::RoomMember, payload.room_id)
+                        .await
+                        .unwrap_or_default();
+                }
             }
         }
     }
 
     fn trigger_room_topic_handlers(&mut self, payload: EventRoomTopicPayload) -> impl Future<Output = ()> + 'static {
+        let event = Some(PuppetEvent::RoomTopic(payload.clone()));
         let ctx = self.ctx.clone();
         let handlers = self.room_topic_handlers.clone();
         let mut room = Room::new(payload.room_id.clone(), ctx.clone(), None);
@@ -628,6 +1186,7 @@ where
                     timestamp: payload.timestamp,
                 },
                 handlers,
+                event,
             )
             .await
         }
@@ -636,6 +1195,312 @@ where
     fn trigger_scan_handlers(&mut self, payload: EventScanPayload) -> impl Future<Output = ()> + 'static {
         let ctx = self.ctx.clone();
         let handlers = self.scan_handlers.clone();
-        async move { EventListenerInner::<T>::trigger_handlers(ctx, payload, handlers).await }
+        let event = Some(PuppetEvent::Scan(payload.clone()));
+        async move { EventListenerInner::<T>::trigger_handlers(ctx, payload, handlers, event).await }
+    }
+}
+
+/// The lane the handlers of an event are queued on in concurrent mode, `None` to run them right away.
+trait Lane {
+    fn lane(&self) -> Option<String>;
+}
+
+impl Lane for DirtyPayload {
+    fn lane(&self) -> Option<String> {
+        Some("dirty".to_owned())
+    }
+}
+
+impl Lane for DongPayload {
+    fn lane(&self) -> Option<String> {
+        Some("dong".to_owned())
+    }
+}
+
+impl Lane for ErrorPayload {
+    fn lane(&self) -> Option<String> {
+        Some("error".to_owned())
+    }
+}
+
+impl Lane for HeartbeatPayload {
+    fn lane(&self) -> Option<String> {
+        Some("heartbeat".to_owned())
+    }
+}
+
+impl Lane for RawPayload {
+    fn lane(&self) -> Option<String> {
+        Some("raw".to_owned())
+    }
+}
+
+impl Lane for ReadyPayload {
+    fn lane(&self) -> Option<String> {
+        Some("ready".to_owned())
+    }
+}
+
+impl Lane for ResetPayload {
+    fn lane(&self) -> Option<String> {
+        Some("reset".to_owned())
+    }
+}
+
+impl Lane for ScanPayload {
+    fn lane(&self) -> Option<String> {
+        Some("scan".to_owned())
+    }
+}
+
+impl<T> Lane for FriendshipPayload<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    fn lane(&self) -> Option<String> {
+        Some("friendship".to_owned())
+    }
+}
+
+impl<T> Lane for LoginPayload<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    fn lane(&self) -> Option<String> {
+        Some("login".to_owned())
+    }
+}
+
+impl<T> Lane for LogoutPayload<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    fn lane(&self) -> Option<String> {
+        Some("logout".to_owned())
+    }
+}
+
+/// Messages are ordered per conversation.
+impl<T> Lane for MessagePayload<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    fn lane(&self) -> Option<String> {
+        match (self.message.room(), self.message.from()) {
+            (Some(room), _) => Some(room.id()),
+            (None, Some(from)) => Some(from.id()),
+            (None, None) => Some("message".to_owned()),
+        }
+    }
+}
+
+impl<T> Lane for RoomInvitePayload<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    fn lane(&self) -> Option<String> {
+        Some("room-invite".to_owned())
+    }
+}
+
+impl<T> Lane for RoomJoinPayload<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    fn lane(&self) -> Option<String> {
+        Some(self.room.id())
+    }
+}
+
+impl<T> Lane for RoomLeavePayload<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    fn lane(&self) -> Option<String> {
+        Some(self.room.id())
+    }
+}
+
+impl<T> Lane for RoomTopicPayload<T>
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    fn lane(&self) -> Option<String> {
+        Some(self.room.id())
+    }
+}
+
+/// Lifecycle handlers are awaited by `Wechaty::start` and `Wechaty::stop`.
+impl Lane for StartPayload {
+    fn lane(&self) -> Option<String> {
+        None
+    }
+}
+
+impl Lane for StopPayload {
+    fn lane(&self) -> Option<String> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use wechaty_puppet::{EventDirtyPayload, EventRawPayload, PayloadType, PuppetEvent};
+
+    use crate::handler::handler_fn;
+    use crate::testing::{PuppetMock, TestBot};
+    use crate::{DirtyPayload, EventListener, MessagePayload, RawPayload, RoomLeavePayload, WechatyContext};
+
+    fn logger(
+        log: &Arc<Mutex<Vec<String>>>,
+        name: &'static str,
+    ) -> impl Fn(MessagePayload<PuppetMock>, WechatyContext<PuppetMock>) -> futures::future::Ready<()> + Send {
+        let log = log.clone();
+        move |payload, _ctx| {
+            let text = payload.message.text().unwrap_or_default();
+            log.lock().unwrap().push(format!("{} {}", name, text));
+            futures::future::ready(())
+        }
+    }
+
+    #[actix_rt::test]
+    async fn handlers_can_be_removed_and_expire() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let bot = TestBot::new();
+        let every = bot.on_message_with_handle(logger(&log, "every"), None);
+        let once = bot.once_message(logger(&log, "once"));
+        assert_ne!(every, once);
+        assert_eq!(every.event_name(), "message");
+        bot.login("bot").await;
+        bot.mocker().create_contact("alice", "Alice");
+
+        bot.receive_text("alice", "first").await;
+        bot.receive_text("alice", "second").await;
+        assert!(!bot.remove_handler(once));
+        assert!(bot.remove_handler(every));
+        assert!(!bot.remove_handler(every));
+        bot.receive_text("alice", "third").await;
+
+        bot.on_message(logger(&log, "again"));
+        bot.receive_text("alice", "fourth").await;
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["every first", "once first", "every second", "again fourth"]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn handles_only_remove_handlers_of_their_own_listener() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let bot = TestBot::new();
+        let other = TestBot::new();
+        let handle = bot.on_message_with_handle(logger(&log, "bot"), None);
+        let other_handle = other.on_message_with_handle(logger(&log, "other"), None);
+        assert_ne!(handle, other_handle);
+        assert!(!other.remove_handler(handle));
+        bot.login("bot").await;
+        bot.mocker().create_contact("alice", "Alice");
+        bot.receive_text("alice", "first").await;
+        assert_eq!(*log.lock().unwrap(), vec!["bot first"]);
+    }
+
+    #[actix_rt::test]
+    async fn handlers_can_be_registered_from_other_threads() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let bot = Arc::new(TestBot::new());
+        bot.login("bot").await;
+        bot.mocker().create_contact("alice", "Alice");
+
+        let (admin, handler) = (bot.clone(), logger(&log, "admin"));
+        let handle = std::thread::spawn(move || admin.on_message_with_handle(handler, None))
+            .join()
+            .unwrap();
+        bot.receive_text("alice", "first").await;
+
+        let admin = bot.clone();
+        let removed = std::thread::spawn(move || admin.remove_handler(handle)).join().unwrap();
+        assert!(removed);
+        bot.receive_text("alice", "second").await;
+        assert_eq!(*log.lock().unwrap(), vec!["admin first"]);
+    }
+
+    #[actix_rt::test]
+    async fn handlers_added_while_the_last_one_is_removed_stay_subscribed() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let bot = TestBot::new();
+        bot.login("bot").await;
+        bot.mocker().create_contact("alice", "Alice");
+        let removed = bot.on_message_with_handle(logger(&log, "removed"), None);
+        let handlers = bot.get_listener().message_handlers.clone();
+
+        // Remove the last handler the way `remove_handler` does, while another thread adds one.
+        let adder = {
+            let mut entries = handlers.entries.lock().unwrap();
+            let adder = {
+                let (handlers, puppet, addr) = (handlers.clone(), bot.get_puppet(), bot.get_addr());
+                let handler = handler_fn(logger(&log, "added"));
+                std::thread::spawn(move || handlers.add(&puppet, addr, handler, None))
+            };
+            std::thread::sleep(Duration::from_millis(50));
+            entries.retain(|entry| entry.id != removed.id);
+            handlers.unsubscribe(&bot.get_listener().ctx());
+            adder
+        };
+        adder.join().unwrap();
+
+        bot.receive_text("alice", "hello").await;
+        assert_eq!(*log.lock().unwrap(), vec!["added hello"]);
+    }
+
+    #[actix_rt::test]
+    async fn rooms_can_be_left_while_logged_out() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let bot = TestBot::new();
+        bot.mocker().create_contact("alice", "Alice");
+        bot.mocker().create_contact("bob", "Bob");
+        bot.mocker().create_room("room", "Friends", &["alice", "bob"]);
+        let leave_log = log.clone();
+        bot.on_room_leave(move |payload: RoomLeavePayload<PuppetMock>, _ctx| {
+            leave_log.lock().unwrap().push(payload.removee_list.len());
+            futures::future::ready(())
+        });
+        bot.mocker().leave_room("room", &["bob"], "alice");
+        bot.settle().await;
+        bot.mocker().leave_room("room", &["alice"], "alice");
+        bot.settle().await;
+        assert_eq!(*log.lock().unwrap(), vec![1, 1]);
+    }
+
+    #[actix_rt::test]
+    async fn dirty_and_raw_events_reach_their_handlers() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let bot = TestBot::new();
+        let dirty_log = log.clone();
+        bot.on_dirty(move |payload: DirtyPayload, _ctx| {
+            dirty_log.lock().unwrap().push(format!("dirty {}", payload.payload_id));
+            futures::future::ready(())
+        });
+        let raw_log = log.clone();
+        bot.on_raw(move |payload: RawPayload, _ctx| {
+            raw_log
+                .lock()
+                .unwrap()
+                .push(format!("raw {} {}", payload.event_type, payload.payload));
+            futures::future::ready(())
+        });
+        bot.emit(PuppetEvent::Dirty(EventDirtyPayload {
+            payload_type: PayloadType::Contact,
+            payload_id: "alice".to_owned(),
+        }))
+        .await;
+        bot.emit(PuppetEvent::Raw(EventRawPayload {
+            event_type: 42,
+            payload: "{}".to_owned(),
+        }))
+        .await;
+        assert_eq!(*log.lock().unwrap(), vec!["dirty alice", "raw 42 {}"]);
     }
 }
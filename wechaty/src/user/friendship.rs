@@ -59,6 +59,12 @@ where
         }
     }
 
+    /// Get the greeting of the friendship request.
+    pub fn hello(&self) -> Option<String> {
+        debug!("Friendship.hello(id = {})", self.id_);
+        self.payload_.as_ref().map(|payload| payload.hello.clone())
+    }
+
     /// Get friendship's contact.
     pub fn contact(&self) -> Option<Contact<T>> {
         debug!("Friendship.contact(id = {})", self.id_);
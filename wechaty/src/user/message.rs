@@ -2,7 +2,9 @@ use std::fmt;
 use std::time::SystemTime;
 
 use log::{debug, error, info};
-use wechaty_puppet::{FileBox, MessagePayload, MessageType, MiniProgramPayload, PuppetImpl, UrlLinkPayload};
+use wechaty_puppet::{
+    FileBox, MessagePayload, MessageType, MiniProgramPayload, PuppetError, PuppetImpl, UrlLinkPayload,
+};
 
 use crate::{Contact, Entity, IntoContact, Room, WechatyContext, WechatyError};
 
@@ -28,14 +30,10 @@ where
         }
     }
 
-    /// Check if the message is sent by the user self.
+    /// Check if the message is sent by the user self, messages without a sender are not.
     pub fn is_self(&self) -> bool {
         debug!("Message.is_self(id = {})", self.id_);
-        if !self.is_ready() {
-            false
-        } else {
-            self.from().unwrap().is_self()
-        }
+        self.from().map_or(false, |from| from.is_self())
     }
 
     /// Check if the message is sent in a room.
@@ -236,38 +234,75 @@ where
 
     pub async fn reply_text(&mut self, text: String) -> Result<Option<Message<T>>, WechatyError> {
         debug!("Message.reply_text(id = {}, text = {})", self.id_, text);
+        self.reply_text_with(text, ReplyOptions::default()).await
+    }
+
+    /// Reply with a text, mentioning the sender or quoting the message as the options say.
+    pub async fn reply_text_with(
+        &mut self,
+        text: String,
+        options: ReplyOptions,
+    ) -> Result<Option<Message<T>>, WechatyError> {
+        debug!(
+            "Message.reply_text_with(id = {}, text = {}, options = {:?})",
+            self.id_, text, options
+        );
         if !self.is_ready() {
             return Err(WechatyError::NoPayload);
         }
-        if self.is_in_room() {
-            unimplemented!()
-        } else {
-            self.from().unwrap().send_text(text).await
+        let room = self.room();
+        let mut from = self.from();
+        let mention = options.mention && room.is_some();
+        // Messages without a sender, such as system messages, are replied to without mention or quote.
+        let name = match from.as_mut() {
+            Some(from) if options.quote || mention => Some(display_name(room.as_ref(), from).await),
+            _ => None,
+        };
+        let text = match &name {
+            Some(name) if options.quote => format!(
+                "「{}: {}」\n- - - - - - - - - - - - - - -\n{}",
+                name,
+                self.text().unwrap_or_default(),
+                text
+            ),
+            _ => text,
+        };
+        match (room, from) {
+            (Some(room), from) => {
+                let (text, mention_id_list) = match (from, name) {
+                    (Some(from), Some(name)) if mention => {
+                        (format!("@{}{}{}", name, MENTION_SEPARATOR, text), vec![from.id()])
+                    }
+                    _ => (text, vec![]),
+                };
+                let result = self
+                    .ctx_
+                    .puppet()
+                    .message_send_text(room.id(), text, mention_id_list)
+                    .await;
+                self.load_reply(result).await
+            }
+            (None, Some(mut from)) => from.send_text(text).await,
+            (None, None) => Err(WechatyError::NoPayload),
         }
     }
 
     pub async fn reply_contact(&mut self, contact_id: String) -> Result<Option<Message<T>>, WechatyError> {
         debug!("Message.reply_contact(id = {}, contact_id = {})", self.id_, contact_id);
-        if !self.is_ready() {
-            return Err(WechatyError::NoPayload);
-        }
-        if self.is_in_room() {
-            unimplemented!()
-        } else {
-            self.from().unwrap().send_contact(contact_id).await
-        }
+        let conversation_id = self.reply_conversation_id()?;
+        let result = self
+            .ctx_
+            .puppet()
+            .message_send_contact(conversation_id, contact_id)
+            .await;
+        self.load_reply(result).await
     }
 
     pub async fn reply_file(&mut self, file: FileBox) -> Result<Option<Message<T>>, WechatyError> {
         debug!("Message.reply_file(id = {})", self.id_);
-        if !self.is_ready() {
-            return Err(WechatyError::NoPayload);
-        }
-        if self.is_in_room() {
-            unimplemented!()
-        } else {
-            self.from().unwrap().send_file(file).await
-        }
+        let conversation_id = self.reply_conversation_id()?;
+        let result = self.ctx_.puppet().message_send_file(conversation_id, file).await;
+        self.load_reply(result).await
     }
 
     pub async fn reply_mini_program(
@@ -278,27 +313,78 @@ where
             "message.reply_mini_program(id = {}, mini_program = {:?})",
             self.id_, mini_program
         );
-        if !self.is_ready() {
-            return Err(WechatyError::NoPayload);
-        }
-        if self.is_in_room() {
-            unimplemented!()
-        } else {
-            self.from().unwrap().send_mini_program(mini_program).await
-        }
+        let conversation_id = self.reply_conversation_id()?;
+        let result = self
+            .ctx_
+            .puppet()
+            .message_send_mini_program(conversation_id, mini_program)
+            .await;
+        self.load_reply(result).await
     }
 
     pub async fn reply_url(&mut self, url: UrlLinkPayload) -> Result<Option<Message<T>>, WechatyError> {
         debug!("Message.reply_url(id = {}, url = {:?})", self.id_, url);
+        let conversation_id = self.reply_conversation_id()?;
+        let result = self.ctx_.puppet().message_send_url(conversation_id, url).await;
+        self.load_reply(result).await
+    }
+
+    /// Where replies go: the room, or the sender outside rooms.
+    fn reply_conversation_id(&self) -> Result<String, WechatyError> {
         if !self.is_ready() {
             return Err(WechatyError::NoPayload);
         }
-        if self.is_in_room() {
-            unimplemented!()
-        } else {
-            self.from().unwrap().send_url(url).await
+        self.conversation_id().ok_or(WechatyError::NoPayload)
+    }
+
+    async fn load_reply(
+        &self,
+        result: Result<Option<String>, PuppetError>,
+    ) -> Result<Option<Message<T>>, WechatyError> {
+        match result {
+            Ok(Some(message_id)) => match self.ctx_.message_load(message_id.clone()).await {
+                Ok(message) => Ok(Some(message)),
+                Err(e) => {
+                    error!("Failed to load reply {}, reason: {}", message_id, e);
+                    Ok(None)
+                }
+            },
+            Ok(None) => {
+                error!("Reply to message {} has been sent but cannot get message id", self.id_);
+                Ok(None)
+            }
+            Err(e) => {
+                error!("Failed to reply to message {}, reason: {}", self.id_, e);
+                Err(WechatyError::from(e))
+            }
+        }
+    }
+}
+
+/// Separates a mention from the text that follows it, as WeChat does.
+pub(crate) const MENTION_SEPARATOR: char = '\u{2005}';
+
+/// How a reply refers to the message it answers.
+#[derive(Clone, Debug, Default)]
+pub struct ReplyOptions {
+    /// @-mention the sender, in rooms.
+    pub mention: bool,
+    /// Quote the text of the message above the reply.
+    pub quote: bool,
+}
+
+/// The name a contact goes by: their alias in the room, or their name.
+async fn display_name<T>(room: Option<&Room<T>>, contact: &mut Contact<T>) -> String
+where
+    T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
+{
+    if let Some(room) = room {
+        if let Ok(Some(alias)) = room.alias(contact).await {
+            return alias;
         }
     }
+    contact.ready(false).await.unwrap_or_default();
+    contact.name().unwrap_or_else(|| contact.id())
 }
 
 impl<T> fmt::Debug for Message<T>
@@ -341,3 +427,103 @@ where
         write!(fmt, "{}", [from, to, room, message_type, text].join(""))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use wechaty_puppet_mock::{MessageContent, SentMessage};
+
+    use crate::testing::{PuppetMock, TestBot};
+    use crate::{EventListener, MessagePayload, ReplyOptions};
+
+    #[actix_rt::test]
+    async fn replies_in_rooms_go_to_the_room() {
+        let bot = TestBot::new();
+        bot.on_message(|payload: MessagePayload<PuppetMock>, _ctx| async move {
+            let mut message = payload.message;
+            let options = ReplyOptions {
+                mention: true,
+                quote: message.text().unwrap_or_default() == "quote me",
+            };
+            message.reply_text_with("ok".to_owned(), options).await.unwrap();
+            message.reply_contact("bob".to_owned()).await.unwrap();
+        });
+        bot.login("bot").await;
+        bot.mocker().create_contact("alice", "Alice");
+        bot.mocker().create_contact("bob", "Bob");
+        bot.mocker().create_room("room", "Friends", &["bot", "alice", "bob"]);
+        bot.mocker().set_room_alias("room", "bob", "Bobby");
+        let texts = |bot: &TestBot| -> Vec<(String, String, Vec<String>)> {
+            bot.take_sent_messages()
+                .into_iter()
+                .map(
+                    |SentMessage {
+                         conversation_id,
+                         content,
+                         ..
+                     }| match content {
+                        MessageContent::Text { text, mention_id_list } => (conversation_id, text, mention_id_list),
+                        content => (conversation_id, format!("{:?}", content), vec![]),
+                    },
+                )
+                .collect()
+        };
+
+        bot.receive_room_text("room", "alice", "hi", &[]).await;
+        let sent = texts(&bot);
+        assert_eq!(
+            sent[0],
+            (
+                "room".to_owned(),
+                "@Alice\u{2005}ok".to_owned(),
+                vec!["alice".to_owned()]
+            )
+        );
+        assert_eq!(sent[1], ("room".to_owned(), "Contact(\"bob\")".to_owned(), vec![]));
+
+        bot.receive_room_text("room", "bob", "quote me", &[]).await;
+        assert_eq!(
+            texts(&bot)[0],
+            (
+                "room".to_owned(),
+                "@Bobby\u{2005}「Bobby: quote me」\n- - - - - - - - - - - - - - -\nok".to_owned(),
+                vec!["bob".to_owned()]
+            )
+        );
+
+        bot.receive_text("alice", "hi").await;
+        assert_eq!(texts(&bot)[0], ("alice".to_owned(), "ok".to_owned(), vec![]));
+
+        // System messages have no sender to mention.
+        bot.receive_room_text("room", "", "quote me", &[]).await;
+        assert_eq!(texts(&bot)[0], ("room".to_owned(), "ok".to_owned(), vec![]));
+    }
+
+    #[actix_rt::test]
+    async fn replies_without_a_conversation_are_errors() {
+        let bot = TestBot::new();
+        let results = Arc::new(Mutex::new(vec![]));
+        let results_in_handler = results.clone();
+        bot.on_message(move |payload: MessagePayload<PuppetMock>, _ctx| {
+            let results = results_in_handler.clone();
+            async move {
+                let mut message = payload.message;
+                let result = message.reply_contact("bob".to_owned()).await;
+                results.lock().unwrap().push(result.is_ok());
+            }
+        });
+        bot.login("bot").await;
+        bot.mocker().create_contact("alice", "Alice");
+        bot.mocker().create_contact("bob", "Bob");
+
+        bot.receive_text("alice", "hi").await;
+        let sent = bot.take_sent_messages();
+        assert_eq!(sent[0].conversation_id, "alice");
+        assert_eq!(sent[0].content, MessageContent::Contact("bob".to_owned()));
+
+        bot.receive_text("", "hi").await;
+        bot.expect_silence();
+        assert_eq!(*results.lock().unwrap(), vec![true, false]);
+    }
+}
@@ -85,6 +85,16 @@ where
         }
     }
 
+    /// Get the alias of a member in the room, `None` if they have not set one.
+    pub async fn alias(&self, contact: &Contact<T>) -> Result<Option<String>, WechatyError> {
+        debug!("Room.alias(id = {}, contact_id = {})", self.id_, contact.id());
+        match self.ctx().puppet().room_member_payload(self.id(), contact.id()).await {
+            Ok(payload) if !payload.room_alias.is_empty() => Ok(Some(payload.room_alias)),
+            Ok(_) => Ok(None),
+            Err(e) => Err(WechatyError::from(e)),
+        }
+    }
+
     pub async fn member_find_all(&self) -> Result<Vec<Contact<T>>, WechatyError> {
         debug!("Room.member_find_all(id = {})", self.id_);
         let ctx = self.ctx();
@@ -1,8 +1,15 @@
 use actix::{Actor, Addr, Recipient};
+use futures::{Stream, StreamExt};
+use log::{debug, error, info};
 use tokio::signal;
-use wechaty_puppet::{Puppet, PuppetEvent, PuppetImpl};
+use wechaty_puppet::{Puppet, PuppetEvent, PuppetImpl, Subscribe};
 
-use crate::{EventListener, EventListenerInner, WechatyContext};
+use crate::session::SessionRouter;
+use crate::traits::event_listener::{Flush, LifecycleEvent, Shutdown};
+use crate::{
+    DispatchOptions, EventListener, EventListenerInner, Message, PluginListener, WechatyContext, WechatyError,
+    WechatyPlugin,
+};
 
 type WechatyListener<T> = EventListenerInner<T>;
 
@@ -13,6 +20,8 @@ where
     puppet: Puppet<T>,
     listener: WechatyListener<T>,
     addr: Addr<WechatyListener<T>>,
+    sessions: Addr<SessionRouter<T>>,
+    plugins: Vec<(Box<dyn WechatyPlugin<T>>, PluginListener<T>)>,
 }
 
 impl<T> Wechaty<T>
@@ -20,15 +29,175 @@ where
     T: 'static + PuppetImpl + Clone + Unpin + Send + Sync,
 {
     pub fn new(puppet: Puppet<T>) -> Self {
-        let listener = EventListenerInner::new("Wechaty".to_owned(), WechatyContext::new(puppet.clone()));
+        Self::with_dispatch(puppet, DispatchOptions::default())
+    }
+
+    /// Create a bot running its handlers as configured by `dispatch`.
+    pub fn with_dispatch(puppet: Puppet<T>, dispatch: DispatchOptions) -> Self {
+        debug!("Wechaty.with_dispatch(dispatch = {:?})", dispatch);
+        let listener = EventListenerInner::new("Wechaty".to_owned(), WechatyContext::new(puppet.clone(), dispatch));
         let addr = listener.clone().start();
-        Self { puppet, addr, listener }
+        let sessions = SessionRouter::new(listener.ctx()).start();
+        if let Err(e) = puppet.get_subscribe_addr().do_send(Subscribe {
+            addr: sessions.clone().recipient(),
+            name: "Sessions".to_owned(),
+            event_name: "message",
+        }) {
+            error!("Sessions failed to subscribe to event message: {}", e);
+        }
+        Self {
+            puppet,
+            addr,
+            sessions,
+            listener,
+            plugins: vec![],
+        }
+    }
+
+    /// Install a plugin, which registers its handlers on a listener of its own.
+    pub fn use_plugin<P>(&mut self, plugin: P) -> Result<&mut Self, WechatyError>
+    where
+        P: 'static + WechatyPlugin<T>,
+    {
+        let name = plugin.name();
+        debug!("Wechaty.use_plugin(name = {})", name);
+        if self.plugins.iter().any(|(installed, _)| installed.name() == name) {
+            return Err(WechatyError::InvalidOperation(format!(
+                "Plugin {} is already installed",
+                name
+            )));
+        }
+        let listener = PluginListener::new(&name, self.puppet.clone(), self.listener.ctx());
+        plugin.install(&listener);
+        self.plugins.push((Box::new(plugin), listener));
+        Ok(self)
+    }
+
+    /// Remove the handlers of a plugin, then let it clean up.
+    ///
+    /// Events the plugin has received are handled first.
+    pub async fn uninstall_plugin(&mut self, name: &str) -> Result<(), WechatyError> {
+        debug!("Wechaty.uninstall_plugin(name = {})", name);
+        match self.plugins.iter().position(|(plugin, _)| plugin.name() == name) {
+            Some(index) => {
+                let (plugin, listener) = self.plugins.remove(index);
+                listener.shutdown().await;
+                plugin.uninstall(listener.ctx());
+                Ok(())
+            }
+            None => Err(WechatyError::InvalidOperation(format!(
+                "Plugin {} is not installed",
+                name
+            ))),
+        }
+    }
+
+    /// Get the names of the installed plugins, in installation order.
+    pub fn plugin_names(&self) -> Vec<String> {
+        self.plugins.iter().map(|(plugin, _)| plugin.name()).collect()
+    }
+
+    /// Start the puppet, then run the `start` handlers.
+    ///
+    /// Return once the puppet is ready to log in, or already logged in.
+    pub async fn start(&self) -> Result<(), WechatyError> {
+        debug!("Wechaty.start()");
+        if !self.addr.connected() {
+            return Err(WechatyError::InvalidOperation("Wechaty has stopped".to_owned()));
+        }
+        self.puppet.start().await?;
+        for (_, listener) in &self.plugins {
+            listener.lifecycle(LifecycleEvent::Start).await;
+        }
+        self.lifecycle(LifecycleEvent::Start).await
+    }
+
+    /// Stop gracefully.
+    ///
+    /// Handlers of the events received so far are waited for, then the `stop` handlers are run before the
+    /// puppet and the listeners, including those of plugins, are stopped. The bot cannot be started again
+    /// afterwards.
+    pub async fn stop(&self) -> Result<(), WechatyError> {
+        debug!("Wechaty.stop()");
+        self.flush().await;
+        self.lifecycle(LifecycleEvent::Stop).await?;
+        for (_, listener) in &self.plugins {
+            listener.lifecycle(LifecycleEvent::Stop).await;
+        }
+        let result = self.puppet.stop().await;
+        for (_, listener) in &self.plugins {
+            listener.shutdown().await;
+        }
+        if let Err(e) = self.addr.send(Shutdown).await {
+            error!("Internal error: {}", e);
+        }
+        if let Err(e) = self.sessions.send(Shutdown).await {
+            error!("Internal error: {}", e);
+        }
+        result.map_err(WechatyError::from)
+    }
+
+    /// Start, wait for Ctrl-C, then stop gracefully.
+    pub async fn run_until_signal(&self) -> Result<(), WechatyError> {
+        self.start().await?;
+        if let Err(e) = signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl-C, stopping: {}", e);
+            self.stop().await?;
+            return Err(WechatyError::InvalidOperation(format!(
+                "Failed to listen for Ctrl-C, reason: {}",
+                e
+            )));
+        }
+        info!("Received Ctrl-C, stopping");
+        self.stop().await
+    }
+
+    async fn lifecycle(&self, event: LifecycleEvent) -> Result<(), WechatyError> {
+        match self.addr.send(event).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(WechatyError::InvalidOperation(format!("Wechaty has stopped: {}", e))),
+        }
+    }
+
+    /// Get a stream of raw puppet events, buffering at most `buffer_size` events for this consumer.
+    ///
+    /// A consumer that falls behind receives `Err(WechatyError::Puppet(PuppetError::Lagged(skipped)))`.
+    pub fn events(&self, buffer_size: usize) -> impl Stream<Item = Result<PuppetEvent, WechatyError>> {
+        self.puppet
+            .events(buffer_size)
+            .map(|item| item.map_err(WechatyError::from))
+    }
+
+    /// Get a stream of incoming messages, buffering at most `buffer_size` events for this consumer.
+    ///
+    /// The buffer holds every puppet event, not only messages, since they are picked out as the stream is
+    /// read. Size it for all the events that may arrive while the consumer is busy.
+    ///
+    /// Messages are loaded before being yielded, so a message whose payload cannot be fetched
+    /// is reported as an error.
+    pub fn messages(&self, buffer_size: usize) -> impl Stream<Item = Result<Message<T>, WechatyError>> {
+        let ctx = self.listener.ctx();
+        self.puppet.events(buffer_size).filter_map(move |item| {
+            let ctx = ctx.clone();
+            async move {
+                match item {
+                    Ok(PuppetEvent::Message(payload)) => Some(ctx.message_load(payload.message_id).await),
+                    Ok(_) => None,
+                    Err(e) => Some(Err(WechatyError::from(e))),
+                }
+            }
+        })
     }
 
-    pub async fn start(&self) {
-        signal::ctrl_c()
-            .await
-            .expect("Failed to establish the listener for graceful exit");
+    /// Wait until the events delivered to the bot so far have been handled.
+    pub(crate) async fn flush(&self) {
+        self.puppet.flush().await;
+        if let Err(e) = self.addr.send(Flush).await {
+            error!("Internal error: {}", e);
+        }
+        for (_, listener) in &self.plugins {
+            listener.flush().await;
+        }
     }
 }
 
@@ -48,3 +217,91 @@ where
         self.addr.clone().recipient()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use wechaty_puppet::{PuppetError, PuppetEvent, PuppetState};
+
+    use crate::testing::TestBot;
+    use crate::{EventListener, MessagePayload, WechatyError};
+
+    #[actix_rt::test]
+    async fn stopping_drains_handlers_first() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let bot = TestBot::new();
+        let (on_start, on_message, on_stop) = (log.clone(), log.clone(), log.clone());
+        bot.on_start(move |_, _| {
+            let log = on_start.clone();
+            async move { log.lock().unwrap().push("start".to_owned()) }
+        })
+        .on_message(move |payload: MessagePayload<_>, _| {
+            let log = on_message.clone();
+            async move {
+                actix_rt::time::sleep(Duration::from_millis(50)).await;
+                log.lock().unwrap().push(payload.message.text().unwrap_or_default());
+            }
+        })
+        .on_stop(move |_, _| {
+            let log = on_stop.clone();
+            async move { log.lock().unwrap().push("stop".to_owned()) }
+        });
+
+        bot.start().await.unwrap();
+        assert_eq!(bot.puppet().state(), PuppetState::LoggedOut);
+        assert_eq!(*log.lock().unwrap(), vec!["start"]);
+
+        bot.mocker().login("bot");
+        bot.mocker().create_contact("alice", "Alice");
+        bot.mocker().receive_text("alice", "first");
+        bot.mocker().receive_text("alice", "second");
+        bot.stop().await.unwrap();
+        assert_eq!(*log.lock().unwrap(), vec!["start", "first", "second", "stop"]);
+        assert_eq!(bot.puppet().state(), PuppetState::Stopped);
+        assert!(bot.start().await.is_err());
+        assert_eq!(bot.puppet().state(), PuppetState::Stopped);
+    }
+
+    #[actix_rt::test]
+    async fn streams_fan_out_and_report_lagging() {
+        let bot = TestBot::new();
+        bot.login("bot").await;
+        bot.mocker().create_contact("alice", "Alice");
+        let mut events = Box::pin(bot.events(16));
+        let mut messages = Box::pin(bot.messages(16));
+        let mut slow = Box::pin(bot.messages(2));
+
+        for text in &["one", "two", "three", "four"] {
+            bot.receive_text("alice", text).await;
+        }
+        for _ in 0..4 {
+            assert!(matches!(events.next().await, Some(Ok(PuppetEvent::Message(_)))));
+        }
+        let mut texts = vec![];
+        for _ in 0..4 {
+            texts.push(messages.next().await.unwrap().unwrap().text().unwrap());
+        }
+        assert_eq!(texts, vec!["one", "two", "three", "four"]);
+
+        match slow.next().await {
+            Some(Err(WechatyError::Puppet(PuppetError::Lagged(skipped)))) => assert_eq!(skipped, 2),
+            item => panic!("Expected the stream to lag, got {:?}", item.map(|item| item.is_ok())),
+        }
+        assert_eq!(slow.next().await.unwrap().unwrap().text().unwrap(), "three");
+        assert_eq!(slow.next().await.unwrap().unwrap().text().unwrap(), "four");
+    }
+
+    #[actix_rt::test]
+    async fn bots_logged_in_before_start_can_start() {
+        let bot = TestBot::new();
+        bot.login("bot").await;
+        bot.start().await.unwrap();
+        assert_eq!(bot.puppet().state(), PuppetState::LoggedIn("bot".to_owned()));
+        assert!(bot.start().await.is_err());
+        bot.stop().await.unwrap();
+        assert_eq!(bot.puppet().state(), PuppetState::Stopped);
+    }
+}